@@ -0,0 +1,193 @@
+use criterion::{criterion_group, BatchSize, Criterion, Throughput};
+use evdev::Key;
+use indoc::indoc;
+use nix::sys::timerfd::{ClockId, TimerFd, TimerFlags};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use xremap::client::{Client, WMClient};
+use xremap::config::keymap::build_keymap_table;
+use xremap::config::Config;
+use xremap::event::InputDeviceInfo;
+use xremap::event::{Event, KeyEvent, KeyValue, RelativeEvent};
+use xremap::event_handler::EventHandler;
+
+// Count allocations so that regressions in allocation-heavy paths show up next to the timings.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const CONFIG: &str = indoc! {"
+    virtual_modifiers:
+      - CapsLock
+    modmap:
+      - remap:
+          Muhenkan:
+            held: Alt_L
+            alone: Muhenkan
+    keymap:
+      - name: application
+        application:
+          only: Firefox
+        remap:
+          C-t: C-n
+      - name: emacs
+        remap:
+          C-b: left
+          C-f: right
+          M-f: C-right
+          M-b: C-left
+          CapsLock-j: down
+          C-x:
+            remap:
+              h: C-a
+              C-s: C-s
+      - name: scroll
+        remap:
+          XUPSCROLL: up
+"};
+
+const REL_X: u16 = 0;
+const REL_Y: u16 = 1;
+const REL_WHEEL: u16 = 8;
+
+struct StaticClient;
+
+impl Client for StaticClient {
+    fn supported(&mut self) -> bool {
+        true
+    }
+    fn current_window(&mut self) -> Option<String> {
+        None
+    }
+    fn current_application(&mut self) -> Option<String> {
+        Some(String::from("Slack"))
+    }
+}
+
+fn device_info<'a>() -> InputDeviceInfo<'a> {
+    InputDeviceInfo {
+        name: "Bench Device",
+        path: Path::new("/dev/input/event0"),
+    }
+}
+
+fn key(key: Key, value: KeyValue) -> Event<'static> {
+    Event::KeyEvent(device_info(), KeyEvent::new(key, value))
+}
+
+fn relative(code: u16, value: i32) -> Event<'static> {
+    Event::RelativeEvent(device_info(), RelativeEvent::new_with(code, value))
+}
+
+// Plain keys plus combinations that go through the modifier interleave
+fn key_events() -> Vec<Event<'static>> {
+    let mut events = vec![];
+    for _ in 0..25 {
+        events.push(key(Key::KEY_A, KeyValue::Press));
+        events.push(key(Key::KEY_A, KeyValue::Release));
+        events.push(key(Key::KEY_LEFTALT, KeyValue::Press));
+        events.push(key(Key::KEY_F, KeyValue::Press));
+        events.push(key(Key::KEY_F, KeyValue::Release));
+        events.push(key(Key::KEY_LEFTALT, KeyValue::Release));
+        events.push(key(Key::KEY_LEFTSHIFT, KeyValue::Press));
+        events.push(key(Key::KEY_LEFTCTRL, KeyValue::Press));
+        events.push(key(Key::KEY_B, KeyValue::Press));
+        events.push(key(Key::KEY_B, KeyValue::Release));
+        events.push(key(Key::KEY_LEFTCTRL, KeyValue::Release));
+        events.push(key(Key::KEY_LEFTSHIFT, KeyValue::Release));
+    }
+    events
+}
+
+// What a gaming mouse produces: lots of small cursor movements
+fn relative_events() -> Vec<Event<'static>> {
+    let mut events = vec![];
+    for i in 0..150 {
+        events.push(relative(REL_X, if i % 2 == 0 { 3 } else { -2 }));
+        events.push(relative(REL_Y, 1));
+    }
+    events
+}
+
+fn mixed_events() -> Vec<Event<'static>> {
+    let mut events = vec![];
+    for _ in 0..25 {
+        for _ in 0..6 {
+            events.push(relative(REL_X, 3));
+            events.push(relative(REL_Y, -1));
+        }
+        events.push(relative(REL_WHEEL, 1));
+        events.push(key(Key::KEY_LEFTALT, KeyValue::Press));
+        events.push(key(Key::KEY_F, KeyValue::Press));
+        events.push(key(Key::KEY_F, KeyValue::Release));
+        events.push(key(Key::KEY_LEFTALT, KeyValue::Release));
+    }
+    events
+}
+
+fn build_config() -> Config {
+    let mut config: Config = serde_yaml::from_str(CONFIG).unwrap();
+    config.keymap_table = build_keymap_table(&config.keymap, config.default_exact_match, config.merge_strategy);
+    config
+}
+
+fn build_handler() -> EventHandler {
+    let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::empty()).unwrap();
+    EventHandler::new(
+        timer,
+        "default",
+        Duration::from_micros(0),
+        Duration::from_millis(10),
+        WMClient::new("static", Box::new(StaticClient)),
+    )
+}
+
+fn bench_stream(c: &mut Criterion, name: &str, events: Vec<Event<'static>>) {
+    let config = build_config();
+
+    let mut handler = build_handler();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let actions = handler.on_events(&events, &config).unwrap().len();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!(
+        "{name}: {} events -> {actions} actions, {allocations} allocations ({:.2} per event)",
+        events.len(),
+        allocations as f64 / events.len() as f64
+    );
+
+    let mut group = c.benchmark_group("on_events");
+    group.throughput(Throughput::Elements(events.len() as u64));
+    group.bench_function(name, |b| {
+        b.iter_batched_ref(build_handler, |handler| handler.on_events(&events, &config).unwrap(), BatchSize::SmallInput)
+    });
+    group.finish();
+}
+
+fn on_events(c: &mut Criterion) {
+    bench_stream(c, "keys", key_events());
+    bench_stream(c, "relative", relative_events());
+    bench_stream(c, "mixed", mixed_events());
+}
+
+criterion_group!(benches, on_events);
+
+fn main() {
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+}
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use xremap::build_keymap_table;
+use xremap::config::parse_yaml_config;
+
+// Any input should be either loaded or rejected with an error, never panic
+fuzz_target!(|data: &[u8]| {
+    let Ok(contents) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(mut config) = parse_yaml_config(contents) {
+        config.keymap_table = build_keymap_table(&config.keymap, config.default_exact_match, config.merge_strategy);
+    }
+});
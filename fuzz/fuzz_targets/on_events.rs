@@ -0,0 +1,258 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use evdev::Key;
+use libfuzzer_sys::fuzz_target;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use xremap::config::parse_yaml_config;
+use xremap::event::{KeyEvent, KeyValue, RelativeEvent};
+use xremap::{build_keymap_table, Action, Client, Config, Event, EventHandler, InputDeviceInfo, Timer, WMClient};
+
+// Covers modmaps, multi-purpose keys, tap counts, virtual modifiers, nested remaps with timeouts, and modes
+const CONFIG: &str = "
+virtual_modifiers:
+  - CapsLock
+modmap:
+  - remap:
+      Muhenkan:
+        held: Alt_L
+        alone: Muhenkan
+      Space:
+        held: Shift_L
+        alone: Space
+        alone_timeout: 200ms
+      T:
+        taps:
+          1: t
+          2: [C-t, Enter]
+          3: { set_mode: vim }
+keymap:
+  - remap:
+      C-b: left
+      M-f: C-right
+      CapsLock-j: down
+      Shift-a: C-a
+      C-x:
+        remap:
+          h: C-a
+          C-s: C-s
+          k:
+            remap:
+              j: b
+            timeout: 100ms
+            timeout_key: Esc
+        timeout: 500ms
+      C-M-m: { set_mode: vim }
+  - mode: vim
+    remap:
+      h: left
+      Esc: { set_mode: default }
+";
+
+// Keys in the config and a few that aren't
+const KEYS: [Key; 17] = [
+    Key::KEY_A,
+    Key::KEY_B,
+    Key::KEY_F,
+    Key::KEY_H,
+    Key::KEY_J,
+    Key::KEY_K,
+    Key::KEY_M,
+    Key::KEY_S,
+    Key::KEY_T,
+    Key::KEY_X,
+    Key::KEY_ESC,
+    Key::KEY_SPACE,
+    Key::KEY_CAPSLOCK,
+    Key::KEY_MUHENKAN,
+    Key::KEY_LEFTCTRL,
+    Key::KEY_LEFTALT,
+    Key::KEY_LEFTSHIFT,
+];
+
+#[derive(Arbitrary, Debug)]
+enum FuzzEvent {
+    Key { key: u8, value: u8 },
+    Relative { code: u8, value: i8 },
+    // Move the clock forward by milliseconds, firing the timer if it expires
+    Advance(u8),
+    ConfigReload,
+}
+
+struct NoClient;
+
+impl Client for NoClient {
+    fn supported(&mut self) -> bool {
+        false
+    }
+    fn current_application(&mut self) -> Option<String> {
+        None
+    }
+    fn current_window(&mut self) -> Option<String> {
+        None
+    }
+}
+
+// A timer whose clock only moves with FuzzEvent::Advance, so that the inputs are reproducible
+#[derive(Clone)]
+struct FakeTimer {
+    now: Rc<Cell<Instant>>,
+    expires_at: Rc<Cell<Option<Instant>>>,
+}
+
+impl FakeTimer {
+    fn with_clock(now: &Rc<Cell<Instant>>) -> FakeTimer {
+        FakeTimer {
+            now: now.clone(),
+            expires_at: Rc::new(Cell::new(None)),
+        }
+    }
+
+    // Whether the timer fired, which disarms it
+    fn expire(&self) -> bool {
+        match self.expires_at.get() {
+            Some(expires_at) if expires_at <= self.now.get() => {
+                self.expires_at.set(None);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Timer for FakeTimer {
+    fn set(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        self.expires_at.set(Some(self.now.get() + duration));
+        Ok(())
+    }
+
+    fn unset(&mut self) -> Result<(), Box<dyn Error>> {
+        self.expires_at.set(None);
+        Ok(())
+    }
+
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
+
+fn config() -> &'static Config {
+    static CONFIG_CELL: OnceLock<Config> = OnceLock::new();
+    CONFIG_CELL.get_or_init(|| {
+        let mut config = parse_yaml_config(CONFIG).unwrap();
+        config.keymap_table = build_keymap_table(&config.keymap, config.default_exact_match, config.merge_strategy);
+        config
+    })
+}
+
+fn device_info<'a>() -> InputDeviceInfo<'a> {
+    InputDeviceInfo {
+        name: "Fuzz Device",
+        path: Path::new("/dev/input/event0"),
+    }
+}
+
+struct Harness {
+    handler: EventHandler,
+    timer: FakeTimer,
+    // Keys pressed on the input and the output devices
+    input_keys: HashSet<Key>,
+    output_keys: HashSet<Key>,
+}
+
+impl Harness {
+    fn new() -> Harness {
+        let now = Rc::new(Cell::new(Instant::now()));
+        let timer = FakeTimer::with_clock(&now);
+        let handler = EventHandler::new(
+            timer.clone(),
+            "default",
+            Duration::ZERO,
+            config().mouse_move_interval,
+            WMClient::new("none", Box::new(NoClient)),
+        );
+        Harness {
+            handler,
+            timer,
+            input_keys: HashSet::new(),
+            output_keys: HashSet::new(),
+        }
+    }
+
+    fn on_event(&mut self, event: Event) {
+        let actions = self.handler.on_events(&vec![event], config()).unwrap();
+        for action in actions {
+            if let Action::KeyEvent(key_event) = action {
+                match key_event.value() {
+                    0 => {
+                        self.output_keys.remove(&key_event.key);
+                    }
+                    1 => {
+                        self.output_keys.insert(key_event.key);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn on_key(&mut self, key: Key, value: KeyValue) {
+        match value {
+            KeyValue::Press => {
+                self.input_keys.insert(key);
+            }
+            KeyValue::Release => {
+                self.input_keys.remove(&key);
+            }
+            KeyValue::Repeat => {}
+        }
+        self.on_event(Event::KeyEvent(device_info(), KeyEvent::new(key, value)));
+    }
+
+    fn advance(&mut self, duration: Duration) {
+        let now = &self.timer.now;
+        now.set(now.get() + duration);
+        if self.timer.expire() {
+            self.on_event(Event::OverrideTimeout);
+        }
+    }
+}
+
+// No sequence of events should panic, and releasing every input key and shutting down
+// should leave no key pressed on the output device
+fuzz_target!(|events: Vec<FuzzEvent>| {
+    let mut harness = Harness::new();
+    for event in events {
+        match event {
+            FuzzEvent::Key { key, value } => {
+                let value = match value % 3 {
+                    0 => KeyValue::Release,
+                    1 => KeyValue::Press,
+                    _ => KeyValue::Repeat,
+                };
+                harness.on_key(KEYS[key as usize % KEYS.len()], value);
+            }
+            FuzzEvent::Relative { code, value } => {
+                let event = RelativeEvent::new_with(code as u16 % 16, value as i32);
+                harness.on_event(Event::RelativeEvent(device_info(), event));
+            }
+            FuzzEvent::Advance(millis) => harness.advance(Duration::from_millis(millis as u64)),
+            FuzzEvent::ConfigReload => harness.on_event(Event::ConfigReload),
+        }
+    }
+
+    let mut input_keys: Vec<Key> = harness.input_keys.iter().copied().collect();
+    input_keys.sort_by_key(|key| key.code());
+    for key in input_keys {
+        harness.on_key(key, KeyValue::Release);
+    }
+    harness.advance(Duration::from_secs(1));
+    harness.on_event(Event::Shutdown);
+    assert!(harness.output_keys.is_empty(), "keys left pressed: {:?}", harness.output_keys);
+});
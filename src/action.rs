@@ -1,23 +1,148 @@
+use std::io::{self, Write};
 use std::time::Duration;
 
-use evdev::InputEvent;
+use evdev::{InputEvent, LedType};
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
 
+use crate::config::led::serialize_led;
 use crate::event::{KeyEvent, RelativeEvent};
 
-// Input to ActionDispatcher. This should only contain things that are easily testable.
-#[derive(Debug)]
+/// Input to ActionDispatcher. This should only contain things that are easily testable.
+/// Serialized as a map with "type" and the fields of each variant for --trace-json.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum Action {
-    // InputEvent (EventType::KEY) sent to evdev
+    /// InputEvent (EventType::KEY) sent to evdev
     KeyEvent(KeyEvent),
-    // InputEvent (EventType::RELATIVE, NOT mouse movement events) sent to evdev
+    /// InputEvent (EventType::RELATIVE, NOT mouse movement events) sent to evdev
     RelativeEvent(RelativeEvent),
-    // InputEvent (EventType::RELATIVE, ONLY mouse movement events) a collection of mouse movement sent to evdev
+    /// InputEvent (EventType::RELATIVE, ONLY mouse movement events) a collection of mouse movement sent to evdev
+    #[serde(serialize_with = "serialize_mouse_movement")]
     MouseMovementEventCollection(Vec<RelativeEvent>),
-    // InputEvent of any event types. It's discouraged to use this for testing because
-    // we don't have full control over timeval and it's not pattern-matching friendly.
+    /// InputEvent of any event types. It's discouraged to use this for testing because
+    /// we don't have full control over timeval and it's not pattern-matching friendly.
+    #[serde(serialize_with = "serialize_input_event")]
     InputEvent(InputEvent),
-    // Run a command
+    /// Run a command
+    #[serde(serialize_with = "serialize_command")]
     Command(Vec<String>),
-    // keypress_delay_ms
+    /// Run a shell command in the background, logging its output
+    #[serde(serialize_with = "serialize_run")]
+    Run(String),
+    /// Run a shell command of { if: ... } in the background, and tell whether it succeeded with Event::PredicateResult
+    #[serde(serialize_with = "serialize_predicate")]
+    Predicate(String),
+    /// Send Event::TitlePoll after the duration, from a worker thread
+    #[serde(serialize_with = "serialize_poll_title")]
+    PollTitle(Duration),
+    /// keypress_delay_ms
+    #[serde(serialize_with = "serialize_delay")]
     Delay(Duration),
+    /// Grab or ungrab the input devices
+    #[serde(serialize_with = "serialize_set_grab")]
+    SetGrab(bool),
+    /// Turn an LED of the input devices on or off
+    SetLed {
+        #[serde(serialize_with = "serialize_led")]
+        led: LedType,
+        on: bool,
+    },
+    /// Press and release a key code that may not have a name in evdev
+    #[serde(serialize_with = "serialize_raw_code")]
+    RawCode(u16),
+    /// A keymap or modmap entry with a description matched. Only written to --trace-json.
+    #[serde(serialize_with = "serialize_matched")]
+    Matched(String),
+    /// Emit the following actions on a device of `virtual_devices`, or the default devices if None
+    #[serde(serialize_with = "serialize_select_device")]
+    SelectDevice(Option<String>),
+    /// Scroll by notches, whose fractions are accumulated by ActionDispatcher
+    Scroll { x: f64, y: f64 },
+}
+
+// Writes each Action as a line of JSON (NDJSON)
+pub struct ActionTrace {
+    writer: Box<dyn Write>,
+}
+
+impl ActionTrace {
+    pub fn new(writer: Box<dyn Write>) -> ActionTrace {
+        ActionTrace { writer }
+    }
+
+    pub fn write(&mut self, action: &Action) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, action)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+}
+
+fn serialize_mouse_movement<S: Serializer>(events: &Vec<RelativeEvent>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("MouseMovementEventCollection", 1)?;
+    state.serialize_field("events", events)?;
+    state.end()
+}
+
+fn serialize_input_event<S: Serializer>(event: &InputEvent, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("InputEvent", 3)?;
+    state.serialize_field("event_type", &event.event_type().0)?;
+    state.serialize_field("code", &event.code())?;
+    state.serialize_field("value", &event.value())?;
+    state.end()
+}
+
+fn serialize_command<S: Serializer>(command: &Vec<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("Command", 1)?;
+    state.serialize_field("command", command)?;
+    state.end()
+}
+
+fn serialize_run<S: Serializer>(command: &String, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("Run", 1)?;
+    state.serialize_field("command", command)?;
+    state.end()
+}
+
+fn serialize_predicate<S: Serializer>(command: &String, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("Predicate", 1)?;
+    state.serialize_field("command", command)?;
+    state.end()
+}
+
+fn serialize_matched<S: Serializer>(description: &String, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("Matched", 1)?;
+    state.serialize_field("description", description)?;
+    state.end()
+}
+
+// Milliseconds as a float so that sub-millisecond delays are kept
+fn serialize_delay<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("Delay", 1)?;
+    state.serialize_field("duration", &(duration.as_secs_f64() * 1000.0))?;
+    state.end()
+}
+
+fn serialize_poll_title<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("PollTitle", 1)?;
+    state.serialize_field("interval", &(duration.as_secs_f64() * 1000.0))?;
+    state.end()
+}
+
+fn serialize_set_grab<S: Serializer>(grab: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("SetGrab", 1)?;
+    state.serialize_field("grab", grab)?;
+    state.end()
+}
+
+fn serialize_select_device<S: Serializer>(device: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("SelectDevice", 1)?;
+    state.serialize_field("device", device)?;
+    state.end()
+}
+
+fn serialize_raw_code<S: Serializer>(code: &u16, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("RawCode", 1)?;
+    state.serialize_field("code", code)?;
+    state.end()
 }
@@ -1,33 +1,186 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
-use evdev::{uinput::VirtualDevice, EventType, InputEvent, Key};
+use evdev::{uinput::VirtualDevice, EventType, InputEvent, Key, LedType, MiscType, RelativeAxisType};
 use fork::{fork, setsid, Fork};
 use log::debug;
 use log::error;
+use log::{info, warn};
 use nix::sys::signal;
 use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet};
 use std::process::{exit, Command, Stdio};
 
+use crate::action::ActionTrace;
+use crate::config::syn_report::SynReport;
+use crate::device::{hid_scancode, is_mouse_button, is_output_key};
 use crate::event::RelativeEvent;
 use crate::{action::Action, event::KeyEvent};
 
 pub struct ActionDispatcher {
     // Device to emit events
     device: VirtualDevice,
+    // Device to emit mouse buttons and movements, unless they're emitted by `device`
+    pointer_device: Option<VirtualDevice>,
+    // Devices of `virtual_devices` by name
+    named_devices: HashMap<String, VirtualDevice>,
+    // Named device selected by Action::SelectDevice, which emits every event until it's unselected
+    selected_device: Option<String>,
+    // Keys that are pressed on the selected device
+    selected_pressed_keys: HashSet<Key>,
+    // Names of Action::SelectDevice without a device, warned once each
+    unknown_devices: HashSet<String>,
     // Whether we've called a sigaction for spawing commands or not
     sigaction_set: bool,
+    // Keys that are pressed on the output device
+    pressed_keys: HashSet<Key>,
+    // Keys that the output devices can't emit, warned once each
+    unsupported_keys: HashSet<Key>,
+    // Grab state requested by Action::SetGrab, applied to the input devices by the caller
+    grab_request: Option<bool>,
+    // LED states requested by Action::SetLed, also applied to the input devices by the caller
+    led_requests: Vec<(LedType, bool)>,
+    // --trace-json
+    trace: Option<ActionTrace>,
+    // Granularity of SYN_REPORT
+    syn_report: SynReport,
+    // Events waiting for a SYN_REPORT with SynReport::Batch
+    pending_events: Vec<InputEvent>,
+    // Worker for Action::Run, started on the first use
+    command_runner: Option<CommandRunner>,
+    // run_max_concurrency
+    run_max_concurrency: Arc<AtomicUsize>,
+    // Worker for Action::Predicate, whose results are taken by the main loop
+    predicate_runner: Option<PredicateRunner>,
+    // Worker for Action::PollTitle, which wakes up the main loop
+    title_poller: Option<TitlePoller>,
+    // enable_hires_wheel
+    hires_wheel: bool,
+    // emit_scancodes
+    emit_scancodes: bool,
+    // Fractions of { scroll: ... } not emitted yet, along REL_HWHEEL and REL_WHEEL
+    scroll_accumulators: [ScrollAccumulator; 2],
+    // max_output_rate
+    rate_limiter: Option<RateLimiter>,
 }
 
 impl ActionDispatcher {
     pub fn new(device: VirtualDevice) -> ActionDispatcher {
         ActionDispatcher {
             device,
+            pointer_device: None,
+            named_devices: HashMap::new(),
+            selected_device: None,
+            selected_pressed_keys: HashSet::new(),
+            unknown_devices: HashSet::new(),
             sigaction_set: false,
+            pressed_keys: HashSet::new(),
+            unsupported_keys: HashSet::new(),
+            grab_request: None,
+            led_requests: vec![],
+            trace: None,
+            syn_report: SynReport::default(),
+            pending_events: vec![],
+            command_runner: None,
+            run_max_concurrency: Arc::new(AtomicUsize::new(usize::MAX)),
+            predicate_runner: None,
+            title_poller: None,
+            hires_wheel: false,
+            emit_scancodes: false,
+            scroll_accumulators: Default::default(),
+            rate_limiter: None,
         }
     }
 
-    // Execute Actions created by EventHandler. This should be the only public method of ActionDispatcher.
+    pub fn set_run_max_concurrency(&mut self, run_max_concurrency: usize) {
+        self.run_max_concurrency.store(run_max_concurrency, Ordering::Relaxed);
+    }
+
+    pub fn set_predicate_runner(&mut self, predicate_runner: PredicateRunner) {
+        self.predicate_runner = Some(predicate_runner);
+    }
+
+    // Predicates of Action::Predicate that have exited since the last call, and whether they succeeded
+    pub fn take_predicate_results(&mut self) -> Vec<(String, bool)> {
+        match &mut self.predicate_runner {
+            Some(runner) => runner.take_results(),
+            None => vec![],
+        }
+    }
+
+    pub fn set_title_poller(&mut self, title_poller: TitlePoller) {
+        self.title_poller = Some(title_poller);
+    }
+
+    // Whether the interval of Action::PollTitle has elapsed since the last call
+    pub fn take_title_poll(&mut self) -> bool {
+        match &mut self.title_poller {
+            Some(poller) => poller.take(),
+            None => false,
+        }
+    }
+
+    pub fn set_syn_report(&mut self, syn_report: SynReport) {
+        self.syn_report = syn_report;
+    }
+
+    pub fn set_max_output_rate(&mut self, max_output_rate: Option<u32>) {
+        let interval = max_output_rate
+            .filter(|rate| *rate > 0)
+            .map(|rate| Duration::from_secs(1) / rate);
+        if self.rate_limiter.as_ref().map(|limiter| limiter.interval) != interval {
+            self.rate_limiter = interval.map(RateLimiter::new);
+        }
+    }
+
+    pub fn set_hires_wheel(&mut self, hires_wheel: bool) {
+        self.hires_wheel = hires_wheel;
+    }
+
+    pub fn set_emit_scancodes(&mut self, emit_scancodes: bool) {
+        self.emit_scancodes = emit_scancodes;
+    }
+
+    pub fn set_pointer_device(&mut self, pointer_device: VirtualDevice) {
+        self.pointer_device = Some(pointer_device);
+    }
+
+    pub fn add_named_device(&mut self, name: &str, device: VirtualDevice) {
+        self.named_devices.insert(name.to_string(), device);
+    }
+
+    // Emit the events delayed by SynReport::Batch. Call this after each list of Actions.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if self.pending_events.is_empty() {
+            return Ok(());
+        }
+        let events = std::mem::take(&mut self.pending_events);
+        let split_pointer = self.pointer_device.is_some() && self.selected_device.is_none();
+        for run in device_runs(&events, split_pointer) {
+            self.emit(run)?;
+        }
+        Ok(())
+    }
+
+    // Record every Action before executing it
+    pub fn set_trace(&mut self, trace: ActionTrace) {
+        self.trace = Some(trace);
+    }
+
+    // Execute Actions created by EventHandler.
     pub fn on_action(&mut self, action: Action) -> anyhow::Result<()> {
+        if let Some(trace) = &mut self.trace {
+            if let Err(e) = trace.write(&action) {
+                error!("Failed to write the trace, disabling it: {e}");
+                self.trace = None;
+            }
+        }
         match action {
             Action::KeyEvent(key_event) => self.on_key_event(key_event)?,
             Action::RelativeEvent(relative_event) => self.on_relative_event(relative_event)?,
@@ -49,11 +202,81 @@ impl ActionDispatcher {
 
             Action::InputEvent(event) => self.send_event(event)?,
             Action::Command(command) => self.run_command(command),
-            Action::Delay(duration) => thread::sleep(duration),
+            Action::Run(command) => {
+                let max_concurrency = &self.run_max_concurrency;
+                let runner = self
+                    .command_runner
+                    .get_or_insert_with(|| CommandRunner::new(max_concurrency.clone()));
+                runner.run(command);
+            }
+            Action::Predicate(command) => match &self.predicate_runner {
+                Some(runner) => runner.run(command),
+                None => error!("No predicate runner to run {:?}", command),
+            },
+            Action::PollTitle(interval) => match &self.title_poller {
+                Some(poller) => poller.poll(interval),
+                None => error!("No title poller to wait {:?}", interval),
+            },
+            Action::Delay(duration) => {
+                self.flush()?;
+                thread::sleep(duration)
+            }
+            Action::RawCode(code) => {
+                self.send_event(InputEvent::new_now(EventType::KEY, code, 1))?;
+                self.send_event(InputEvent::new_now(EventType::KEY, code, 0))?;
+            }
+            Action::Scroll { x, y } => self.scroll(x, y)?,
+            Action::SelectDevice(device) => self.select_device(device)?,
+            // Already written to the trace
+            Action::Matched(_) => {}
+            Action::SetGrab(grab) => {
+                if !grab {
+                    // Applications would see held keys forever once the input devices are ungrabbed
+                    self.release_pressed_keys()?;
+                }
+                self.grab_request = Some(grab);
+            }
+            Action::SetLed { led, on } => self.led_requests.push((led, on)),
         }
         Ok(())
     }
 
+    // Take the grab state requested since the last call
+    pub fn take_grab_request(&mut self) -> Option<bool> {
+        self.grab_request.take()
+    }
+
+    // Take the LED states requested since the last call, in the order they were requested
+    pub fn take_led_requests(&mut self) -> Vec<(LedType, bool)> {
+        std::mem::take(&mut self.led_requests)
+    }
+
+    pub fn release_pressed_keys(&mut self) -> std::io::Result<()> {
+        let selected_device = self.selected_device.clone();
+        self.select_device(None)?;
+        for key in self.pressed_keys.clone() {
+            self.send_event(InputEvent::new_now(EventType::KEY, key.code(), 0))?;
+        }
+        self.select_device(selected_device)
+    }
+
+    // Events pending for the previous device are emitted on it, and keys left pressed on it are released
+    // since nothing would release them later.
+    fn select_device(&mut self, device: Option<String>) -> std::io::Result<()> {
+        for key in self.selected_pressed_keys.clone() {
+            self.send_event(InputEvent::new_now(EventType::KEY, key.code(), 0))?;
+        }
+        self.flush()?;
+        self.selected_device = device.filter(|name| {
+            let known = self.named_devices.contains_key(name);
+            if !known && self.unknown_devices.insert(name.clone()) {
+                warn!("No virtual device named {}, using the default one", name);
+            }
+            known
+        });
+        Ok(())
+    }
+
     fn on_key_event(&mut self, event: KeyEvent) -> std::io::Result<()> {
         let event = InputEvent::new_now(EventType::KEY, event.code(), event.value());
         self.send_event(event)
@@ -64,6 +287,33 @@ impl ActionDispatcher {
         self.send_event(event)
     }
 
+    // Emit REL_HWHEEL and REL_WHEEL once a whole notch is accumulated, in the same report as their hi-res events
+    // regardless of syn_report, like a physical hi-res wheel.
+    fn scroll(&mut self, x: f64, y: f64) -> std::io::Result<()> {
+        let axes = [
+            (RelativeAxisType::REL_HWHEEL, RelativeAxisType::REL_HWHEEL_HI_RES, x),
+            (RelativeAxisType::REL_WHEEL, RelativeAxisType::REL_WHEEL_HI_RES, y),
+        ];
+        let mut events = vec![];
+        for (accumulator, (axis, hires_axis, notches)) in self.scroll_accumulators.iter_mut().zip(axes) {
+            let (value, hires_value) = accumulator.add(notches);
+            if value != 0 {
+                events.push(InputEvent::new_now(EventType::RELATIVE, axis.0, value));
+            }
+            if hires_value != 0 && self.hires_wheel {
+                events.push(InputEvent::new_now(EventType::RELATIVE, hires_axis.0, hires_value));
+            }
+        }
+        match self.syn_report {
+            _ if events.is_empty() => Ok(()),
+            SynReport::Event | SynReport::Action => self.emit(&events),
+            SynReport::Batch => {
+                self.pending_events.extend(events);
+                Ok(())
+            }
+        }
+    }
+
     // a function that takes mouse movement events to send in a single batch, unseparated by synchronization events.
     fn send_mousemovement_event_batch(&mut self, eventbatch: Vec<RelativeEvent>) -> std::io::Result<()> {
         let mut mousemovementbatch: Vec<InputEvent> = Vec::new();
@@ -74,14 +324,71 @@ impl ActionDispatcher {
                 mouse_movement.value,
             ));
         }
-        self.device.emit(&mousemovementbatch)
+        match self.syn_report {
+            // Changes the cursor movement as described in on_action
+            SynReport::Event => mousemovementbatch.into_iter().try_for_each(|event| self.emit(&[event])),
+            SynReport::Action => self.emit(&mousemovementbatch),
+            SynReport::Batch => {
+                self.pending_events.extend(mousemovementbatch);
+                Ok(())
+            }
+        }
     }
 
     fn send_event(&mut self, event: InputEvent) -> std::io::Result<()> {
         if event.event_type() == EventType::KEY {
-            debug!("{}: {:?}", event.value(), Key::new(event.code()))
+            let key = Key::new(event.code());
+            debug!("{}: {:?}", event.value(), key);
+            if !is_output_key(key) && self.unsupported_keys.insert(key) {
+                warn!("{:?} can't be emitted since the output device doesn't support it", key);
+            }
+            let pressed_keys = match self.selected_device {
+                Some(_) => &mut self.selected_pressed_keys,
+                None => &mut self.pressed_keys,
+            };
+            match event.value() {
+                0 => pressed_keys.remove(&Key::new(event.code())),
+                _ => pressed_keys.insert(Key::new(event.code())),
+            };
+        }
+        let mut events = vec![];
+        // In the same report as the key like a keyboard, which doesn't send one for autorepeat
+        if self.emit_scancodes && event.event_type() == EventType::KEY && event.value() != 2 {
+            if let Some(scancode) = hid_scancode(Key::new(event.code())) {
+                events.push(InputEvent::new_now(EventType::MISC, MiscType::MSC_SCAN.0, scancode));
+            }
+        }
+        events.push(event);
+        match self.syn_report {
+            // Every Action other than mouse movements and raw codes is a single event
+            SynReport::Event | SynReport::Action => self.emit(&events),
+            SynReport::Batch => {
+                self.pending_events.extend(events);
+                Ok(())
+            }
+        }
+    }
+
+    // Emit events of the same device, chosen by the first one
+    fn emit(&mut self, events: &[InputEvent]) -> std::io::Result<()> {
+        // Wait instead of dropping events so that nothing is lost
+        if let Some(rate_limiter) = &mut self.rate_limiter {
+            let delay = rate_limiter.delay(Instant::now(), events.len());
+            if !delay.is_zero() {
+                thread::sleep(delay);
+            }
+        }
+        if let Some(device) = self
+            .selected_device
+            .as_ref()
+            .and_then(|name| self.named_devices.get_mut(name))
+        {
+            return device.emit(events);
+        }
+        match &mut self.pointer_device {
+            Some(pointer_device) if events.first().is_some_and(is_pointer_event) => pointer_device.emit(events),
+            _ => self.device.emit(events),
         }
-        self.device.emit(&[event])
     }
 
     fn run_command(&mut self, command: Vec<String>) {
@@ -132,3 +439,324 @@ impl ActionDispatcher {
         }
     }
 }
+
+fn is_pointer_event(event: &InputEvent) -> bool {
+    match event.event_type() {
+        EventType::RELATIVE => true,
+        EventType::KEY => is_mouse_button(Key::new(event.code())),
+        _ => false,
+    }
+}
+
+// Consecutive events for the same output device, so that e.g. Ctrl is still pressed before a click
+// on the pointer device. Each run is emitted with a SYN_REPORT after it.
+fn device_runs(events: &[InputEvent], split_pointer: bool) -> impl Iterator<Item = &[InputEvent]> {
+    events.chunk_by(move |a, b| !split_pointer || is_pointer_event(a) == is_pointer_event(b))
+}
+
+// A hi-res wheel reports 120 per notch, and a notch of the normal wheel once it has been scrolled that much
+#[derive(Default)]
+struct ScrollAccumulator {
+    // Less than 1/120 of a notch
+    hires_fraction: f64,
+    // Hi-res scroll since the last notch
+    hires_since_notch: i32,
+}
+
+impl ScrollAccumulator {
+    // Return the notches and the hi-res value to emit
+    fn add(&mut self, notches: f64) -> (i32, i32) {
+        let hires = notches * 120.0 + self.hires_fraction;
+        let hires_value = hires.trunc() as i32;
+        self.hires_fraction = hires.fract();
+        self.hires_since_notch += hires_value;
+        let value = self.hires_since_notch / 120;
+        self.hires_since_notch -= value * 120;
+        (value, hires_value)
+    }
+}
+
+// Spaces out emitted events by the interval, without bursts
+struct RateLimiter {
+    interval: Duration,
+    // When the next event may be emitted
+    next_at: Option<Instant>,
+}
+
+impl RateLimiter {
+    fn new(interval: Duration) -> RateLimiter {
+        RateLimiter {
+            interval,
+            next_at: None,
+        }
+    }
+
+    // How long to wait before emitting the events at `now`
+    fn delay(&mut self, now: Instant, events: usize) -> Duration {
+        let start = self.next_at.map_or(now, |next_at| next_at.max(now));
+        self.next_at = Some(start + self.interval * events as u32);
+        start - now
+    }
+}
+
+// Runs commands of Action::Run on a worker thread so that slow commands don't block input
+struct CommandRunner {
+    sender: Sender<String>,
+}
+
+impl CommandRunner {
+    fn new(max_concurrency: Arc<AtomicUsize>) -> CommandRunner {
+        let (sender, receiver) = mpsc::channel::<String>();
+        let running = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            for command in receiver {
+                let max_concurrency = max_concurrency.load(Ordering::Relaxed);
+                if running.load(Ordering::SeqCst) >= max_concurrency {
+                    warn!("Skipped running {:?} since {} commands are running", command, max_concurrency);
+                    continue;
+                }
+                let child = match Command::new("sh")
+                    .arg("-c")
+                    .arg(&command)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                {
+                    Ok(child) => child,
+                    Err(e) => {
+                        error!("Error running command {:?}: {}", command, e);
+                        continue;
+                    }
+                };
+                debug!("Process started: {:?}, pid {}", command, child.id());
+
+                // Wait for each process on its own thread so that a slow one doesn't block the others
+                running.fetch_add(1, Ordering::SeqCst);
+                let running = running.clone();
+                thread::spawn(move || {
+                    match child.wait_with_output() {
+                        Ok(output) => {
+                            let stdout = String::from_utf8_lossy(&output.stdout);
+                            if !stdout.trim().is_empty() {
+                                info!("{:?} stdout: {}", command, stdout.trim_end());
+                            }
+                            let stderr = String::from_utf8_lossy(&output.stderr);
+                            if !stderr.trim().is_empty() {
+                                warn!("{:?} stderr: {}", command, stderr.trim_end());
+                            }
+                            if !output.status.success() {
+                                error!("Command {:?} failed: {}", command, output.status);
+                            }
+                        }
+                        // e.g. the process was reaped by SA_NOCLDWAIT set for { launch: ... }
+                        Err(e) => debug!("Error waiting for {:?}: {}", command, e),
+                    }
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        CommandRunner { sender }
+    }
+
+    fn run(&self, command: String) {
+        if self.sender.send(command).is_err() {
+            error!("The command runner thread has stopped");
+        }
+    }
+}
+
+// Runs commands of Action::Predicate on worker threads, and wakes up select(2) of the main loop when they exit
+pub struct PredicateRunner {
+    sender: Sender<(String, bool)>,
+    receiver: Receiver<(String, bool)>,
+    // A byte is written to the first one for each result, which makes the second one readable
+    waker: UnixStream,
+    wakee: UnixStream,
+}
+
+impl PredicateRunner {
+    pub fn new() -> io::Result<PredicateRunner> {
+        let (sender, receiver) = mpsc::channel();
+        let (waker, wakee) = UnixStream::pair()?;
+        wakee.set_nonblocking(true)?;
+        Ok(PredicateRunner {
+            sender,
+            receiver,
+            waker,
+            wakee,
+        })
+    }
+
+    fn run(&self, command: String) {
+        let sender = self.sender.clone();
+        let mut waker = match self.waker.try_clone() {
+            Ok(waker) => waker,
+            Err(e) => {
+                error!("Error running predicate {:?}: {}", command, e);
+                return;
+            }
+        };
+        thread::spawn(move || {
+            let success = run_predicate(&command);
+            debug!("Predicate {:?} is {}", command, success);
+            if sender.send((command, success)).is_ok() {
+                let _ = waker.write_all(&[0]);
+            }
+        });
+    }
+
+    fn take_results(&mut self) -> Vec<(String, bool)> {
+        let mut buffer = [0; 64];
+        while matches!(self.wakee.read(&mut buffer), Ok(n) if n > 0) {}
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl AsRawFd for PredicateRunner {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wakee.as_raw_fd()
+    }
+}
+
+// Waits for the interval of Action::PollTitle on a worker thread, and wakes up select(2) of the main loop.
+// The title is queried by EventHandler, which owns the Client.
+pub struct TitlePoller {
+    // A byte is written to the first one when the interval elapses, which makes the second one readable
+    waker: UnixStream,
+    wakee: UnixStream,
+}
+
+impl TitlePoller {
+    pub fn new() -> io::Result<TitlePoller> {
+        let (waker, wakee) = UnixStream::pair()?;
+        wakee.set_nonblocking(true)?;
+        Ok(TitlePoller { waker, wakee })
+    }
+
+    fn poll(&self, interval: Duration) {
+        let mut waker = match self.waker.try_clone() {
+            Ok(waker) => waker,
+            Err(e) => {
+                error!("Error polling the title: {}", e);
+                return;
+            }
+        };
+        thread::spawn(move || {
+            thread::sleep(interval);
+            let _ = waker.write_all(&[0]);
+        });
+    }
+
+    fn take(&mut self) -> bool {
+        let mut buffer = [0; 64];
+        let mut elapsed = false;
+        while matches!(self.wakee.read(&mut buffer), Ok(n) if n > 0) {
+            elapsed = true;
+        }
+        elapsed
+    }
+}
+
+impl AsRawFd for TitlePoller {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wakee.as_raw_fd()
+    }
+}
+
+// Whether the command exits with 0. The shell prints the exit status because
+// waiting for the process fails once SA_NOCLDWAIT is set for { launch: ... }.
+fn run_predicate(command: &str) -> bool {
+    let mut child = match Command::new("sh")
+        .arg("-c")
+        .arg("eval \"$1\" > /dev/null; echo $?")
+        .arg("sh")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Error running predicate {:?}: {}", command, e);
+            return false;
+        }
+    };
+    let mut status = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        let _ = stdout.read_to_string(&mut status);
+    }
+    let _ = child.wait();
+    status.trim() == "0"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{device_runs, RateLimiter, ScrollAccumulator};
+    use crate::device::hid_scancode;
+    use evdev::{EventType, InputEvent, Key};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_device_runs() {
+        let key = |key: Key, value| InputEvent::new(EventType::KEY, key.code(), value);
+        let events = [
+            key(Key::KEY_LEFTCTRL, 1),
+            key(Key::BTN_LEFT, 1),
+            key(Key::BTN_LEFT, 0),
+            key(Key::KEY_LEFTCTRL, 0),
+        ];
+        let runs = |split_pointer| {
+            device_runs(&events, split_pointer)
+                .map(|run| run.iter().map(|event| (event.code(), event.value())).collect())
+                .collect::<Vec<Vec<_>>>()
+        };
+        // The click is between the press and release of Ctrl
+        assert_eq!(
+            runs(true),
+            vec![
+                vec![(Key::KEY_LEFTCTRL.code(), 1)],
+                vec![(Key::BTN_LEFT.code(), 1), (Key::BTN_LEFT.code(), 0)],
+                vec![(Key::KEY_LEFTCTRL.code(), 0)],
+            ]
+        );
+        assert_eq!(runs(false).len(), 1);
+    }
+
+    #[test]
+    fn test_scroll_accumulator() {
+        let mut accumulator = ScrollAccumulator::default();
+        assert_eq!(accumulator.add(0.4), (0, 48));
+        assert_eq!(accumulator.add(0.4), (0, 48));
+        assert_eq!(accumulator.add(0.4), (1, 48));
+        assert_eq!(accumulator.add(-0.25), (0, -30));
+        assert_eq!(accumulator.add(-2.0), (-2, -240));
+        assert_eq!(accumulator.add(1.0 / 3.0), (0, 40));
+        assert_eq!(accumulator.add(1.0 / 3.0), (0, 40));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(Duration::from_millis(10));
+        let now = Instant::now();
+        assert_eq!(limiter.delay(now, 2), Duration::ZERO);
+        // Queued after the previous events
+        assert_eq!(limiter.delay(now + Duration::from_millis(5), 1), Duration::from_millis(15));
+        assert_eq!(limiter.delay(now + Duration::from_millis(20), 1), Duration::from_millis(10));
+        // Idle time doesn't build up a burst
+        assert_eq!(limiter.delay(now + Duration::from_secs(1), 1), Duration::ZERO);
+        assert_eq!(limiter.delay(now + Duration::from_secs(1), 1), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_hid_scancode() {
+        assert_eq!(hid_scancode(Key::KEY_A), Some(0x70004));
+        assert_eq!(hid_scancode(Key::KEY_1), Some(0x7001e));
+        assert_eq!(hid_scancode(Key::KEY_BACKSLASH), Some(0x70031));
+        assert_eq!(hid_scancode(Key::KEY_MUHENKAN), Some(0x7008b));
+        assert_eq!(hid_scancode(Key::KEY_LEFTCTRL), Some(0x700e0));
+        assert_eq!(hid_scancode(Key::KEY_RIGHTMETA), Some(0x700e7));
+        assert_eq!(hid_scancode(Key::KEY_RESERVED), None);
+        assert_eq!(hid_scancode(Key::BTN_LEFT), None);
+    }
+}
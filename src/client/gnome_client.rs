@@ -14,7 +14,7 @@ impl GnomeClient {
     fn connect(&mut self) {
         match Connection::new_session() {
             Ok(connection) => self.connection = Some(connection),
-            Err(e) => println!("GnomeClient#connect() failed: {}", e),
+            Err(e) => eprintln!("GnomeClient#connect() failed: {}", e),
         }
     }
 }
@@ -29,4 +29,9 @@ impl Client for HyprlandClient {
         }
         None
     }
+
+    fn current_window_pid(&mut self) -> Option<u32> {
+        let win = HyprClient::get_active().ok()??;
+        u32::try_from(win.pid).ok()
+    }
 }
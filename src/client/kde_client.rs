@@ -1,28 +1,63 @@
-use log::{debug, info, warn};
-use std::env::temp_dir;
+use log::{debug, error, info, warn};
+use std::env::{self, temp_dir};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::client::Client;
+use crate::client::{Client, WindowGeometry};
+use crate::device::request_rescan;
 use zbus::{dbus_interface, fdo, Connection};
 
 const KWIN_SCRIPT: &str = include_str!("kwin-script.js");
+// Followed by a hash of KWIN_SCRIPT, so that the script of another xremap version has another name
 const KWIN_SCRIPT_PLUGIN_NAME: &str = "xremap";
+const KWIN_OWNER_CHANGED_RULE: &str =
+    "type='signal',interface='org.freedesktop.DBus',member='NameOwnerChanged',arg0='org.kde.KWin'";
+const MAX_RECONNECT_ATTEMPTS: u32 = 8;
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+const RESCAN_DEVICES_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct KdeClient {
     supported: Option<bool>,
     active_window: Arc<Mutex<ActiveWindow>>,
+    // Updated by the DBus server thread
+    server_state: Arc<Mutex<ServerState>>,
+    warned_reconnecting: bool,
+    // A reload of the kwin script by refresh is running
+    reloading: Arc<AtomicBool>,
 }
 
 struct KwinScriptTempFile(PathBuf);
 
 impl KwinScriptTempFile {
     fn new() -> Self {
-        Self(temp_dir().join("xremap-kwin-script.js"))
+        Self(kwin_script_dir().join("xremap-kwin-script.js"))
     }
 }
 
+// XREMAP_KWIN_SCRIPT_DIR for systems where KWin can't read the files xremap writes to /tmp
+fn kwin_script_dir() -> PathBuf {
+    match env::var_os("XREMAP_KWIN_SCRIPT_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => temp_dir(),
+    }
+}
+
+// The plugin name loaded last, which is unloaded on the next start even if it's of another version
+fn loaded_plugin_name_path() -> PathBuf {
+    kwin_script_dir().join("xremap-kwin-script.plugin")
+}
+
+fn kwin_script_plugin_name() -> String {
+    // FNV-1a, which unlike DefaultHasher is the same across Rust versions
+    let hash = KWIN_SCRIPT
+        .bytes()
+        .fold(0x811c9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+    format!("{KWIN_SCRIPT_PLUGIN_NAME}-{hash:08x}")
+}
+
 impl Drop for KwinScriptTempFile {
     fn drop(&mut self) {
         let _ = std::fs::remove_file(&self.0);
@@ -30,36 +65,34 @@ impl Drop for KwinScriptTempFile {
 }
 
 trait KWinScripting {
-    fn load_script(&self, path: &Path) -> Result<i32, ConnectionError>;
-    fn unload_script(&self) -> Result<bool, ConnectionError>;
+    fn load_script(&self, path: &Path, plugin_name: &str) -> Result<i32, ConnectionError>;
+    fn unload_script(&self, plugin_name: &str) -> Result<bool, ConnectionError>;
     fn start_script(&self, script_obj_id: i32) -> Result<(), ConnectionError>;
-    #[allow(dead_code)]
-    fn is_script_loaded(&self) -> Result<bool, ConnectionError>;
+    fn is_script_loaded(&self, plugin_name: &str) -> Result<bool, ConnectionError>;
 }
 
 impl KWinScripting for Connection {
-    fn load_script(&self, path: &Path) -> Result<i32, ConnectionError> {
+    fn load_script(&self, path: &Path, plugin_name: &str) -> Result<i32, ConnectionError> {
         self.call_method(
             Some("org.kde.KWin"),
             "/Scripting",
             Some("org.kde.kwin.Scripting"),
             "loadScript",
             // since OsStr does not implement zvariant::Type, the temp-path must be valid utf-8
-            &(path.to_str().ok_or(ConnectionError::TempPathNotValidUtf8)?, KWIN_SCRIPT_PLUGIN_NAME),
+            &(path.to_str().ok_or(ConnectionError::TempPathNotValidUtf8)?, plugin_name),
         )
         .map_err(|_| ConnectionError::LoadScriptCall)?
         .body::<i32>()
         .map_err(|_| ConnectionError::InvalidLoadScriptResult)
     }
 
-    fn unload_script(&self) -> Result<bool, ConnectionError> {
+    fn unload_script(&self, plugin_name: &str) -> Result<bool, ConnectionError> {
         self.call_method(
             Some("org.kde.KWin"),
             "/Scripting",
             Some("org.kde.kwin.Scripting"),
             "unloadScript",
-            // since OsStr does not implement zvariant::Type, the temp-path must be valid utf-8
-            &KWIN_SCRIPT_PLUGIN_NAME,
+            &plugin_name,
         )
         .map_err(|_| ConnectionError::UnloadScriptCall)?
         .body::<bool>()
@@ -84,13 +117,13 @@ impl KWinScripting for Connection {
         Err(ConnectionError::StartScriptCall)
     }
 
-    fn is_script_loaded(&self) -> Result<bool, ConnectionError> {
+    fn is_script_loaded(&self, plugin_name: &str) -> Result<bool, ConnectionError> {
         self.call_method(
             Some("org.kde.KWin"),
             "/Scripting",
             Some("org.kde.kwin.Scripting"),
             "isScriptLoaded",
-            &KWIN_SCRIPT_PLUGIN_NAME,
+            &plugin_name,
         )
         .map_err(|_| ConnectionError::IsScriptLoadedCall)?
         .body::<bool>()
@@ -103,23 +136,40 @@ impl KWinScripting for Connection {
 fn load_kwin_script() -> Result<(), ConnectionError> {
     let dbus = Connection::new_session().map_err(|_| ConnectionError::ClientSession)?;
 
-    // Unload previous script
+    let plugin_name = kwin_script_plugin_name();
 
-    dbus.unload_script()?;
+    // Unload previous scripts. The one loaded last may be of another xremap version, and the name without
+    // a hash is of versions before it was added.
+
+    let loaded_plugin_name = std::fs::read_to_string(loaded_plugin_name_path()).ok();
+    for stale_name in loaded_plugin_name
+        .iter()
+        .map(String::as_str)
+        .chain([KWIN_SCRIPT_PLUGIN_NAME])
+    {
+        if stale_name != plugin_name && dbus.is_script_loaded(stale_name)? {
+            info!("Unloading the kwin-script of another xremap version ('{stale_name}').");
+            dbus.unload_script(stale_name)?;
+        }
+    }
+    dbus.unload_script(&plugin_name)?;
 
     // Start new script
 
     let init_script = || {
         let temp_file_path = KwinScriptTempFile::new();
         std::fs::write(&temp_file_path.0, KWIN_SCRIPT).map_err(|_| ConnectionError::WriteScriptToTempFile)?;
-        let script_obj_id = dbus.load_script(&temp_file_path.0)?;
+        let script_obj_id = dbus.load_script(&temp_file_path.0, &plugin_name)?;
         dbus.start_script(script_obj_id)?;
+        if let Err(err) = std::fs::write(loaded_plugin_name_path(), &plugin_name) {
+            debug!("Could not record the kwin-script plugin name ('{err:?}').");
+        }
         Ok(())
     };
 
     if let Err(err) = init_script() {
-        debug!("Trying to unload kwin-script plugin ('{KWIN_SCRIPT_PLUGIN_NAME}').");
-        match dbus.unload_script() {
+        debug!("Trying to unload kwin-script plugin ('{plugin_name}').");
+        match dbus.unload_script(&plugin_name) {
                 Err(err) => debug!("Error unloading plugin ('{err:?}'). It may still be loaded and could cause future runs of xremap to fail."),
                 Ok(unloaded) if unloaded => debug!("Successfully unloaded plugin."),
                 Ok(_) => debug!("Plugin was not loaded in the first place."),
@@ -136,11 +186,17 @@ impl KdeClient {
             title: String::new(),
             res_name: String::new(),
             res_class: String::new(),
+            geometry: None,
+            pid: None,
+            updated_at: None,
         }));
 
         let mut client = KdeClient {
             active_window,
             supported: None,
+            server_state: Arc::new(Mutex::new(ServerState::Running)),
+            warned_reconnecting: false,
+            reloading: Arc::new(AtomicBool::new(false)),
         };
 
         let conn_res = client.connect();
@@ -156,35 +212,39 @@ impl KdeClient {
 
     fn connect(&mut self) -> Result<(), ConnectionError> {
         let active_window = Arc::clone(&self.active_window);
+        let server_state = Arc::clone(&self.server_state);
         let (tx, rx) = channel();
         std::thread::spawn(move || {
-            let connect = move || {
-                let connection = Connection::new_session().map_err(|_| ConnectionError::ServerSession)?;
-                fdo::DBusProxy::new(&connection)
-                    .map_err(|_| ConnectionError::CreateDBusProxy)?
-                    .request_name("com.k0kubun.Xremap", fdo::RequestNameFlags::ReplaceExisting.into())
-                    .map_err(|_| ConnectionError::RequestName)?;
-                let mut object_server = zbus::ObjectServer::new(&connection);
-                let awi = ActiveWindowInterface { active_window };
-                object_server
-                    .at(&"/com/k0kubun/Xremap".try_into().unwrap(), awi)
-                    .map_err(|_| ConnectionError::ServeObjServer)?;
-                Ok(object_server)
-            };
-            let object_server: Result<zbus::ObjectServer, ConnectionError> = connect();
-            match object_server {
-                Ok(mut object_server) => {
+            let mut object_server = match start_server(&active_window) {
+                Ok(object_server) => {
                     let _ = tx.send(Ok(()));
-                    loop {
-                        if let Err(err) = object_server.try_handle_next() {
-                            eprintln!("{}", err);
-                        }
+                    object_server
+                }
+                Err(err) => {
+                    let _ = tx.send(Err(err));
+                    return;
+                }
+            };
+            loop {
+                let err = serve(&mut object_server);
+                error!("KDE DBus connection lost: {err:?}");
+                *server_state.lock().unwrap() = ServerState::Reconnecting;
+                match reconnect(&active_window) {
+                    Some(reconnected) => {
+                        info!("Reconnected to KDE.");
+                        object_server = reconnected;
+                        *server_state.lock().unwrap() = ServerState::Running;
+                    }
+                    None => {
+                        error!("Gave up reconnecting to KDE after {MAX_RECONNECT_ATTEMPTS} attempts.");
+                        *server_state.lock().unwrap() = ServerState::Failed;
+                        return;
                     }
                 }
-                Err(err) => tx.send(Err(err)),
             }
         });
-        rx.recv().unwrap()?;
+        // The thread dropping the sender without sending means it panicked
+        rx.recv().map_err(|_| ConnectionError::ServerThread)??;
 
         // Load kwin script after server has started. To be sure the server gets
         //  the output the kwin script sends when it starts.
@@ -193,19 +253,156 @@ impl KdeClient {
     }
 }
 
+fn start_server(active_window: &Arc<Mutex<ActiveWindow>>) -> Result<zbus::ObjectServer<'static>, ConnectionError> {
+    let connection = Connection::new_session().map_err(|_| ConnectionError::ServerSession)?;
+    let dbus = fdo::DBusProxy::new(&connection).map_err(|_| ConnectionError::CreateDBusProxy)?;
+    dbus.request_name("com.k0kubun.Xremap", fdo::RequestNameFlags::ReplaceExisting.into())
+        .map_err(|_| ConnectionError::RequestName)?;
+    // Get notified when KWin restarts, which unloads the script
+    dbus.add_match(KWIN_OWNER_CHANGED_RULE)
+        .map_err(|_| ConnectionError::AddMatch)?;
+    let mut object_server = zbus::ObjectServer::new(&connection);
+    let awi = ActiveWindowInterface {
+        active_window: Arc::clone(active_window),
+    };
+    let path = "/com/k0kubun/Xremap"
+        .try_into()
+        .map_err(|_| ConnectionError::ServeObjServer)?;
+    object_server
+        .at(&path, awi)
+        .map_err(|_| ConnectionError::ServeObjServer)?;
+    Ok(object_server)
+}
+
+// Handle messages until the connection is lost
+fn serve(object_server: &mut zbus::ObjectServer) -> ConnectionError {
+    loop {
+        match object_server.try_handle_next() {
+            Ok(Some(message)) if is_kwin_started(&message) => {
+                info!("KWin restarted. Reloading the kwin script.");
+                if let Err(err) = load_kwin_script() {
+                    warn!("Could not reload the kwin script. Error: {err:?}");
+                }
+            }
+            Ok(_) => {}
+            // The connection is gone, so every following call would fail the same way
+            Err(zbus::Error::Io(_)) => return ConnectionError::ServerConnectionLost,
+            Err(err) => eprintln!("{}", err),
+        }
+    }
+}
+
+// Retry with exponential backoff so that a permanently broken session doesn't busy-loop
+fn reconnect(active_window: &Arc<Mutex<ActiveWindow>>) -> Option<zbus::ObjectServer<'static>> {
+    let mut backoff = Duration::from_secs(1);
+    for attempt in 1..=MAX_RECONNECT_ATTEMPTS {
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+        match start_server(active_window) {
+            Ok(object_server) => match load_kwin_script() {
+                Ok(()) => return Some(object_server),
+                Err(err) => warn!("Reconnecting to KDE failed ({attempt}/{MAX_RECONNECT_ATTEMPTS}). Error: {err:?}"),
+            },
+            Err(err) => warn!("Reconnecting to KDE failed ({attempt}/{MAX_RECONNECT_ATTEMPTS}). Error: {err:?}"),
+        }
+    }
+    None
+}
+
+// NameOwnerChanged(name, old_owner, new_owner) of org.kde.KWin with a new owner
+fn is_kwin_started(message: &zbus::Message) -> bool {
+    let is_owner_changed = message
+        .header()
+        .and_then(|header| Ok(header.member()? == Some("NameOwnerChanged")))
+        .unwrap_or(false);
+    is_owner_changed
+        && matches!(
+            message.body::<(&str, &str, &str)>(),
+            Ok(("org.kde.KWin", _, new_owner)) if !new_owner.is_empty()
+        )
+}
+
+impl KdeClient {
+    // Whether the active window is still known. It's the last known one while reconnecting.
+    fn server_running(&mut self) -> bool {
+        let state = *self.server_state.lock().unwrap();
+        match state {
+            ServerState::Running => self.warned_reconnecting = false,
+            ServerState::Reconnecting => {
+                if !self.warned_reconnecting {
+                    warn!("Reconnecting to KDE. Using the last known active window until then.");
+                    self.warned_reconnecting = true;
+                }
+            }
+            ServerState::Failed => self.supported = Some(false),
+        }
+        self.supported.unwrap_or(false)
+    }
+}
+
 impl Client for KdeClient {
     fn supported(&mut self) -> bool {
-        self.supported.unwrap()
+        self.supported.unwrap_or(false)
     }
     fn current_window(&mut self) -> Option<String> {
+        if !self.server_running() {
+            return None;
+        }
         let aw = self.active_window.lock().ok()?;
         Some(aw.title.clone())
     }
 
     fn current_application(&mut self) -> Option<String> {
+        if !self.server_running() {
+            return None;
+        }
         let aw = self.active_window.lock().ok()?;
         Some(aw.res_class.clone())
     }
+
+    fn updated_at(&mut self) -> Option<Instant> {
+        self.active_window.lock().ok()?.updated_at
+    }
+
+    // KWin only notifies focus changes, but the script notifies the active window when it starts.
+    // It's loaded on another thread so that key events don't wait for the DBus calls.
+    // Reconnecting loads the script by itself, and only one reload runs at a time.
+    fn refresh(&mut self) {
+        if !matches!(*self.server_state.lock().unwrap(), ServerState::Running)
+            || self.reloading.swap(true, Ordering::AcqRel)
+        {
+            return;
+        }
+        let reloading = Arc::clone(&self.reloading);
+        std::thread::spawn(move || {
+            if let Err(err) = load_kwin_script() {
+                warn!("Could not reload the kwin script. Error: {err:?}");
+            }
+            reloading.store(false, Ordering::Release);
+        });
+    }
+
+    fn current_window_geometry(&mut self) -> Option<WindowGeometry> {
+        if !self.server_running() {
+            return None;
+        }
+        self.active_window.lock().ok()?.geometry
+    }
+
+    fn current_window_pid(&mut self) -> Option<u32> {
+        if !self.server_running() {
+            return None;
+        }
+        self.active_window.lock().ok()?.pid
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ServerState {
+    Running,
+    Reconnecting,
+    // Gave up reconnecting
+    Failed,
 }
 
 #[derive(Debug)]
@@ -229,12 +426,21 @@ enum ConnectionError {
     CreateDBusProxy,
     RequestName,
     ServeObjServer,
+    AddMatch,
+    ServerThread,
+    ServerConnectionLost,
 }
 
 struct ActiveWindow {
     res_class: String,
     res_name: String,
     title: String,
+    // As of when the window was activated. None if KWin didn't tell it.
+    geometry: Option<WindowGeometry>,
+    // None if KWin didn't tell it
+    pid: Option<u32>,
+    // When KWin last notified the active window
+    updated_at: Option<Instant>,
 }
 
 struct ActiveWindowInterface {
@@ -243,12 +449,36 @@ struct ActiveWindowInterface {
 
 #[dbus_interface(name = "com.k0kubun.Xremap")]
 impl ActiveWindowInterface {
-    fn notify_active_window(&mut self, caption: String, res_class: String, res_name: String) {
+    // The arguments are the DBus method's, which KWin calls with one per value
+    #[allow(clippy::too_many_arguments)]
+    fn notify_active_window(
+        &mut self,
+        caption: String,
+        res_class: String,
+        res_name: String,
+        pid: i32,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) {
         // I want to always print this, since it is the only way to know what the resource class of applications is.
         info!("active window: caption: '{caption}', class: '{res_class}', name: '{res_name}'");
         let mut aw = self.active_window.lock().unwrap();
         aw.title = caption;
         aw.res_class = res_class;
         aw.res_name = res_name;
+        aw.geometry = Some(WindowGeometry { x, y, width, height }).filter(|geometry| geometry.width > 0);
+        aw.pid = u32::try_from(pid).ok().filter(|pid| *pid > 0);
+        aw.updated_at = Some(Instant::now());
+    }
+
+    // Select devices again with the current filters, and reply with the devices added and removed.
+    // This isn't from KWin, but for scripts, e.g. after plugging in a device without --watch=device.
+    fn rescan_devices(&self) -> fdo::Result<(Vec<String>, Vec<String>)> {
+        match request_rescan(RESCAN_DEVICES_TIMEOUT) {
+            Some(changes) => Ok((changes.added, changes.removed)),
+            None => Err(fdo::Error::Failed("Failed to rescan devices".into())),
+        }
     }
 }
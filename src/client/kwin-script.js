@@ -1,4 +1,5 @@
 function notifyActiveWindow(client) {
+    var geometry = "frameGeometry" in client ? client.frameGeometry : { x: 0, y: 0, width: 0, height: 0 };
     callDBus(
         "com.k0kubun.Xremap",
         "/com/k0kubun/Xremap",
@@ -6,7 +7,12 @@ function notifyActiveWindow(client) {
         "NotifyActiveWindow",
         "caption" in client ? client.caption : "",
         "resourceClass" in client ? client.resourceClass : "",
-        "resourceName" in client ? client.resourceName : ""
+        "resourceName" in client ? client.resourceName : "",
+        "pid" in client ? client.pid : 0,
+        Math.round(geometry.x),
+        Math.round(geometry.y),
+        Math.round(geometry.width),
+        Math.round(geometry.height)
     );
 }
 
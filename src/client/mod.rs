@@ -1,9 +1,35 @@
-use log::info;
+use log::{debug, info, warn};
+use std::fs;
+use std::time::{Duration, Instant};
 
 pub trait Client {
     fn supported(&mut self) -> bool;
     fn current_application(&mut self) -> Option<String>;
     fn current_window(&mut self) -> Option<String>;
+    // When the window manager last reported the values, for clients caching values that are
+    // updated asynchronously. None if the values are queried on every call.
+    fn updated_at(&mut self) -> Option<Instant> {
+        None
+    }
+    // Ask the window manager to report the values again, which updates updated_at when it's answered
+    fn refresh(&mut self) {}
+    // Position and size of the active window, for clients that know them
+    fn current_window_geometry(&mut self) -> Option<WindowGeometry> {
+        None
+    }
+    // Process of the active window, for clients that know it
+    fn current_window_pid(&mut self) -> Option<u32> {
+        None
+    }
+}
+
+// In the global coordinates of the compositor, including the window decorations
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
 }
 
 pub struct WMClient {
@@ -12,6 +38,20 @@ pub struct WMClient {
     supported: Option<bool>,
     last_application: String,
     last_window: String,
+    last_process: String,
+    // Number of key events handled so far
+    event: u64,
+    // The last event since which the values are known to be up to date
+    fresh_as_of: u64,
+    // Client::updated_at of the last query
+    last_updated_at: Option<Instant>,
+    // client_cache_ttl_ms
+    cache_ttl: Option<Duration>,
+    // When Client::refresh was called last, until the values are reported again
+    refresh_requested_at: Option<Instant>,
+    // Client::refresh was called since the focus changed last, which it isn't called again until
+    refreshed: bool,
+    warned_stale: bool,
 }
 
 impl WMClient {
@@ -22,6 +62,70 @@ impl WMClient {
             supported: None,
             last_application: String::new(),
             last_window: String::new(),
+            last_process: String::new(),
+            event: 0,
+            fresh_as_of: 0,
+            last_updated_at: None,
+            cache_ttl: None,
+            refresh_requested_at: None,
+            refreshed: false,
+            warned_stale: false,
+        }
+    }
+
+    // Called for every key event before querying the values for it
+    pub fn begin_event(&mut self, cache_ttl: Option<Duration>) -> u64 {
+        self.event += 1;
+        self.cache_ttl = cache_ttl;
+        self.event
+    }
+
+    // The values were reported by the window manager as of this event. If it's older than the current
+    // event, a focus change may not have been reported yet when the values were used for matching.
+    pub fn fresh_as_of(&self) -> u64 {
+        self.fresh_as_of
+    }
+
+    fn check_freshness(&mut self) {
+        let updated_at = match self.client.updated_at() {
+            Some(updated_at) => updated_at,
+            None => {
+                // Queried just now
+                self.fresh_as_of = self.event;
+                return;
+            }
+        };
+        if self.last_updated_at != Some(updated_at) {
+            debug!("application-client: {} updated as of event {}", self.name, self.event);
+            self.last_updated_at = Some(updated_at);
+            self.fresh_as_of = self.event;
+            self.warned_stale = false;
+            // Reports other than the answer to a refresh are of focus changes
+            match self.refresh_requested_at {
+                Some(requested_at) if updated_at >= requested_at => self.refresh_requested_at = None,
+                _ => self.refreshed = false,
+            }
+        }
+        let cache_ttl = match self.cache_ttl {
+            Some(cache_ttl) => cache_ttl,
+            None => return,
+        };
+        // Values not reported for a while are usually just of a window that's still focused, so they're
+        // queried again once per focus. They're only stale if the window manager doesn't answer that.
+        if let Some(requested_at) = self.refresh_requested_at {
+            if requested_at.elapsed() > cache_ttl && !self.warned_stale {
+                warn!(
+                    "application-client: {} hasn't reported the active window for {:?} since it was queried again, which may be stale",
+                    self.name,
+                    requested_at.elapsed()
+                );
+                self.warned_stale = true;
+            }
+        } else if !self.refreshed && updated_at.elapsed() > cache_ttl {
+            debug!("application-client: {} querying the active window again", self.name);
+            self.client.refresh();
+            self.refresh_requested_at = Some(Instant::now());
+            self.refreshed = true;
         }
     }
     pub fn current_window(&mut self) -> Option<String> {
@@ -35,10 +139,45 @@ impl WMClient {
         }
 
         let result = self.client.current_window();
+        self.check_freshness();
         if let Some(window) = &result {
             if &self.last_window != window {
                 self.last_window = window.clone();
-                println!("window: {}", window);
+                eprintln!("window: {}", window);
+            }
+        }
+        result
+    }
+
+    pub fn current_window_geometry(&mut self) -> Option<WindowGeometry> {
+        if self.supported.is_none() {
+            let supported = self.client.supported();
+            self.supported = Some(supported);
+            info!("application-client: {} (supported: {})", self.name, supported);
+        }
+        if !self.supported.unwrap() {
+            return None;
+        }
+        self.client.current_window_geometry()
+    }
+
+    // Executable name of the process of the active window. None if the client doesn't know its PID.
+    pub fn current_process(&mut self) -> Option<String> {
+        if self.supported.is_none() {
+            let supported = self.client.supported();
+            self.supported = Some(supported);
+            info!("application-client: {} (supported: {})", self.name, supported);
+        }
+        if !self.supported.unwrap() {
+            return None;
+        }
+
+        let pid = self.client.current_window_pid()?;
+        let result = process_name(pid);
+        if let Some(process) = &result {
+            if &self.last_process != process {
+                self.last_process = process.clone();
+                info!("process: {}", process);
             }
         }
         result
@@ -55,6 +194,7 @@ impl WMClient {
         }
 
         let result = self.client.current_application();
+        self.check_freshness();
         if let Some(application) = &result {
             if &self.last_application != application {
                 self.last_application = application.clone();
@@ -65,56 +205,64 @@ impl WMClient {
     }
 }
 
-#[cfg(feature = "gnome")]
-mod gnome_client;
-#[cfg(feature = "gnome")]
-pub fn build_client() -> WMClient {
-    WMClient::new("GNOME", Box::new(gnome_client::GnomeClient::new()))
-}
-
-#[cfg(feature = "kde")]
-mod kde_client;
-#[cfg(feature = "kde")]
-pub fn build_client() -> WMClient {
-    WMClient::new("KDE", Box::new(kde_client::KdeClient::new()))
+// The file name of /proc/<pid>/exe, or the command name if the executable isn't readable
+fn process_name(pid: u32) -> Option<String> {
+    match fs::read_link(format!("/proc/{}/exe", pid)) {
+        Ok(exe) => Some(exe.file_name()?.to_string_lossy().into_owned()),
+        Err(_) => Some(
+            fs::read_to_string(format!("/proc/{}/comm", pid))
+                .ok()?
+                .trim_end()
+                .to_string(),
+        ),
+    }
 }
 
+#[cfg(feature = "gnome")]
+mod gnome_client;
 #[cfg(feature = "hypr")]
 mod hypr_client;
-#[cfg(feature = "hypr")]
-pub fn build_client() -> WMClient {
-    WMClient::new("Hypr", Box::new(hypr_client::HyprlandClient::new()))
-}
-
+#[cfg(feature = "kde")]
+mod kde_client;
+mod null_client;
+#[cfg(feature = "wlroots")]
+mod wlroots_client;
 #[cfg(feature = "x11")]
 mod x11_client;
-#[cfg(feature = "x11")]
-pub fn build_client() -> WMClient {
-    WMClient::new("X11", Box::new(x11_client::X11Client::new()))
-}
 
-#[cfg(feature = "wlroots")]
-mod wlroots_client;
-#[cfg(feature = "wlroots")]
+// The client of the feature that xremap is built with, or the first of them if it's built with several
 pub fn build_client() -> WMClient {
-    WMClient::new("wlroots", Box::new(wlroots_client::WlRootsClient::new()))
+    let name = if cfg!(feature = "gnome") {
+        "gnome"
+    } else if cfg!(feature = "kde") {
+        "kde"
+    } else if cfg!(feature = "hypr") {
+        "hypr"
+    } else if cfg!(feature = "x11") {
+        "x11"
+    } else if cfg!(feature = "wlroots") {
+        "wlroots"
+    } else {
+        "none"
+    };
+    build_named_client(name).unwrap()
 }
 
-#[cfg(not(any(
-    feature = "gnome",
-    feature = "x11",
-    feature = "hypr",
-    feature = "kde",
-    feature = "wlroots"
-)))]
-mod null_client;
-#[cfg(not(any(
-    feature = "gnome",
-    feature = "x11",
-    feature = "hypr",
-    feature = "kde",
-    feature = "wlroots"
-)))]
-pub fn build_client() -> WMClient {
-    WMClient::new("none", Box::new(null_client::NullClient))
+// The client given by --client, bypassing the one of the feature. None if xremap isn't built with it.
+// "none" disables the window manager queries, so that the application and the window are always unknown.
+pub fn build_named_client(name: &str) -> Option<WMClient> {
+    match name {
+        #[cfg(feature = "gnome")]
+        "gnome" => Some(WMClient::new("GNOME", Box::new(gnome_client::GnomeClient::new()))),
+        #[cfg(feature = "kde")]
+        "kde" => Some(WMClient::new("KDE", Box::new(kde_client::KdeClient::new()))),
+        #[cfg(feature = "hypr")]
+        "hypr" => Some(WMClient::new("Hypr", Box::new(hypr_client::HyprlandClient::new()))),
+        #[cfg(feature = "x11")]
+        "x11" => Some(WMClient::new("X11", Box::new(x11_client::X11Client::new()))),
+        #[cfg(feature = "wlroots")]
+        "wlroots" => Some(WMClient::new("wlroots", Box::new(wlroots_client::WlRootsClient::new()))),
+        "none" => Some(WMClient::new("none", Box::new(null_client::NullClient))),
+        _ => None,
+    }
 }
@@ -1,7 +1,9 @@
 use crate::client::Client;
 use anyhow::bail;
 use std::env;
+use x11rb::connection::Connection;
 use x11rb::cookie::Cookie;
+use x11rb::protocol::xinput::{self, DeviceId, DeviceType};
 use x11rb::protocol::xproto::{self};
 use x11rb::protocol::xproto::{AtomEnum, Window};
 use x11rb::rust_connection::ConnectionError;
@@ -10,11 +12,42 @@ use x11rb::{protocol::xproto::get_property, rust_connection::RustConnection};
 
 pub struct X11Client {
     connection: Option<RustConnection>,
+    root: Window,
+    focus_source: FocusSource,
+    // The master keyboard whose XInput2 focus is queried, or None if XInput2 isn't available
+    xinput_keyboard: Option<DeviceId>,
+}
+
+// Where the focused window is looked up first, set by $XREMAP_X11_FOCUS
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FocusSource {
+    // _NET_ACTIVE_WINDOW of the window manager, or the keyboard focus if the WM doesn't set it
+    ActiveWindow,
+    // The keyboard focus only
+    Focus,
+}
+
+impl FocusSource {
+    fn from_env() -> FocusSource {
+        match env::var("XREMAP_X11_FOCUS").as_deref() {
+            Err(_) | Ok("active_window") => FocusSource::ActiveWindow,
+            Ok("focus") => FocusSource::Focus,
+            Ok(value) => {
+                eprintln!("warning: Unknown XREMAP_X11_FOCUS={value}. Expected active_window or focus.");
+                FocusSource::ActiveWindow
+            }
+        }
+    }
 }
 
 impl X11Client {
     pub fn new() -> X11Client {
-        X11Client { connection: None }
+        X11Client {
+            connection: None,
+            root: 0,
+            focus_source: FocusSource::from_env(),
+            xinput_keyboard: None,
+        }
     }
 
     fn connect(&mut self) {
@@ -23,7 +56,7 @@ impl X11Client {
         }
 
         if let Err(env::VarError::NotPresent) = env::var("DISPLAY") {
-            println!("$DISPLAY is not set. Defaulting to DISPLAY=:0");
+            eprintln!("$DISPLAY is not set. Defaulting to DISPLAY=:0");
             env::set_var("DISPLAY", ":0");
         }
         self.reconnect();
@@ -31,12 +64,16 @@ impl X11Client {
 
     fn reconnect(&mut self) {
         match x11rb::connect(None) {
-            Ok((connection, _)) => self.connection = Some(connection),
+            Ok((connection, screen)) => {
+                self.root = connection.setup().roots[screen].root;
+                self.xinput_keyboard = get_xinput_keyboard(&connection);
+                self.connection = Some(connection);
+            }
             Err(error) => {
                 let var = env::var("DISPLAY").unwrap();
-                println!("warning: Failed to connect to X11: {error}");
-                println!("If you saw \"No protocol specified\", try running `xhost +SI:localuser:root`.");
-                println!("If not, make sure `echo $DISPLAY` outputs xremap's $DISPLAY ({var}).");
+                eprintln!("warning: Failed to connect to X11: {error}");
+                eprintln!("If you saw \"No protocol specified\", try running `xhost +SI:localuser:root`.");
+                eprintln!("If not, make sure `echo $DISPLAY` outputs xremap's $DISPLAY ({var}).");
             }
         }
     }
@@ -55,7 +92,10 @@ impl Client for X11Client {
 
     fn current_application(&mut self) -> Option<String> {
         self.connect();
-        let mut window = get_focus_window(self)?;
+        let mut window = match self.focus_source {
+            FocusSource::ActiveWindow => get_active_window(self).or_else(|| get_focus_window(self))?,
+            FocusSource::Focus => get_focus_window(self)?,
+        };
         loop {
             if let Some(wm_class) = get_wm_class(self, window) {
                 // Workaround: https://github.com/JetBrains/jdk8u_jdk/blob/master/src/solaris/classes/sun/awt/X11/XFocusProxyWindow.java#L35
@@ -69,12 +109,50 @@ impl Client for X11Client {
     }
 }
 
+// Some window managers don't set _NET_ACTIVE_WINDOW, or set it to None while nothing is active
+fn get_active_window(client: &mut X11Client) -> Option<Window> {
+    let atom = get_cookie_reply_with_reconnect(client, |conn| xproto::intern_atom(conn, true, b"_NET_ACTIVE_WINDOW"))
+        .ok()?
+        .atom;
+    if atom == u32::from(AtomEnum::NONE) {
+        return None;
+    }
+    let root = client.root;
+    let reply =
+        get_cookie_reply_with_reconnect(client, |conn| get_property(conn, false, root, atom, AtomEnum::WINDOW, 0, 1))
+            .ok()?;
+    let window = reply.value32()?.next()?;
+    if window == x11rb::NONE {
+        return None;
+    }
+    Some(window)
+}
+
+// The XInput2 focus of the master keyboard, or the core keyboard focus without XInput2
 fn get_focus_window(client: &mut X11Client) -> Option<Window> {
+    if let Some(keyboard) = client.xinput_keyboard {
+        if let Ok(reply) = get_cookie_reply_with_reconnect(client, |conn| xinput::xi_get_focus(conn, keyboard)) {
+            return Some(reply.focus);
+        }
+    }
     get_cookie_reply_with_reconnect(client, xproto::get_input_focus)
         .map(|reply| reply.focus)
         .ok()
 }
 
+fn get_xinput_keyboard(connection: &RustConnection) -> Option<DeviceId> {
+    xinput::xi_query_version(connection, 2, 0).ok()?.reply().ok()?;
+    let devices = xinput::xi_query_device(connection, xinput::Device::ALL_MASTER)
+        .ok()?
+        .reply()
+        .ok()?;
+    devices
+        .infos
+        .iter()
+        .find(|info| info.type_ == DeviceType::MASTER_KEYBOARD)
+        .map(|info| info.deviceid)
+}
+
 fn get_parent_window(client: &mut X11Client, window: Window) -> Option<Window> {
     get_cookie_reply_with_reconnect(client, |conn| xproto::query_tree(conn, window))
         .map(|reply| reply.parent)
@@ -112,7 +190,7 @@ fn get_cookie_reply_with_reconnect<T: TryParse>(
 ) -> anyhow::Result<T> {
     return match get_cookie_reply(client, &get_cookie) {
         Err(e) => {
-            println!("Reconnecting to X11 due to error: {}", e);
+            eprintln!("Reconnecting to X11 due to error: {}", e);
             client.reconnect();
             get_cookie_reply(client, &get_cookie)
         }
@@ -2,15 +2,23 @@ use std::str::FromStr;
 
 use anyhow::anyhow;
 use regex::Regex;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 // TODO: Use trait to allow only either `only` or `not`
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct OnlyOrNot {
-    #[serde(default, deserialize_with = "deserialize_matchers")]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_matchers",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub only: Option<Vec<ApplicationMatcher>>,
-    #[serde(default, deserialize_with = "deserialize_matchers")]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_matchers",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub not: Option<Vec<ApplicationMatcher>>,
 }
 
@@ -57,6 +65,20 @@ impl FromStr for ApplicationMatcher {
     }
 }
 
+impl Serialize for ApplicationMatcher {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            ApplicationMatcher::Literal(s) | ApplicationMatcher::Name(s) => serializer.serialize_str(s),
+            ApplicationMatcher::Regex(r) => {
+                serializer.collect_str(&format_args!("/{}/", r.as_str().replace('/', "\\/")))
+            }
+        }
+    }
+}
+
 fn slash_unescape(s: &str) -> anyhow::Result<String> {
     let mut result = String::with_capacity(s.len());
     let mut escaping = false;
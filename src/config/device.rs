@@ -1,12 +1,94 @@
 use crate::config::application::deserialize_string_or_vec;
-use serde::Deserialize;
+use crate::event::InputDeviceInfo;
+use evdev::RelativeAxisType;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::str::FromStr;
 
 // TODO: Use trait to allow only either `only` or `not`
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Device {
-    #[serde(default, deserialize_with = "deserialize_string_or_vec")]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_string_or_vec",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub only: Option<Vec<String>>,
-    #[serde(default, deserialize_with = "deserialize_string_or_vec")]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_string_or_vec",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub not: Option<Vec<String>>,
 }
+
+impl Device {
+    pub fn matches(&self, device: &InputDeviceInfo) -> bool {
+        if let Some(device_only) = &self.only {
+            return device_only.iter().any(|m| device.matches(m));
+        }
+        if let Some(device_not) = &self.not {
+            return device_not.iter().all(|m| !device.matches(m));
+        }
+        false
+    }
+}
+
+// Multiply REL_X and REL_Y of the matching devices, e.g. to give mice the same sensitivity
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelScale {
+    pub device: Device,
+    pub scale: f64,
+}
+
+// Flip the sign of relative axes of the matching devices, e.g. to reverse a scroll wheel
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelInvert {
+    pub device: Device,
+    // Codes of the axes, including the hi-res pair of each wheel
+    #[serde(deserialize_with = "deserialize_rel_axes")]
+    pub invert: Vec<u16>,
+}
+
+// Accepts the names of RelativeAxisType with or without the prefix, e.g. wheel or REL_WHEEL
+fn deserialize_rel_axes<'de, D>(deserializer: D) -> Result<Vec<u16>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut codes = vec![];
+    for name in Vec::<String>::deserialize(deserializer)? {
+        let name = name.to_uppercase();
+        let name = if name.starts_with("REL_") {
+            name
+        } else {
+            format!("REL_{}", name)
+        };
+        let axis = RelativeAxisType::from_str(&name)
+            .map_err(|_| de::Error::custom(format!("unknown relative axis: {}", name)))?;
+        codes.push(axis.0);
+        // A hi-res wheel reports both, so they'd go opposite ways otherwise
+        codes.extend(match axis {
+            RelativeAxisType::REL_WHEEL => Some(RelativeAxisType::REL_WHEEL_HI_RES.0),
+            RelativeAxisType::REL_WHEEL_HI_RES => Some(RelativeAxisType::REL_WHEEL.0),
+            RelativeAxisType::REL_HWHEEL => Some(RelativeAxisType::REL_HWHEEL_HI_RES.0),
+            RelativeAxisType::REL_HWHEEL_HI_RES => Some(RelativeAxisType::REL_HWHEEL.0),
+            _ => None,
+        });
+    }
+    Ok(codes)
+}
+
+// What to do with devices that no modmap or keymap applies to because of their `device` filters
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnmatchedDevices {
+    // Grab them and handle their events like any other device
+    #[default]
+    Remap,
+    // Grab them, but send their events without remapping like passthrough_devices
+    Passthrough,
+    // Don't grab them
+    Ignore,
+}
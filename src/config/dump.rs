@@ -0,0 +1,174 @@
+use crate::config::application::OnlyOrNot;
+use crate::config::device::Device;
+use crate::config::duration::format_duration;
+use crate::config::key_press::KeyPress;
+use crate::config::keymap::{Condition, KeymapEntry, KeymapRepeat, KeymapTrigger, OnCooldown};
+use crate::config::keymap_action::KeymapAction;
+use crate::config::remap::Remap;
+use crate::config::Config;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+// The keymaps that xremap resolved from the config files for --dump-config
+#[derive(Serialize)]
+struct DumpedConfig<'a> {
+    keymap: Vec<DumpedKeymap<'a>>,
+}
+
+// An entry of keymap_table, written like a keymap with a single remap
+#[derive(Serialize)]
+struct DumpedKeymap<'a> {
+    remap: BTreeMap<String, Vec<KeymapAction>>,
+    #[serde(flatten)]
+    filters: Filters<'a>,
+}
+
+// Everything but remap. Entries with the same filters are triggered together.
+#[derive(Serialize)]
+struct Filters<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    application: Option<&'a OnlyOrNot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    window: Option<&'a OnlyOrNot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    process: Option<&'a OnlyOrNot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<&'a Device>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<&'a Vec<String>>,
+    exact_match: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    led: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interleave_modifiers: Option<bool>,
+    on: KeymapTrigger,
+    repeat: KeymapRepeat,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repeat_rate_ms: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cooldown_ms: Option<String>,
+    on_cooldown: OnCooldown,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after_timeout_ms: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    held: Option<Vec<String>>,
+}
+
+impl<'a> Filters<'a> {
+    fn new(entry: &'a KeymapEntry) -> Filters<'a> {
+        let mut filters = Filters {
+            description: entry.description.as_ref(),
+            application: None,
+            window: None,
+            process: None,
+            device: None,
+            mode: None,
+            exact_match: entry.exact_match,
+            led: None,
+            interleave_modifiers: entry.interleave_modifiers,
+            on: entry.on,
+            repeat: entry.repeat,
+            repeat_rate_ms: entry.repeat_rate.as_ref().map(format_duration),
+            cooldown_ms: entry.cooldown.as_ref().map(format_duration),
+            on_cooldown: entry.on_cooldown,
+            after: None,
+            after_timeout_ms: None,
+            held: None,
+        };
+        for condition in &entry.conditions {
+            match condition {
+                Condition::Window(window) => filters.window = Some(window),
+                Condition::Application(application) => filters.application = Some(application),
+                Condition::Process(process) => filters.process = Some(process),
+                Condition::Device(device) => filters.device = Some(device),
+                Condition::Mode(modes) => filters.mode = Some(modes),
+                Condition::Led(leds) => filters.led = Some(leds.iter().map(|led| format!("{:?}", led)).collect()),
+                Condition::Held(keys) => filters.held = Some(keys.iter().map(|key| format!("{:?}", key)).collect()),
+                Condition::After(key, timeout) => {
+                    filters.after = Some(format!("{:?}", key));
+                    filters.after_timeout_ms = timeout.as_ref().map(format_duration);
+                }
+            }
+        }
+        filters
+    }
+}
+
+pub fn dump_config(config: &Config) -> Result<String, serde_yaml::Error> {
+    let mut keys: Vec<_> = config.keymap_table.keys().collect();
+    keys.sort_by_key(|key| key.code());
+
+    let mut keymap: Vec<DumpedKeymap> = vec![];
+    for key in keys {
+        // Entries of the same key press and filters, in the order they're looked up
+        let mut groups: Vec<(KeyPress, serde_yaml::Value, Vec<&Vec<KeymapAction>>, Filters)> = vec![];
+        for entry in &config.keymap_table[key] {
+            let key_press = KeyPress {
+                key: *key,
+                modifiers: entry.modifiers.clone(),
+            };
+            let filters = Filters::new(entry);
+            let filters_value = serde_yaml::to_value(&filters)?;
+            match groups.iter_mut().find(|(other_key_press, other_filters, _, _)| {
+                *other_key_press == key_press && *other_filters == filters_value
+            }) {
+                Some((_, _, actions, _)) => actions.push(&entry.actions),
+                None => groups.push((key_press, filters_value, vec![&entry.actions], filters)),
+            }
+        }
+        for (key_press, _, actions, filters) in groups {
+            keymap.push(DumpedKeymap {
+                remap: BTreeMap::from([(key_press.to_string(), merge_actions(&actions))]),
+                filters,
+            });
+        }
+    }
+    serde_yaml::to_string(&DumpedConfig { keymap })
+}
+
+// Like the keymap lookup, the first actions win unless they're remaps, which are merged into one
+fn merge_actions(candidates: &[&Vec<KeymapAction>]) -> Vec<KeymapAction> {
+    if !is_remap(candidates[0]) {
+        return candidates[0].clone();
+    }
+    let remaps: Vec<&Remap> = candidates
+        .iter()
+        .filter(|actions| is_remap(actions))
+        .flat_map(|actions| actions.iter())
+        .filter_map(|action| match action {
+            KeymapAction::Remap(remap) => Some(remap),
+            _ => None,
+        })
+        .collect();
+    vec![KeymapAction::Remap(merge_remaps(&remaps))]
+}
+
+// Nested remaps pushed together, whose timeout and on_unmatched are taken from the first one
+fn merge_remaps(remaps: &[&Remap]) -> Remap {
+    let mut merged: HashMap<KeyPress, Vec<KeymapAction>> = HashMap::new();
+    for key_press in remaps.iter().flat_map(|remap| remap.remap.keys()) {
+        if merged.contains_key(key_press) {
+            continue;
+        }
+        let candidates: Vec<&Vec<KeymapAction>> =
+            remaps.iter().filter_map(|remap| remap.remap.get(key_press)).collect();
+        merged.insert(key_press.clone(), merge_actions(&candidates));
+    }
+    Remap {
+        remap: merged,
+        timeout: remaps[0].timeout,
+        timeout_key: remaps[0].timeout_key,
+        replay: remaps[0].replay,
+        on_timeout: remaps[0].on_timeout.clone(),
+        on_unmatched: remaps[0].on_unmatched,
+        literal_timeout: remaps[0].literal_timeout,
+    }
+}
+
+fn is_remap(actions: &[KeymapAction]) -> bool {
+    !actions.is_empty() && actions.iter().all(|action| matches!(action, KeymapAction::Remap(_)))
+}
@@ -0,0 +1,118 @@
+use log::warn;
+use serde::de::{self, Deserializer, Visitor};
+use serde_with::DeserializeAs;
+use std::fmt;
+use std::time::Duration;
+
+// Use `#[serde_as(as = "Millis")]` for a Duration written like "100ms", "1s", "1.5s", or "1m".
+// A number without a unit is read as milliseconds for compatibility, with a warning.
+pub struct Millis;
+
+impl<'de> DeserializeAs<'de, Duration> for Millis {
+    fn deserialize_as<D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(MillisVisitor)
+    }
+}
+
+struct MillisVisitor;
+
+impl<'de> Visitor<'de> for MillisVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a duration like 100ms or 1s")
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Duration, E>
+    where
+        E: de::Error,
+    {
+        warn!(
+            "A duration without a unit is read as milliseconds. Write it like \"{}ms\" instead of {}",
+            value, value
+        );
+        Ok(Duration::from_millis(value))
+    }
+
+    // TOML integers are signed
+    fn visit_i64<E>(self, value: i64) -> Result<Duration, E>
+    where
+        E: de::Error,
+    {
+        match u64::try_from(value) {
+            Ok(value) => self.visit_u64(value),
+            Err(_) => Err(E::custom(format!("a duration must not be negative: {}", value))),
+        }
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Duration, E>
+    where
+        E: de::Error,
+    {
+        parse_duration(value).map_err(E::custom)
+    }
+}
+
+// The inverse of parse_duration, e.g. "100ms" or "1.5ms"
+pub fn format_duration(duration: &Duration) -> String {
+    if duration.subsec_nanos().is_multiple_of(1_000_000) {
+        format!("{}ms", duration.as_millis())
+    } else {
+        format!("{}ms", duration.as_secs_f64() * 1_000.0)
+    }
+}
+
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let (number, millis_per_unit) = if let Some(number) = input.strip_suffix("ms") {
+        (number, 1)
+    } else if let Some(number) = input.strip_suffix('s') {
+        (number, 1_000)
+    } else if let Some(number) = input.strip_suffix('m') {
+        (number, 60_000)
+    } else {
+        return Err(format!("a duration must end with ms, s, or m: '{}'", input));
+    };
+    let number = number.trim();
+    // Integers are converted exactly, and fractions like 1.5s to the nearest nanosecond
+    if let Ok(number) = number.parse::<u64>() {
+        return match number.checked_mul(millis_per_unit) {
+            Some(millis) => Ok(Duration::from_millis(millis)),
+            None => Err(format!("invalid duration: '{}'", input)),
+        };
+    }
+    match number.parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => {
+            Ok(Duration::from_nanos((number * millis_per_unit as f64 * 1_000_000.0).round() as u64))
+        }
+        _ => Err(format!("invalid duration: '{}'", input)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_duration, parse_duration};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("100ms"), Ok(Duration::from_millis(100)));
+        assert_eq!(parse_duration("1s"), Ok(Duration::from_secs(1)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2 m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("0.5ms"), Ok(Duration::from_micros(500)));
+        assert!(parse_duration("100").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("99999999999999999m").is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        for duration in [Duration::from_millis(1500), Duration::from_micros(500), Duration::ZERO] {
+            assert_eq!(parse_duration(&format_duration(&duration)), Ok(duration));
+        }
+    }
+}
@@ -1,9 +1,105 @@
-use crate::event_handler::{DISGUISED_EVENT_OFFSETTER, KEY_MATCH_ANY};
+use crate::event_handler::{DISGUISED_EVENT_OFFSETTER, KEY_MATCH_ANY, KEY_MATCH_ANY_INCLUDING_MODIFIERS, KEY_SELF};
 use evdev::Key;
 use serde::{Deserialize, Deserializer};
+use serde_yaml::Value;
+use std::collections::HashMap;
 use std::error::Error;
 use std::str::FromStr;
 
+// The relative pseudo-keys that rel_aliases can name, in the order of their scancodes
+static RELATIVE_KEYS: [&str; 26] = [
+    "XRIGHTCURSOR",
+    "XLEFTCURSOR",
+    "XDOWNCURSOR",
+    "XUPCURSOR",
+    "XREL_Z_AXIS_1",
+    "XREL_Z_AXIS_2",
+    "XREL_RX_AXIS_1",
+    "XREL_RX_AXIS_2",
+    "XREL_RY_AXIS_1",
+    "XREL_RY_AXIS_2",
+    "XREL_RZ_AXIS_1",
+    "XREL_RZ_AXIS_2",
+    "XRIGHTSCROLL",
+    "XLEFTSCROLL",
+    "XREL_DIAL_1",
+    "XREL_DIAL_2",
+    "XUPSCROLL",
+    "XDOWNSCROLL",
+    "XREL_MISC_1",
+    "XREL_MISC_2",
+    "XREL_RESERVED_1",
+    "XREL_RESERVED_2",
+    "XHIRES_UPSCROLL",
+    "XHIRES_DOWNSCROLL",
+    "XHIRES_RIGHTSCROLL",
+    "XHIRES_LEFTSCROLL",
+];
+
+// Fields of modmap and keymap whose strings are never keys, e.g. `name` or `launch`
+static NON_KEY_FIELDS: [&str; 11] = [
+    "name",
+    "description",
+    "application",
+    "window",
+    "device",
+    "mode",
+    "set_mode",
+    "launch",
+    "run",
+    "command",
+    "if",
+];
+
+// Check that `name` can be used in place of a relative pseudo-key like XUPSCROLL
+pub fn check_rel_alias(name: &str, target: &str) -> Result<(), Box<dyn Error>> {
+    if !RELATIVE_KEYS.contains(&target.to_uppercase().as_str()) {
+        return Err(format!(
+            "rel_aliases: '{}' is not a relative pseudo-key. Use one of {}",
+            target,
+            RELATIVE_KEYS.join(", ")
+        )
+        .into());
+    }
+    if parse_key(name).is_ok() {
+        return Err(format!("rel_aliases: '{}' is already a key", name).into());
+    }
+    Ok(())
+}
+
+// Replace the rel_aliases, by upper-case names, in the keys and key presses of a modmap or keymap section
+pub fn resolve_rel_aliases(value: &mut Value, aliases: &HashMap<String, String>) {
+    match value {
+        Value::String(key_press) => {
+            // Only the last part of a key press like C-scroll_up is a key
+            let (modifiers, key) = key_press.rsplit_once('-').unwrap_or(("", key_press));
+            if let Some(target) = aliases.get(&key.to_uppercase()) {
+                *key_press = if modifiers.is_empty() {
+                    target.clone()
+                } else {
+                    format!("{}-{}", modifiers, target)
+                };
+            }
+        }
+        Value::Sequence(values) => {
+            for value in values {
+                resolve_rel_aliases(value, aliases);
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (mut field, mut value) in std::mem::take(mapping) {
+                if !field.as_str().is_some_and(|field| NON_KEY_FIELDS.contains(&field)) {
+                    resolve_rel_aliases(&mut field, aliases);
+                    resolve_rel_aliases(&mut value, aliases);
+                }
+                mapping.insert(field, value);
+            }
+        }
+        Value::Tagged(tagged) => resolve_rel_aliases(&mut tagged.value, aliases),
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
 pub fn deserialize_key<'de, D>(deserializer: D) -> Result<Key, D::Error>
 where
     D: Deserializer<'de>,
@@ -54,6 +150,23 @@ pub fn parse_key(input: &str) -> Result<Key, Box<dyn Error>> {
         "WIN_L" => Key::KEY_LEFTMETA,
         "W_R" => Key::KEY_RIGHTMETA,
         "W_L" => Key::KEY_LEFTMETA,
+        // Media. Names like VOLUMEUP, BRIGHTNESSDOWN, and PLAYPAUSE already work without "KEY_".
+        "VOLUME_UP" => Key::KEY_VOLUMEUP,
+        "VOLUME_DOWN" => Key::KEY_VOLUMEDOWN,
+        "VOLUME_MUTE" => Key::KEY_MUTE,
+        "MIC_MUTE" => Key::KEY_MICMUTE,
+        "PLAY_PAUSE" => Key::KEY_PLAYPAUSE,
+        "MEDIA_STOP" => Key::KEY_STOPCD,
+        "NEXT_TRACK" => Key::KEY_NEXTSONG,
+        "PREV_TRACK" => Key::KEY_PREVIOUSSONG,
+        "PREVIOUS_TRACK" => Key::KEY_PREVIOUSSONG,
+        "PREVSONG" => Key::KEY_PREVIOUSSONG,
+        // Brightness
+        "BRIGHTNESS_UP" => Key::KEY_BRIGHTNESSUP,
+        "BRIGHTNESS_DOWN" => Key::KEY_BRIGHTNESSDOWN,
+        "KBD_BRIGHTNESS_UP" => Key::KEY_KBDILLUMUP,
+        "KBD_BRIGHTNESS_DOWN" => Key::KEY_KBDILLUMDOWN,
+        "KBD_BRIGHTNESS_TOGGLE" => Key::KEY_KBDILLUMTOGGLE,
 
         // Custom aliases used in config files to represent scancodes for disguised relative events.
         // Relative events are disguised into key events with those scancodes,
@@ -66,11 +179,10 @@ pub fn parse_key(input: &str) -> Result<Key, Box<dyn Error>> {
         //
         // The DISGUISED_EVENT_OFFSETTER const is used here to make it easy to change the scancodes should it ever be necessary.
         // Because configs use name and custom aliases, changing their assigned value doesn't change how to write configs;
-        // In other words, a config that works when DISGUISED_EVENT_OFFSETTER == 59974
-        // will work exactly the same way if DISGUISED_EVENT_OFFSETTER == 46221
+        // In other words, a config works the same way whatever DISGUISED_EVENT_OFFSETTER is.
         //
-        // DISGUISED_EVENT_OFFSETTER is also used in tests.rs::verify_disguised_relative_events(),
-        // to prevent its modification to a number too low or too big.
+        // DISGUISED_EVENT_OFFSETTER is derived from DISGUISED_EVENT_AXES, and it's checked at compile time
+        // that it's above KEY_MAX, so that the scancodes never collide with real ones.
         //
         // Cursor movement
         "XRIGHTCURSOR" => Key(DISGUISED_EVENT_OFFSETTER), // Cursor right
@@ -126,6 +238,8 @@ pub fn parse_key(input: &str) -> Result<Key, Box<dyn Error>> {
             REL_HWHEEL_HI_RES = 0x0c,
         */
         "ANY" => KEY_MATCH_ANY,
+        "ANY_INCLUDING_MODIFIERS" => KEY_MATCH_ANY_INCLUDING_MODIFIERS,
+        "<SELF>" => KEY_SELF,
         // End of custom scancodes
 
         // else
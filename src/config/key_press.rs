@@ -1,7 +1,8 @@
 use crate::config::key::parse_key;
 use evdev::Key;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::error::{self, Error};
+use std::fmt;
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct KeyPress {
@@ -18,6 +19,8 @@ pub enum Modifier {
     Windows,
     // Matches exactly this key
     Key(Key),
+    // `*`: Matches whatever modifiers are pressed, even with exact_match
+    Any,
 }
 
 impl<'de> Deserialize<'de> for KeyPress {
@@ -30,6 +33,32 @@ impl<'de> Deserialize<'de> for KeyPress {
     }
 }
 
+// Written in the config syntax, e.g. "C-KEY_X"
+impl fmt::Display for KeyPress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for modifier in &self.modifiers {
+            match modifier {
+                Modifier::Shift => write!(f, "Shift-")?,
+                Modifier::Control => write!(f, "C-")?,
+                Modifier::Alt => write!(f, "M-")?,
+                Modifier::Windows => write!(f, "Super-")?,
+                Modifier::Key(key) => write!(f, "{:?}-", key)?,
+                Modifier::Any => write!(f, "*-")?,
+            }
+        }
+        write!(f, "{:?}", self.key)
+    }
+}
+
+impl Serialize for KeyPress {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
 fn parse_key_press(input: &str) -> Result<KeyPress, Box<dyn error::Error>> {
     let keys: Vec<&str> = input.split('-').collect();
     if let Some((key, modifier_keys)) = keys.split_last() {
@@ -50,6 +79,21 @@ fn parse_key_press(input: &str) -> Result<KeyPress, Box<dyn error::Error>> {
     }
 }
 
+// A key, or a modifier name like "ctrl" for its left key
+pub fn parse_modifier_key(input: &str) -> Result<Key, Box<dyn Error>> {
+    if let Ok(key) = parse_key(input) {
+        return Ok(key);
+    }
+    match parse_modifier(input)? {
+        Modifier::Shift => Ok(Key::KEY_LEFTSHIFT),
+        Modifier::Control => Ok(Key::KEY_LEFTCTRL),
+        Modifier::Alt => Ok(Key::KEY_LEFTALT),
+        Modifier::Windows => Ok(Key::KEY_LEFTMETA),
+        Modifier::Key(key) => Ok(key),
+        Modifier::Any => Err(format!("not a key: {}", input).into()),
+    }
+}
+
 fn parse_modifier(modifier: &str) -> Result<Modifier, Box<dyn Error>> {
     // Everything is case-insensitive
     match &modifier.to_uppercase()[..] {
@@ -69,6 +113,20 @@ fn parse_modifier(modifier: &str) -> Result<Modifier, Box<dyn Error>> {
         "W" => Ok(Modifier::Windows),
         "WIN" => Ok(Modifier::Windows),
         "WINDOWS" => Ok(Modifier::Windows),
+        // One side of a modifier, which is also pressed on that side in actions
+        "LS" => Ok(Modifier::Key(Key::KEY_LEFTSHIFT)),
+        "RS" => Ok(Modifier::Key(Key::KEY_RIGHTSHIFT)),
+        "LC" => Ok(Modifier::Key(Key::KEY_LEFTCTRL)),
+        "RC" => Ok(Modifier::Key(Key::KEY_RIGHTCTRL)),
+        "LA" => Ok(Modifier::Key(Key::KEY_LEFTALT)),
+        "RA" => Ok(Modifier::Key(Key::KEY_RIGHTALT)),
+        // Unlike "M", which is Alt, these are the Meta keys of evdev like "W"
+        "LM" => Ok(Modifier::Key(Key::KEY_LEFTMETA)),
+        "RM" => Ok(Modifier::Key(Key::KEY_RIGHTMETA)),
+        "LW" => Ok(Modifier::Key(Key::KEY_LEFTMETA)),
+        "RW" => Ok(Modifier::Key(Key::KEY_RIGHTMETA)),
+        // Any
+        "*" => Ok(Modifier::Any),
         // else
         key => parse_key(key).map(|key| Modifier::Key(key)),
     }
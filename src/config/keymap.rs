@@ -1,53 +1,301 @@
 use crate::config::application::deserialize_string_or_vec;
 use crate::config::application::OnlyOrNot;
+use crate::config::key::{deserialize_key, parse_key};
 use crate::config::key_press::KeyPress;
 use crate::config::keymap_action::{Actions, KeymapAction};
-use evdev::Key;
-use serde::{Deserialize, Deserializer};
+use crate::config::led::deserialize_leds;
+use evdev::{Key, LedType};
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_with::serde_as;
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
 
 use super::device::Device;
+use super::duration::Millis;
 use super::key_press::Modifier;
+use super::remap::Remap;
 
 // Config interface
+#[serde_as]
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Keymap {
-    #[allow(dead_code)]
     #[serde(default = "String::new")]
     pub name: String,
+    // Logged and traced when a remap of this keymap matches
+    pub description: Option<String>,
     #[serde(deserialize_with = "deserialize_remap")]
-    pub remap: HashMap<KeyPress, Vec<KeymapAction>>,
+    pub remap: HashMap<KeyPress, Binding>,
     pub application: Option<OnlyOrNot>,
     pub window: Option<OnlyOrNot>,
+    // Executable name of the active window's process, or its application if the client doesn't know it
+    pub process: Option<OnlyOrNot>,
     pub device: Option<Device>,
     #[serde(default, deserialize_with = "deserialize_string_or_vec")]
     pub mode: Option<Vec<String>>,
+    // Falls back to default_exact_match
     #[serde(default)]
-    pub exact_match: bool,
+    pub exact_match: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_leds")]
+    pub led: Option<Vec<LedType>>,
+    // Falls back to interleave_modifiers of the config
+    #[serde(default)]
+    pub interleave_modifiers: Option<bool>,
+    #[serde(default)]
+    pub on: KeymapTrigger,
+    #[serde(default)]
+    pub repeat: KeymapRepeat,
+    // Emit the actions again at this interval while the key is held, instead of on the kernel's autorepeat
+    #[serde_as(as = "Option<Millis>")]
+    #[serde(default, rename = "repeat_rate_ms")]
+    pub repeat_rate: Option<Duration>,
+    // Don't trigger each remap again within this duration
+    #[serde_as(as = "Option<Millis>")]
+    #[serde(default, rename = "cooldown_ms")]
+    pub cooldown: Option<Duration>,
+    #[serde(default)]
+    pub on_cooldown: OnCooldown,
+    // Match only if this key was pressed right before
+    #[serde(default, deserialize_with = "deserialize_after")]
+    pub after: Option<Key>,
+    #[serde_as(as = "Option<Millis>")]
+    #[serde(default, rename = "after_timeout_ms")]
+    pub after_timeout: Option<Duration>,
+    // Match only while all of these modifiers or virtual_modifiers are held
+    #[serde(default, deserialize_with = "deserialize_held")]
+    pub held: Option<Vec<Key>>,
+    // Keymaps with a higher priority are matched first, and ones with the same priority in the order of the config
+    #[serde(default)]
+    pub priority: i32,
+}
+
+impl Keymap {
+    // The filters of a remap, in the order they're evaluated. The binding's device replaces the keymap's.
+    fn conditions(&self, binding: &Binding) -> Vec<Condition> {
+        let mut conditions = vec![];
+        if let Some(window) = &self.window {
+            conditions.push(Condition::Window(window.clone()));
+        }
+        if let Some(application) = &self.application {
+            conditions.push(Condition::Application(application.clone()));
+        }
+        if let Some(process) = &self.process {
+            conditions.push(Condition::Process(process.clone()));
+        }
+        if let Some(device) = binding.device.as_ref().or(self.device.as_ref()) {
+            conditions.push(Condition::Device(device.clone()));
+        }
+        if let Some(modes) = &self.mode {
+            conditions.push(Condition::Mode(modes.clone()));
+        }
+        if let Some(leds) = &self.led {
+            conditions.push(Condition::Led(leds.clone()));
+        }
+        if let Some(keys) = &self.held {
+            conditions.push(Condition::Held(keys.clone()));
+        }
+        if let Some(after) = self.after {
+            conditions.push(Condition::After(after, self.after_timeout));
+        }
+        conditions
+    }
+}
+
+// A filter of a keymap entry, which matches only if all of its conditions do
+#[derive(Clone, Debug)]
+pub enum Condition {
+    // Title of the active window
+    Window(OnlyOrNot),
+    Application(OnlyOrNot),
+    Process(OnlyOrNot),
+    // Device that the key event comes from
+    Device(Device),
+    Mode(Vec<String>),
+    Led(Vec<LedType>),
+    Held(Vec<Key>),
+    // The key pressed right before, within the timeout if any
+    After(Key, Option<Duration>),
+}
+
+// Key event that triggers the actions of a keymap
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeymapTrigger {
+    #[default]
+    Press,
+    // Matched on press, but the actions are dispatched on release
+    Release,
+}
+
+// Whether autorepeat of a key matches a keymap. Others fall through to the next keymap.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeymapRepeat {
+    // Both the press and its repeats
+    #[default]
+    Include,
+    // Only the press
+    Ignore,
+    // Only the repeats
+    Only,
+}
+
+// What a key does when its remap is in the cooldown
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnCooldown {
+    #[default]
+    Swallow,
+    // Emit the key as if it weren't remapped
+    Passthrough,
+}
+
+// Which of the nested remaps of the same key press and filters wins a key they both map
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    // The one of the earlier keymap
+    #[default]
+    First,
+    // The one of the later keymap
+    Last,
+    // Fail to load the config
+    Error,
+}
+
+// A value of `remap`
+#[derive(Debug)]
+pub struct Binding {
+    pub actions: Vec<KeymapAction>,
+    // Replaces the device filter of the keymap
+    pub device: Option<Device>,
+}
+
+// `actions`, or `{ device: ..., actions: ... }` to remap the key of the devices only
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BindingConfig {
+    #[serde(deserialize_with = "deserialize_device_binding")]
+    Device(DeviceBinding),
+    Actions(Actions),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeviceBinding {
+    device: DeviceConfig,
+    actions: Actions,
+}
+
+// `{ only: ..., not: ... }`, or device names for `only`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DeviceConfig {
+    Device(Device),
+    Name(String),
+    Names(Vec<String>),
+}
+
+impl From<DeviceConfig> for Device {
+    fn from(device: DeviceConfig) -> Device {
+        match device {
+            DeviceConfig::Device(device) => device,
+            DeviceConfig::Name(name) => Device {
+                only: Some(vec![name]),
+                not: None,
+            },
+            DeviceConfig::Names(names) => Device {
+                only: Some(names),
+                not: None,
+            },
+        }
+    }
+}
+
+// Only a map, since a struct could also be deserialized from an array of actions
+fn deserialize_device_binding<'de, D>(deserializer: D) -> Result<DeviceBinding, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct DeviceBindingVisitor;
+
+    impl<'de> Visitor<'de> for DeviceBindingVisitor {
+        type Value = DeviceBinding;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map with \"device\" and \"actions\"")
+        }
+
+        fn visit_map<M>(self, map: M) -> Result<Self::Value, M::Error>
+        where
+            M: MapAccess<'de>,
+        {
+            DeviceBinding::deserialize(de::value::MapAccessDeserializer::new(map))
+        }
+    }
+
+    deserializer.deserialize_map(DeviceBindingVisitor)
 }
 
-fn deserialize_remap<'de, D>(deserializer: D) -> Result<HashMap<KeyPress, Vec<KeymapAction>>, D::Error>
+fn deserialize_remap<'de, D>(deserializer: D) -> Result<HashMap<KeyPress, Binding>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let remap = HashMap::<KeyPress, Actions>::deserialize(deserializer)?;
+    let remap = HashMap::<KeyPress, BindingConfig>::deserialize(deserializer)?;
     Ok(remap
         .into_iter()
-        .map(|(key_press, actions)| (key_press, actions.into_vec()))
+        .map(|(key_press, binding)| {
+            let binding = match binding {
+                BindingConfig::Device(binding) => Binding {
+                    actions: binding.actions.into_vec(),
+                    device: Some(binding.device.into()),
+                },
+                BindingConfig::Actions(actions) => Binding {
+                    actions: actions.into_vec(),
+                    device: None,
+                },
+            };
+            (key_press, binding)
+        })
         .collect())
 }
 
+fn deserialize_after<'de, D>(deserializer: D) -> Result<Option<Key>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_key(deserializer).map(Some)
+}
+
+fn deserialize_held<'de, D>(deserializer: D) -> Result<Option<Vec<Key>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let keys = Vec::<String>::deserialize(deserializer)?;
+    keys.iter()
+        .map(|key| parse_key(key).map_err(de::Error::custom))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
 // Internals for efficient keymap lookup
 #[derive(Clone, Debug)]
 pub struct KeymapEntry {
+    pub name: String,
+    pub description: Option<String>,
     pub actions: Vec<KeymapAction>,
     pub modifiers: Vec<Modifier>,
-    pub application: Option<OnlyOrNot>,
-    pub title: Option<OnlyOrNot>,
-    pub device: Option<Device>,
-    pub mode: Option<Vec<String>>,
+    pub conditions: Vec<Condition>,
     pub exact_match: bool,
+    pub interleave_modifiers: Option<bool>,
+    pub on: KeymapTrigger,
+    pub repeat: KeymapRepeat,
+    pub repeat_rate: Option<Duration>,
+    pub cooldown: Option<Duration>,
+    pub on_cooldown: OnCooldown,
 }
 
 // Convert an array of keymaps to a single hashmap whose key is a triggering key.
@@ -56,29 +304,167 @@ pub struct KeymapEntry {
 // and then it's scanned again, allowing extra modifiers.
 //
 // First matching KeymapEntry wins at each iteration.
-pub fn build_keymap_table(keymaps: &Vec<Keymap>) -> HashMap<Key, Vec<KeymapEntry>> {
+pub fn build_keymap_table(
+    keymaps: &[Keymap],
+    default_exact_match: bool,
+    merge_strategy: MergeStrategy,
+) -> HashMap<Key, Vec<KeymapEntry>> {
     let mut table: HashMap<Key, Vec<KeymapEntry>> = HashMap::new();
+    // A stable sort keeps the order of the config for the same priority
+    let mut keymaps: Vec<&Keymap> = keymaps.iter().collect();
+    keymaps.sort_by_key(|keymap| Reverse(keymap.priority));
     for keymap in keymaps {
-        for (key_press, actions) in keymap.remap.iter() {
+        for (key_press, binding) in keymap.remap.iter() {
             let mut entries: Vec<KeymapEntry> = match table.get(&key_press.key) {
                 Some(entries) => entries.to_vec(),
                 None => vec![],
             };
-            entries.push(KeymapEntry {
-                actions: actions.to_vec(),
+            let entry = KeymapEntry {
+                name: keymap.name.clone(),
+                description: keymap.description.clone(),
+                actions: binding.actions.to_vec(),
                 modifiers: key_press.modifiers.clone(),
-                application: keymap.application.clone(),
-                title: keymap.window.clone(),
-                device: keymap.device.clone(),
-                mode: keymap.mode.clone(),
-                exact_match: keymap.exact_match,
-            });
+                conditions: keymap.conditions(binding),
+                exact_match: keymap.exact_match.unwrap_or(default_exact_match),
+                interleave_modifiers: keymap.interleave_modifiers,
+                on: keymap.on,
+                repeat: keymap.repeat,
+                repeat_rate: keymap.repeat_rate,
+                cooldown: keymap.cooldown,
+                on_cooldown: keymap.on_cooldown,
+            };
+            // Nested remaps are merged in the order of the entries, so a later one goes first to win
+            let position = match merge_strategy {
+                MergeStrategy::Last if is_remap(&entry.actions) => entries
+                    .iter()
+                    .position(|other| is_remap(&other.actions) && other.same_trigger(&entry)),
+                _ => None,
+            };
+            entries.insert(position.unwrap_or(entries.len()), entry);
             table.insert(key_press.key, entries);
         }
     }
     return table;
 }
 
+// Remaps that never match because an earlier entry of the same key press, filters, and trigger always does.
+// Nested remaps aren't conflicts with each other since they're merged.
+pub fn find_conflicts(table: &HashMap<Key, Vec<KeymapEntry>>) -> Vec<String> {
+    let mut keys: Vec<&Key> = table.keys().collect();
+    keys.sort_by_key(|key| key.code());
+
+    let mut conflicts = vec![];
+    for key in keys {
+        let entries = &table[key];
+        for (index, entry) in entries.iter().enumerate() {
+            let shadowing = entries[..index]
+                .iter()
+                .find(|other| other.same_trigger(entry) && !(is_remap(&other.actions) && is_remap(&entry.actions)));
+            if let Some(other) = shadowing {
+                let key_press = KeyPress {
+                    key: *key,
+                    modifiers: entry.modifiers.clone(),
+                };
+                conflicts.push(format!(
+                    "{} of {} is never used since {} remaps it first",
+                    key_press,
+                    entry.keymap_label(),
+                    other.keymap_label()
+                ));
+            }
+        }
+    }
+    conflicts
+}
+
+// Keys mapped by more than one of the nested remaps merged for the same key press and filters,
+// which merge_strategy: error rejects. Keys that are nested remaps in all of them are merged further.
+pub fn find_merge_conflicts(table: &HashMap<Key, Vec<KeymapEntry>>) -> Vec<String> {
+    let mut keys: Vec<&Key> = table.keys().collect();
+    keys.sort_by_key(|key| key.code());
+
+    let mut conflicts = vec![];
+    for key in keys {
+        let entries = &table[key];
+        for (index, entry) in entries.iter().enumerate() {
+            if !is_remap(&entry.actions) {
+                continue;
+            }
+            for other in entries[..index]
+                .iter()
+                .filter(|other| is_remap(&other.actions) && other.same_trigger(entry))
+            {
+                let key_press = KeyPress {
+                    key: *key,
+                    modifiers: entry.modifiers.clone(),
+                };
+                for path in overlapping_keys(&other.actions, &entry.actions) {
+                    conflicts.push(format!(
+                        "{} {} is remapped by both {} and {}",
+                        key_press,
+                        path,
+                        other.keymap_label(),
+                        entry.keymap_label()
+                    ));
+                }
+            }
+        }
+    }
+    conflicts
+}
+
+// Key presses, or sequences of them, that both nested remaps map to something other than another nested remap
+fn overlapping_keys(first: &[KeymapAction], second: &[KeymapAction]) -> Vec<String> {
+    let (first, second) = (nested_remaps(first), nested_remaps(second));
+    let mut overlaps = vec![];
+    for (key_press, first_actions) in first.iter().flat_map(|remap| remap.remap.iter()) {
+        for second_actions in second.iter().filter_map(|remap| remap.remap.get(key_press)) {
+            if is_remap(first_actions) && is_remap(second_actions) {
+                for path in overlapping_keys(first_actions, second_actions) {
+                    overlaps.push(format!("{} {}", key_press, path));
+                }
+            } else {
+                overlaps.push(key_press.to_string());
+            }
+        }
+    }
+    overlaps.sort();
+    overlaps
+}
+
+fn nested_remaps(actions: &[KeymapAction]) -> Vec<&Remap> {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            KeymapAction::Remap(remap) => Some(remap),
+            _ => None,
+        })
+        .collect()
+}
+
+fn is_remap(actions: &[KeymapAction]) -> bool {
+    !actions.is_empty() && actions.iter().all(|action| matches!(action, KeymapAction::Remap(_)))
+}
+
+impl KeymapEntry {
+    // Whether both are looked up for the same key press with the same filters and trigger
+    fn same_trigger(&self, other: &KeymapEntry) -> bool {
+        self.modifiers.len() == other.modifiers.len()
+            && self.modifiers.iter().all(|modifier| other.modifiers.contains(modifier))
+            && format!("{:?}", self.conditions) == format!("{:?}", other.conditions)
+            && (self.on, self.repeat, self.exact_match) == (other.on, other.repeat, other.exact_match)
+    }
+
+    // How a warning refers to the keymap of the entry
+    fn keymap_label(&self) -> String {
+        match (&self.name, &self.description) {
+            (name, _) if !name.is_empty() => format!("keymap '{}'", name),
+            (_, Some(description)) => format!("keymap '{}'", description),
+            _ => "an unnamed keymap".to_string(),
+        }
+    }
+}
+
 // Subset of KeymapEntry for override_remap
 #[derive(Clone)]
 pub struct OverrideEntry {
@@ -1,14 +1,22 @@
-use crate::config::key_press::KeyPress;
-use std::collections::HashMap;
+use crate::config::key_press::{parse_modifier_key, KeyPress};
+use crate::event::KEY_MAX;
+use crate::event_handler::{KEY_SELF, PRESS, RELEASE};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::config::remap::Remap;
-use evdev::Key;
+use evdev::{Key, LedType};
 use serde::de;
-use serde::{Deserialize, Deserializer};
-use std::fmt::Debug;
+use serde::ser::{self, SerializeMap};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_with::serde_as;
+use std::error::Error;
+use std::fmt::{self, Debug};
 use std::time::Duration;
 
+use super::duration::{format_duration, Millis};
 use super::key::parse_key;
+use super::led::{deserialize_led, serialize_led};
 use super::remap::RemapActions;
 
 // Values in `keymap.remap`
@@ -22,7 +30,9 @@ pub enum KeymapAction {
     #[serde(deserialize_with = "deserialize_launch")]
     Launch(Vec<String>),
     #[serde(deserialize_with = "deserialize_set_mode")]
-    SetMode(String),
+    SetMode(SetMode),
+    #[serde(deserialize_with = "deserialize_clear_mode")]
+    ClearMode(bool),
     #[serde(deserialize_with = "deserialize_set_mark")]
     SetMark(bool),
     #[serde(deserialize_with = "deserialize_with_mark")]
@@ -31,12 +41,158 @@ pub enum KeymapAction {
     EscapeNextKey(bool),
     #[serde(deserialize_with = "deserialize_sleep")]
     Sleep(u64),
+    #[serde(deserialize_with = "deserialize_keys_raw")]
+    KeysRaw(Vec<(Key, i32)>),
+    #[serde(deserialize_with = "deserialize_press")]
+    Press(Key),
+    #[serde(deserialize_with = "deserialize_release")]
+    Release(Key),
+    #[serde(deserialize_with = "deserialize_repeat")]
+    Repeat(Repeat),
+    #[serde(deserialize_with = "deserialize_set_grab")]
+    SetGrab(bool),
+    #[serde(deserialize_with = "deserialize_set_led")]
+    SetLed(SetLed),
+    #[serde(deserialize_with = "deserialize_mouse_move")]
+    MouseMove(MouseMove),
+    #[serde(deserialize_with = "deserialize_move_relative")]
+    MoveRelative(MouseMove),
+    #[serde(deserialize_with = "deserialize_raw_code")]
+    RawCode(u16),
+    #[serde(deserialize_with = "deserialize_run")]
+    Run(Run),
+    #[serde(deserialize_with = "deserialize_scroll")]
+    Scroll(Scroll),
+    #[serde(deserialize_with = "deserialize_chord")]
+    Chord(Vec<Key>),
+    #[serde(deserialize_with = "deserialize_compose")]
+    Compose(Vec<KeyPress>),
+    #[serde(deserialize_with = "deserialize_cycle")]
+    Cycle(Cycle),
+    #[serde(deserialize_with = "deserialize_hold_while_pressed")]
+    HoldWhilePressed(Key),
+    #[serde(deserialize_with = "deserialize_conditional")]
+    If(Conditional),
+    #[serde(deserialize_with = "deserialize_await_title_change")]
+    AwaitTitleChange(AwaitTitleChange),
+    #[serde(deserialize_with = "deserialize_on_device")]
+    OnDevice(OnDevice),
+    #[serde(deserialize_with = "deserialize_layer")]
+    Layer(Layer),
 
     // Internals
     #[serde(skip)]
     SetExtraModifiers(Vec<Key>),
 }
 
+// Whether `<self>` is sent by the actions, including nested ones
+pub fn uses_self_key(actions: &[KeymapAction]) -> bool {
+    actions.iter().any(|action| match action {
+        KeymapAction::KeyPress(key_press) | KeymapAction::WithMark(key_press) => key_press.key == KEY_SELF,
+        KeymapAction::Repeat(repeat) => repeat.key.key == KEY_SELF,
+        KeymapAction::AwaitTitleChange(await_title_change) => await_title_change.key.key == KEY_SELF,
+        KeymapAction::Compose(key_presses) => key_presses.iter().any(|key_press| key_press.key == KEY_SELF),
+        KeymapAction::Press(key) | KeymapAction::Release(key) | KeymapAction::HoldWhilePressed(key) => *key == KEY_SELF,
+        KeymapAction::Chord(keys) => keys.contains(&KEY_SELF),
+        KeymapAction::KeysRaw(keys) => keys.iter().any(|(key, _)| *key == KEY_SELF),
+        KeymapAction::Remap(remap) => {
+            remap.remap.values().any(|actions| uses_self_key(actions))
+                || uses_self_key(&remap.on_timeout)
+                || remap.timeout_key == Some(KEY_SELF)
+        }
+        KeymapAction::Layer(layer) => layer.remap.values().any(|actions| uses_self_key(actions)),
+        KeymapAction::Cycle(cycle) => cycle.actions.iter().any(|actions| uses_self_key(actions)),
+        KeymapAction::If(conditional) => uses_self_key(&conditional.then) || uses_self_key(&conditional.otherwise),
+        KeymapAction::OnDevice(on_device) => uses_self_key(&on_device.actions),
+        _ => false,
+    })
+}
+
+// Whether `<self>` is a key of the nested remaps or layers in the actions
+pub fn remaps_self_key(actions: &[KeymapAction]) -> bool {
+    actions.iter().any(|action| match action {
+        KeymapAction::Remap(Remap { remap, .. }) | KeymapAction::Layer(Layer { remap, .. }) => remap
+            .iter()
+            .any(|(key_press, actions)| key_press.key == KEY_SELF || remaps_self_key(actions)),
+        _ => false,
+    })
+}
+
+// Written in the config syntax for --dump-config
+impl Serialize for KeymapAction {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            KeymapAction::KeyPress(key_press) => key_press.serialize(serializer),
+            KeymapAction::Remap(remap) => remap.serialize(serializer),
+            KeymapAction::Launch(command) => serialize_action(serializer, "launch", command),
+            KeymapAction::SetMode(set_mode) if set_mode.sticky => {
+                serialize_action(serializer, "set_mode", &set_mode.mode)
+            }
+            KeymapAction::SetMode(set_mode) => serialize_action(serializer, "set_mode", set_mode),
+            KeymapAction::ClearMode(clear) => serialize_action(serializer, "clear_mode", clear),
+            KeymapAction::SetMark(set) => serialize_action(serializer, "set_mark", set),
+            KeymapAction::WithMark(key_press) => serialize_action(serializer, "with_mark", key_press),
+            KeymapAction::EscapeNextKey(escape) => serialize_action(serializer, "escape_next_key", escape),
+            KeymapAction::Sleep(millis) => serialize_action(serializer, "sleep", millis),
+            KeymapAction::KeysRaw(events) => {
+                let events: Vec<String> = events
+                    .iter()
+                    .map(|(key, value)| format!("{}{:?}", if *value == PRESS { '+' } else { '-' }, key))
+                    .collect();
+                serialize_action(serializer, "keys_raw", &events)
+            }
+            KeymapAction::Press(key) => serialize_action(serializer, "press", &format!("{:?}", key)),
+            KeymapAction::Release(key) => serialize_action(serializer, "release", &format!("{:?}", key)),
+            KeymapAction::Repeat(repeat) => serialize_action(serializer, "repeat", repeat),
+            KeymapAction::SetGrab(grab) => serialize_action(serializer, "grab", grab),
+            KeymapAction::SetLed(set_led) => serialize_action(serializer, "set_led", set_led),
+            KeymapAction::MouseMove(mouse_move) => serialize_action(serializer, "mouse_move", mouse_move),
+            KeymapAction::MoveRelative(mouse_move) => serialize_action(serializer, "move_relative", mouse_move),
+            KeymapAction::RawCode(code) => serialize_action(serializer, "raw_code", code),
+            KeymapAction::Run(run) => serialize_action(serializer, "run", run),
+            KeymapAction::Scroll(scroll) => serialize_action(serializer, "scroll", scroll),
+            KeymapAction::Chord(keys) => {
+                let keys: Vec<String> = keys.iter().map(|key| format!("{:?}", key)).collect();
+                serialize_action(serializer, "chord", &keys)
+            }
+            KeymapAction::Compose(key_presses) => serialize_action(serializer, "compose", key_presses),
+            KeymapAction::Cycle(cycle) => serialize_action(serializer, "cycle", &cycle.actions),
+            KeymapAction::HoldWhilePressed(key) => {
+                serialize_action(serializer, "hold_while_pressed", &format!("{:?}", key))
+            }
+            KeymapAction::If(conditional) => ConditionalConfigRef {
+                predicate: &conditional.predicate,
+                then: &conditional.then,
+                otherwise: &conditional.otherwise,
+            }
+            .serialize(serializer),
+            KeymapAction::AwaitTitleChange(await_title_change) => {
+                serialize_action(serializer, "await_title_change", await_title_change)
+            }
+            KeymapAction::OnDevice(on_device) => OnDeviceConfigRef {
+                device: &on_device.device,
+                action: &on_device.actions,
+            }
+            .serialize(serializer),
+            KeymapAction::Layer(layer) => serialize_action(serializer, "layer", layer),
+            KeymapAction::SetExtraModifiers(_) => Err(ser::Error::custom("SetExtraModifiers is not in the config")),
+        }
+    }
+}
+
+fn serialize_action<S, T>(serializer: S, name: &str, value: &T) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Serialize + ?Sized,
+{
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry(name, value)?;
+    map.end()
+}
+
 fn deserialize_remap<'de, D>(deserializer: D) -> Result<Remap, D::Error>
 where
     D: Deserializer<'de>,
@@ -44,7 +200,7 @@ where
     let action = RemapActions::deserialize(deserializer)?;
     Ok(Remap {
         remap: action.remap.into_iter().map(|(k, v)| (k, v.into_vec())).collect(),
-        timeout: action.timeout_millis.map(Duration::from_millis),
+        timeout: action.timeout_millis,
         timeout_key: if let Some(key) = action.timeout_key {
             match parse_key(&key) {
                 Ok(key) => Some(key),
@@ -53,6 +209,10 @@ where
         } else {
             None
         },
+        replay: action.replay,
+        on_timeout: action.on_timeout.map(Actions::into_vec).unwrap_or_default(),
+        on_unmatched: action.on_unmatched,
+        literal_timeout: action.literal_timeout_millis,
     })
 }
 
@@ -69,19 +229,57 @@ where
     Err(de::Error::custom("not a map with a single \"launch\" key"))
 }
 
-fn deserialize_set_mode<'de, D>(deserializer: D) -> Result<String, D::Error>
+// A mode to switch to, which lasts until another mode is set unless it's a one-shot mode
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetMode {
+    pub mode: String,
+    // false goes back to default_mode after the next key press
+    #[serde(default = "default_sticky")]
+    pub sticky: bool,
+}
+
+fn default_sticky() -> bool {
+    true
+}
+
+// { set_mode: "mode" } or { set_mode: { mode: "mode", sticky: false } }
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SetModeConfig {
+    Mode(String),
+    SetMode(SetMode),
+}
+
+fn deserialize_set_mode<'de, D>(deserializer: D) -> Result<SetMode, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let mut action = HashMap::<String, String>::deserialize(deserializer)?;
+    let mut action = HashMap::<String, SetModeConfig>::deserialize(deserializer)?;
     if let Some(set) = action.remove("set_mode") {
         if action.is_empty() {
-            return Ok(set);
+            return Ok(match set {
+                SetModeConfig::Mode(mode) => SetMode { mode, sticky: true },
+                SetModeConfig::SetMode(set_mode) => set_mode,
+            });
         }
     }
     Err(de::Error::custom("not a map with a single \"set_mode\" key"))
 }
 
+fn deserialize_clear_mode<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, bool>::deserialize(deserializer)?;
+    if let Some(clear) = action.remove("clear_mode") {
+        if action.is_empty() {
+            return Ok(clear);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"clear_mode\" key"))
+}
+
 fn deserialize_set_mark<'de, D>(deserializer: D) -> Result<bool, D::Error>
 where
     D: Deserializer<'de>,
@@ -134,6 +332,505 @@ where
     Err(de::Error::custom("not a map with a single \"sleep\" key"))
 }
 
+fn deserialize_keys_raw<'de, D>(deserializer: D) -> Result<Vec<(Key, i32)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, Vec<String>>::deserialize(deserializer)?;
+    if let Some(events) = action.remove("keys_raw") {
+        if action.is_empty() {
+            return events
+                .iter()
+                .map(|event| parse_raw_key_event(event).map_err(de::Error::custom))
+                .collect();
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"keys_raw\" key"))
+}
+
+// "+KEY" is a press and "-KEY" is a release
+fn parse_raw_key_event(input: &str) -> Result<(Key, i32), Box<dyn Error>> {
+    let value = match input.chars().next() {
+        Some('+') => PRESS,
+        Some('-') => RELEASE,
+        _ => return Err(format!("raw key event must start with '+' or '-': '{}'", input).into()),
+    };
+    Ok((parse_key(&input[1..])?, value))
+}
+
+fn deserialize_press<'de, D>(deserializer: D) -> Result<Key, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, String>::deserialize(deserializer)?;
+    if let Some(key) = action.remove("press") {
+        if action.is_empty() {
+            return parse_modifier_key(&key).map_err(de::Error::custom);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"press\" key"))
+}
+
+fn deserialize_chord<'de, D>(deserializer: D) -> Result<Vec<Key>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, Vec<String>>::deserialize(deserializer)?;
+    if let Some(keys) = action.remove("chord") {
+        if action.is_empty() && !keys.is_empty() {
+            return keys
+                .iter()
+                .map(|key| parse_modifier_key(key).map_err(de::Error::custom))
+                .collect();
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"chord\" key of a non-empty array"))
+}
+
+fn deserialize_compose<'de, D>(deserializer: D) -> Result<Vec<KeyPress>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, Vec<KeyPress>>::deserialize(deserializer)?;
+    if let Some(key_presses) = action.remove("compose") {
+        if action.is_empty() && !key_presses.is_empty() {
+            return Ok(key_presses);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"compose\" key of a non-empty array"))
+}
+
+// Actions dispatched in turn on each press, wrapping around
+#[derive(Clone, Debug)]
+pub struct Cycle {
+    // Unique to the cycle in the config, shared by its clones, to remember which actions are next
+    pub id: usize,
+    pub actions: Vec<Vec<KeymapAction>>,
+}
+
+static NEXT_CYCLE_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn deserialize_cycle<'de, D>(deserializer: D) -> Result<Cycle, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, Vec<Actions>>::deserialize(deserializer)?;
+    if let Some(actions) = action.remove("cycle") {
+        if action.is_empty() && !actions.is_empty() {
+            return Ok(Cycle {
+                id: NEXT_CYCLE_ID.fetch_add(1, Ordering::Relaxed),
+                actions: actions.into_iter().map(Actions::into_vec).collect(),
+            });
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"cycle\" key of a non-empty array"))
+}
+
+fn deserialize_hold_while_pressed<'de, D>(deserializer: D) -> Result<Key, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, String>::deserialize(deserializer)?;
+    if let Some(key) = action.remove("hold_while_pressed") {
+        if action.is_empty() {
+            return parse_modifier_key(&key).map_err(de::Error::custom);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"hold_while_pressed\" key"))
+}
+
+// Actions chosen by whether a shell command exits with 0
+#[derive(Clone, Debug)]
+pub struct Conditional {
+    pub predicate: String,
+    pub then: Vec<KeymapAction>,
+    pub otherwise: Vec<KeymapAction>,
+}
+
+// { if: "command", then: [...], else: [...] }
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConditionalConfig {
+    #[serde(rename = "if")]
+    predicate: String,
+    #[serde(default)]
+    then: Option<Actions>,
+    #[serde(default, rename = "else")]
+    otherwise: Option<Actions>,
+}
+
+// Same as ConditionalConfig for --dump-config
+#[derive(Serialize)]
+struct ConditionalConfigRef<'a> {
+    #[serde(rename = "if")]
+    predicate: &'a String,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    then: &'a [KeymapAction],
+    #[serde(rename = "else", skip_serializing_if = "<[_]>::is_empty")]
+    otherwise: &'a [KeymapAction],
+}
+
+fn deserialize_conditional<'de, D>(deserializer: D) -> Result<Conditional, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // Only a map, since a struct could also be deserialized from an array of actions
+    struct ConditionalVisitor;
+    impl<'de> de::Visitor<'de> for ConditionalVisitor {
+        type Value = ConditionalConfig;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map with \"if\"")
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<ConditionalConfig, A::Error> {
+            ConditionalConfig::deserialize(de::value::MapAccessDeserializer::new(map))
+        }
+    }
+    let conditional = deserializer.deserialize_map(ConditionalVisitor)?;
+    Ok(Conditional {
+        predicate: conditional.predicate,
+        then: conditional.then.map(Actions::into_vec).unwrap_or_default(),
+        otherwise: conditional.otherwise.map(Actions::into_vec).unwrap_or_default(),
+    })
+}
+
+fn deserialize_release<'de, D>(deserializer: D) -> Result<Key, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, String>::deserialize(deserializer)?;
+    if let Some(key) = action.remove("release") {
+        if action.is_empty() {
+            return parse_modifier_key(&key).map_err(de::Error::custom);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"release\" key"))
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Repeat {
+    pub key: KeyPress,
+    pub count: u64,
+}
+
+fn deserialize_repeat<'de, D>(deserializer: D) -> Result<Repeat, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, Repeat>::deserialize(deserializer)?;
+    if let Some(repeat) = action.remove("repeat") {
+        if action.is_empty() {
+            if repeat.count == 0 {
+                return Err(de::Error::custom("\"repeat\" count must be greater than 0"));
+            }
+            return Ok(repeat);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"repeat\" key"))
+}
+
+// Pixels moved on each tick while the key is held, or once for move_relative
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MouseMove {
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub y: i32,
+}
+
+fn deserialize_mouse_move<'de, D>(deserializer: D) -> Result<MouseMove, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, MouseMove>::deserialize(deserializer)?;
+    if let Some(mouse_move) = action.remove("mouse_move") {
+        if action.is_empty() {
+            return Ok(mouse_move);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"mouse_move\" key"))
+}
+
+fn deserialize_move_relative<'de, D>(deserializer: D) -> Result<MouseMove, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, MouseMove>::deserialize(deserializer)?;
+    if let Some(mouse_move) = action.remove("move_relative") {
+        if action.is_empty() {
+            return Ok(mouse_move);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"move_relative\" key"))
+}
+
+fn deserialize_raw_code<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, u16>::deserialize(deserializer)?;
+    if let Some(code) = action.remove("raw_code") {
+        if action.is_empty() {
+            if code > KEY_MAX {
+                return Err(de::Error::custom(format!("\"raw_code\" must not be greater than {:#x}", KEY_MAX)));
+            }
+            return Ok(code);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"raw_code\" key"))
+}
+
+// Notches scrolled right and up, which may be fractions
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scroll {
+    #[serde(default)]
+    pub x: f64,
+    #[serde(default)]
+    pub y: f64,
+}
+
+fn deserialize_scroll<'de, D>(deserializer: D) -> Result<Scroll, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, Scroll>::deserialize(deserializer)?;
+    if let Some(scroll) = action.remove("scroll") {
+        if action.is_empty() {
+            return Ok(scroll);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"scroll\" key"))
+}
+
+// A shell command run in the background, whose output and exit status are logged
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Run {
+    pub command: String,
+    // Run it again on every autorepeat of the key
+    #[serde(default)]
+    pub repeat: bool,
+}
+
+// { run: "command" } or { run: { command: "command", repeat: true } }
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RunConfig {
+    Command(String),
+    Run(Run),
+}
+
+fn deserialize_run<'de, D>(deserializer: D) -> Result<Run, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, RunConfig>::deserialize(deserializer)?;
+    if let Some(run) = action.remove("run") {
+        if action.is_empty() {
+            return Ok(match run {
+                RunConfig::Command(command) => Run { command, repeat: false },
+                RunConfig::Run(run) => run,
+            });
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"run\" key"))
+}
+
+// { set_led: { led: scrolllock, on: true } }, e.g. to indicate a mode on the keyboard
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetLed {
+    #[serde(deserialize_with = "deserialize_led", serialize_with = "serialize_led")]
+    pub led: LedType,
+    pub on: bool,
+}
+
+fn deserialize_set_led<'de, D>(deserializer: D) -> Result<SetLed, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, SetLed>::deserialize(deserializer)?;
+    if let Some(set_led) = action.remove("set_led") {
+        if action.is_empty() {
+            return Ok(set_led);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"set_led\" key"))
+}
+
+fn deserialize_set_grab<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let action = HashMap::<String, bool>::deserialize(deserializer)?;
+    if action.len() == 1 {
+        if let Some(grab) = action.get("grab") {
+            return Ok(*grab);
+        }
+        if let Some(ungrab) = action.get("ungrab") {
+            return Ok(!*ungrab);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"grab\" or \"ungrab\" key"))
+}
+
+// Press a key, and dispatch the actions after it once the title of the active window changes
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AwaitTitleChange {
+    pub key: KeyPress,
+    // The rest is dispatched anyway after this long
+    #[serde_as(as = "Millis")]
+    #[serde(default = "default_await_timeout", rename = "timeout_ms")]
+    pub timeout: Duration,
+    // How often the title is queried
+    #[serde_as(as = "Millis")]
+    #[serde(default = "default_await_interval", rename = "interval_ms")]
+    pub interval: Duration,
+}
+
+fn default_await_timeout() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_await_interval() -> Duration {
+    Duration::from_millis(50)
+}
+
+impl Serialize for AwaitTitleChange {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("key", &self.key)?;
+        map.serialize_entry("timeout_ms", &format_duration(&self.timeout))?;
+        map.serialize_entry("interval_ms", &format_duration(&self.interval))?;
+        map.end()
+    }
+}
+
+fn deserialize_await_title_change<'de, D>(deserializer: D) -> Result<AwaitTitleChange, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, AwaitTitleChange>::deserialize(deserializer)?;
+    if let Some(await_title_change) = action.remove("await_title_change") {
+        if action.is_empty() {
+            return Ok(await_title_change);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"await_title_change\" key"))
+}
+
+// Actions whose events are emitted on a device of `virtual_devices`
+#[derive(Clone, Debug)]
+pub struct OnDevice {
+    pub device: String,
+    pub actions: Vec<KeymapAction>,
+}
+
+// { device: "name", action: ... }
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OnDeviceConfig {
+    device: String,
+    action: Actions,
+}
+
+// Same as OnDeviceConfig for --dump-config
+#[derive(Serialize)]
+struct OnDeviceConfigRef<'a> {
+    device: &'a String,
+    action: &'a [KeymapAction],
+}
+
+fn deserialize_on_device<'de, D>(deserializer: D) -> Result<OnDevice, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // Only a map, since a struct could also be deserialized from an array of actions
+    struct OnDeviceVisitor;
+    impl<'de> de::Visitor<'de> for OnDeviceVisitor {
+        type Value = OnDeviceConfig;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map with \"device\" and \"action\"")
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<OnDeviceConfig, A::Error> {
+            OnDeviceConfig::deserialize(de::value::MapAccessDeserializer::new(map))
+        }
+    }
+    let on_device = deserializer.deserialize_map(OnDeviceVisitor)?;
+    Ok(OnDevice {
+        device: on_device.device,
+        actions: on_device.action.into_vec(),
+    })
+}
+
+// Remaps that apply to every key until the timeout, unlike a nested remap that ends with the next key
+#[derive(Clone, Debug)]
+pub struct Layer {
+    pub remap: HashMap<KeyPress, Vec<KeymapAction>>,
+    pub timeout: Duration,
+}
+
+// { remap: { ... }, timeout_ms: 2000 }
+#[serde_as]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LayerConfig {
+    remap: HashMap<KeyPress, Actions>,
+    #[serde_as(as = "Millis")]
+    timeout_ms: Duration,
+}
+
+// Same as LayerConfig, with the remaps sorted for --dump-config
+#[derive(Serialize)]
+struct LayerConfigRef<'a> {
+    remap: BTreeMap<String, &'a Vec<KeymapAction>>,
+    timeout_ms: String,
+}
+
+impl Serialize for Layer {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        LayerConfigRef {
+            remap: self
+                .remap
+                .iter()
+                .map(|(key_press, actions)| (key_press.to_string(), actions))
+                .collect(),
+            timeout_ms: format_duration(&self.timeout),
+        }
+        .serialize(serializer)
+    }
+}
+
+fn deserialize_layer<'de, D>(deserializer: D) -> Result<Layer, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, LayerConfig>::deserialize(deserializer)?;
+    if let Some(layer) = action.remove("layer") {
+        if action.is_empty() {
+            return Ok(Layer {
+                remap: layer.remap.into_iter().map(|(k, v)| (k, v.into_vec())).collect(),
+                timeout: layer.timeout_ms,
+            });
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"layer\" key"))
+}
+
 // Used only for deserializing Vec<Action>
 #[derive(Clone, Debug, Deserialize)]
 #[serde(untagged)]
@@ -179,6 +876,137 @@ mod tests {
         test_yaml_parsing_key_launch("{launch: [\"bla\"]}", vec!["bla".into()]);
     }
 
+    #[test]
+    fn test_keys_raw_action() {
+        match serde_yaml::from_str("{keys_raw: [+leftctrl, +c, -leftctrl, -c]}").unwrap() {
+            KeymapAction::KeysRaw(events) => assert_eq!(
+                events,
+                vec![
+                    (Key::KEY_LEFTCTRL, 1),
+                    (Key::KEY_C, 1),
+                    (Key::KEY_LEFTCTRL, 0),
+                    (Key::KEY_C, 0)
+                ]
+            ),
+            _ => panic!("unexpected type"),
+        }
+        assert!(serde_yaml::from_str::<KeymapAction>("{keys_raw: [c]}").is_err());
+    }
+
+    #[test]
+    fn test_chord_action() {
+        match serde_yaml::from_str("{chord: [alt, shift_l]}").unwrap() {
+            KeymapAction::Chord(keys) => assert_eq!(keys, vec![Key::KEY_LEFTALT, Key::KEY_LEFTSHIFT]),
+            _ => panic!("unexpected type"),
+        }
+        assert!(serde_yaml::from_str::<KeymapAction>("{chord: []}").is_err());
+    }
+
+    #[test]
+    fn test_cycle_action() {
+        match serde_yaml::from_str("{cycle: [a, [b, c]]}").unwrap() {
+            KeymapAction::Cycle(cycle) => {
+                assert_eq!(cycle.actions.len(), 2);
+                assert_eq!(cycle.actions[1].len(), 2);
+            }
+            _ => panic!("unexpected type"),
+        }
+        assert!(serde_yaml::from_str::<KeymapAction>("{cycle: []}").is_err());
+    }
+
+    #[test]
+    fn test_press_release_action() {
+        match serde_yaml::from_str("{press: ctrl}").unwrap() {
+            KeymapAction::Press(key) => assert_eq!(key, Key::KEY_LEFTCTRL),
+            _ => panic!("unexpected type"),
+        }
+        match serde_yaml::from_str("{release: ctrl}").unwrap() {
+            KeymapAction::Release(key) => assert_eq!(key, Key::KEY_LEFTCTRL),
+            _ => panic!("unexpected type"),
+        }
+        match serde_yaml::from_str("{press: a}").unwrap() {
+            KeymapAction::Press(key) => assert_eq!(key, Key::KEY_A),
+            _ => panic!("unexpected type"),
+        }
+        match serde_yaml::from_str("{hold_while_pressed: ctrl}").unwrap() {
+            KeymapAction::HoldWhilePressed(key) => assert_eq!(key, Key::KEY_LEFTCTRL),
+            _ => panic!("unexpected type"),
+        }
+        assert!(serde_yaml::from_str::<KeymapAction>("{press: \"*\"}").is_err());
+    }
+
+    #[test]
+    fn test_repeat_action() {
+        match serde_yaml::from_str("{repeat: {key: pagedown, count: 5}}").unwrap() {
+            KeymapAction::Repeat(repeat) => {
+                assert_eq!(repeat.key.key, Key::KEY_PAGEDOWN);
+                assert_eq!(repeat.count, 5);
+            }
+            _ => panic!("unexpected type"),
+        }
+        assert!(serde_yaml::from_str::<KeymapAction>("{repeat: {key: pagedown, count: 0}}").is_err());
+    }
+
+    #[test]
+    fn test_raw_code_action() {
+        match serde_yaml::from_str("{raw_code: 0x2f5}").unwrap() {
+            KeymapAction::RawCode(code) => assert_eq!(code, 0x2f5),
+            _ => panic!("unexpected type"),
+        }
+        assert!(serde_yaml::from_str::<KeymapAction>("{raw_code: 0x300}").is_err());
+    }
+
+    #[test]
+    fn test_move_relative_action() {
+        match serde_yaml::from_str("{move_relative: {x: -10, y: 20}}").unwrap() {
+            KeymapAction::MoveRelative(mouse_move) => assert_eq!((mouse_move.x, mouse_move.y), (-10, 20)),
+            _ => panic!("unexpected type"),
+        }
+    }
+
+    #[test]
+    fn test_scroll_action() {
+        match serde_yaml::from_str("{scroll: {y: -0.25}}").unwrap() {
+            KeymapAction::Scroll(scroll) => assert_eq!((scroll.x, scroll.y), (0.0, -0.25)),
+            _ => panic!("unexpected type"),
+        }
+    }
+
+    #[test]
+    fn test_run_action() {
+        match serde_yaml::from_str("{run: notify-send hi}").unwrap() {
+            KeymapAction::Run(run) => {
+                assert_eq!(run.command, "notify-send hi");
+                assert!(!run.repeat);
+            }
+            _ => panic!("unexpected type"),
+        }
+        match serde_yaml::from_str("{run: {command: notify-send hi, repeat: true}}").unwrap() {
+            KeymapAction::Run(run) => {
+                assert_eq!(run.command, "notify-send hi");
+                assert!(run.repeat);
+            }
+            _ => panic!("unexpected type"),
+        }
+    }
+
+    #[test]
+    fn test_conditional_action() {
+        match serde_yaml::from_str("{if: pgrep foo, then: [a, b], else: c}").unwrap() {
+            KeymapAction::If(conditional) => {
+                assert_eq!(conditional.predicate, "pgrep foo");
+                assert_eq!(conditional.then.len(), 2);
+                assert_eq!(conditional.otherwise.len(), 1);
+            }
+            _ => panic!("unexpected type"),
+        }
+        match serde_yaml::from_str("{if: pgrep foo, then: a}").unwrap() {
+            KeymapAction::If(conditional) => assert!(conditional.otherwise.is_empty()),
+            _ => panic!("unexpected type"),
+        }
+        assert!(serde_yaml::from_str::<KeymapAction>("{if: pgrep foo, than: a}").is_err());
+    }
+
     #[test]
     fn test_null_action() {
         if let Actions::NoAction = serde_yaml::from_str("null").unwrap() {
@@ -0,0 +1,56 @@
+use crate::config::application::deserialize_string_or_vec;
+use evdev::LedType;
+use serde::{de, Deserialize, Deserializer, Serializer};
+use std::error::Error;
+use std::str::FromStr;
+
+pub fn deserialize_leds<'de, D>(deserializer: D) -> Result<Option<Vec<LedType>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match deserialize_string_or_vec(deserializer)? {
+        None => Ok(None),
+        Some(strings) => {
+            let mut leds: Vec<LedType> = vec![];
+            for s in strings {
+                leds.push(parse_led(&s).map_err(de::Error::custom)?);
+            }
+            Ok(Some(leds))
+        }
+    }
+}
+
+pub fn deserialize_led<'de, D>(deserializer: D) -> Result<LedType, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    parse_led(&name).map_err(de::Error::custom)
+}
+
+pub fn serialize_led<S: Serializer>(led: &LedType, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:?}", led))
+}
+
+pub fn parse_led(input: &str) -> Result<LedType, Box<dyn Error>> {
+    // Everything is case-insensitive
+    let name = input.to_uppercase();
+
+    // Original evdev names should always work
+    if let Ok(led) = LedType::from_str(&name) {
+        return Ok(led);
+    }
+
+    // You can abbreviate "LED_" of any "LED_*" names.
+    if let Ok(led) = LedType::from_str(&format!("LED_{}", name)) {
+        return Ok(led);
+    }
+
+    // Name the LEDs after the keys that toggle them
+    match &name[..] {
+        "NUMLOCK" => Ok(LedType::LED_NUML),
+        "CAPSLOCK" => Ok(LedType::LED_CAPSL),
+        "SCROLLLOCK" => Ok(LedType::LED_SCROLLL),
+        _ => Err(format!("unknown led '{}'", input).into()),
+    }
+}
@@ -1,31 +1,56 @@
 pub mod application;
 pub mod device;
+pub mod dump;
+pub mod duration;
 mod key;
 pub mod key_press;
 pub mod keymap;
 pub mod keymap_action;
+pub mod led;
 mod modmap;
 pub mod modmap_action;
+pub mod mouse_movement_coalescing;
 
 pub mod remap;
+pub mod scroll_acceleration;
+pub mod syn_report;
 #[cfg(test)]
 mod tests;
+pub mod virtual_device;
 
 extern crate serde_yaml;
 extern crate toml;
 
+use crate::event::InputDeviceInfo;
+use crate::event_handler::KEY_SELF;
+use device::{RelInvert, RelScale, UnmatchedDevices};
+use duration::Millis;
 use evdev::Key;
+use key_press::{parse_modifier_key, KeyPress};
 use keymap::Keymap;
+use keymap_action::{remaps_self_key, uses_self_key, Actions, KeymapAction};
+use log::warn;
 use modmap::Modmap;
+use mouse_movement_coalescing::MouseMovementCoalescing;
 use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use scroll_acceleration::{deserialize_scroll_acceleration, ScrollAcceleration};
 use serde::{de::IgnoredAny, Deserialize, Deserializer};
-use std::{collections::HashMap, error, fs, path::PathBuf, time::SystemTime};
+use serde_with::serde_as;
+use std::{
+    collections::HashMap,
+    error, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use syn_report::SynReport;
+use virtual_device::{deserialize_virtual_devices, NamedVirtualDevice, VirtualDevice};
 
 use self::{
     key::parse_key,
-    keymap::{build_keymap_table, KeymapEntry},
+    keymap::{build_keymap_table, find_conflicts, find_merge_conflicts, KeymapEntry, MergeStrategy},
 };
 
+#[serde_as]
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
@@ -38,8 +63,70 @@ pub struct Config {
     pub default_mode: String,
     #[serde(deserialize_with = "deserialize_virtual_modifiers", default = "Vec::new")]
     pub virtual_modifiers: Vec<Key>,
+    #[serde_as(as = "Millis")]
+    #[serde(default, rename = "keypress_delay_ms")]
+    pub keypress_delay: Duration,
+    // keypress_delay_ms after modifiers are pressed or released for a key, and after the key itself
+    #[serde_as(as = "Option<Millis>")]
+    #[serde(default, rename = "modifier_delay_ms")]
+    pub modifier_delay: Option<Duration>,
+    #[serde_as(as = "Option<Millis>")]
+    #[serde(default, rename = "key_delay_ms")]
+    pub key_delay: Option<Duration>,
+    // exact_match of keymaps that don't specify it
+    #[serde(default)]
+    pub default_exact_match: bool,
+    #[serde_as(as = "Millis")]
+    #[serde(default = "default_mouse_move_interval", rename = "mouse_move_interval_ms")]
+    pub mouse_move_interval: Duration,
+    #[serde(default)]
+    pub mouse_movement_coalescing: MouseMovementCoalescing,
+    #[serde(default = "Vec::new")]
+    pub include: Vec<PathBuf>,
+    // Devices whose events are sent to the output device without remapping
+    #[serde(default = "Vec::new")]
+    pub passthrough_devices: Vec<String>,
+    #[serde(default)]
+    pub unmatched_devices: UnmatchedDevices,
+    // Fail to load a config with remaps that never match, instead of warning about them
+    #[serde(default)]
+    pub strict: bool,
+    // Which nested remap wins a key mapped by more than one of the same key press and filters
     #[serde(default)]
-    pub keypress_delay_ms: u64,
+    pub merge_strategy: MergeStrategy,
+    // Warn if the application client hasn't updated its values for this long
+    #[serde_as(as = "Option<Millis>")]
+    #[serde(default, rename = "client_cache_ttl_ms")]
+    pub client_cache_ttl: Option<Duration>,
+    #[serde(default)]
+    pub syn_report: SynReport,
+    // How many { run: ... } commands may run at the same time
+    #[serde(default = "default_run_max_concurrency")]
+    pub run_max_concurrency: usize,
+    // Events emitted per second at most. Unlimited if None.
+    #[serde(default)]
+    pub max_output_rate: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_scroll_acceleration")]
+    pub scroll_acceleration: Option<ScrollAcceleration>,
+    // The first one matching a device applies to it
+    #[serde(default = "Vec::new")]
+    pub rel_scale: Vec<RelScale>,
+    // The first one matching a device applies to it
+    #[serde(default = "Vec::new")]
+    pub rel_invert: Vec<RelInvert>,
+    // A key press that releases every held key and resets nested remaps and the mode
+    #[serde(default)]
+    pub panic_key: Option<KeyPress>,
+    // Modifiers of a key press are pressed in this order, followed by unlisted ones in the order they're written
+    #[serde(deserialize_with = "deserialize_modifier_order", default = "Vec::new")]
+    pub modifier_order: Vec<Key>,
+    // Release held modifiers that a remap's key press doesn't have, and press them again afterwards.
+    // Keymaps may override it.
+    #[serde(default = "default_interleave_modifiers")]
+    pub interleave_modifiers: bool,
+    // Actions dispatched once before handling any input
+    #[serde(default = "Vec::new", deserialize_with = "deserialize_on_startup")]
+    pub on_startup: Vec<KeymapAction>,
 
     // Data is not used by any part of the application.
     // but can be used with Anchors and Aliases
@@ -50,10 +137,31 @@ pub struct Config {
     // Internals
     #[serde(skip)]
     pub modify_time: Option<SystemTime>,
+    // The config files and the files they include, which --watch=config watches
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
     #[serde(skip)]
     pub keymap_table: HashMap<Key, Vec<KeymapEntry>>,
     #[serde(default = "const_true")]
     pub enable_wheel: bool,
+    // Declare REL_WHEEL_HI_RES and REL_HWHEEL_HI_RES, and emit them for { scroll: ... }
+    #[serde(default)]
+    pub enable_hires_wheel: bool,
+    // Emit mouse buttons and movements through the keyboard output device
+    #[serde(default)]
+    pub single_output_device: bool,
+    // Declare MSC_SCAN, and emit it before each key press and release like a USB keyboard
+    #[serde(default)]
+    pub emit_scancodes: bool,
+    // The key that starts a sequence of { compose: [...] }, which is the Compose key of XKB
+    #[serde(default = "default_compose_key")]
+    pub compose_key: KeyPress,
+    // Only read at startup, when the output devices are created
+    #[serde(default)]
+    pub virtual_device: VirtualDevice,
+    // Output devices that { device: ..., action: ... } emits on, also created only at startup
+    #[serde(default = "Vec::new", deserialize_with = "deserialize_virtual_devices")]
+    pub virtual_devices: Vec<NamedVirtualDevice>,
 }
 
 enum ConfigFiletype {
@@ -61,7 +169,7 @@ enum ConfigFiletype {
     Toml,
 }
 
-fn get_file_ext(filename: &PathBuf) -> ConfigFiletype {
+fn get_file_ext(filename: &Path) -> ConfigFiletype {
     match filename.extension() {
         Some(f) => {
             if f.to_str().unwrap_or("").to_lowercase() == "toml" {
@@ -75,55 +183,312 @@ fn get_file_ext(filename: &PathBuf) -> ConfigFiletype {
 }
 
 pub fn load_configs(filenames: &Vec<PathBuf>) -> Result<Config, Box<dyn error::Error>> {
+    let mut files = filenames.clone();
+    // rel_aliases of a file apply to the files loaded after it
+    let mut rel_aliases = HashMap::new();
     // Assumes filenames is non-empty
-    let config_contents = fs::read_to_string(&filenames[0])?;
-
-    let mut config: Config = match get_file_ext(&filenames[0]) {
-        ConfigFiletype::Yaml => serde_yaml::from_str(&config_contents)?,
-        ConfigFiletype::Toml => toml::from_str(&config_contents)?,
-    };
+    let mut config = load_config(&filenames[0], &mut vec![], &mut files, &mut rel_aliases)?;
 
     for filename in &filenames[1..] {
-        let config_contents = fs::read_to_string(&filename)?;
-        let c: Config = match get_file_ext(&filename) {
-            ConfigFiletype::Yaml => serde_yaml::from_str(&config_contents)?,
-            ConfigFiletype::Toml => serde_yaml::from_str(&config_contents)?,
-        };
-
-        config.modmap.extend(c.modmap);
-        config.keymap.extend(c.keymap);
-        config.virtual_modifiers.extend(c.virtual_modifiers);
+        config.merge(load_config(filename, &mut vec![], &mut files, &mut rel_aliases)?);
     }
 
     // Timestamp for --watch=config
-    config.modify_time = filenames.last().and_then(|path| path.metadata().ok()?.modified().ok());
+    config.modify_time = modify_time(&files);
+    config.files = files;
+
+    check_self_key(&config)?;
 
     // Convert keymap for efficient keymap lookup
-    config.keymap_table = build_keymap_table(&config.keymap);
+    config.keymap_table = build_keymap_table(&config.keymap, config.default_exact_match, config.merge_strategy);
+    let conflicts = find_conflicts(&config.keymap_table);
+    for conflict in &conflicts {
+        warn!("{}", conflict);
+    }
+    if config.strict && !conflicts.is_empty() {
+        return Err(format!("Conflicting remaps with strict: true: {}", conflicts.join(", ")).into());
+    }
+    if config.merge_strategy == MergeStrategy::Error {
+        let merge_conflicts = find_merge_conflicts(&config.keymap_table);
+        if !merge_conflicts.is_empty() {
+            return Err(format!(
+                "Conflicting nested remaps with merge_strategy: error: {}",
+                merge_conflicts.join(", ")
+            )
+            .into());
+        }
+    }
+
+    Ok(config)
+}
+
+// `<self>` is sent as the key that triggered the actions, so it can't be remapped or used in on_startup
+fn check_self_key(config: &Config) -> Result<(), String> {
+    if uses_self_key(&config.on_startup) {
+        return Err("<self> can't be used in on_startup, which isn't triggered by a key".into());
+    }
+    let remapped = config.keymap.iter().any(|keymap| {
+        keymap
+            .remap
+            .iter()
+            .any(|(key_press, binding)| key_press.key == KEY_SELF || remaps_self_key(&binding.actions))
+    }) || config.modmap.iter().any(|modmap| modmap.remap.contains_key(&KEY_SELF));
+    if remapped {
+        return Err("<self> can't be remapped since it stands for the key that triggered the actions".into());
+    }
+    Ok(())
+}
+
+// The latest modification time of the files, which tells --watch=config whether they changed since loaded
+pub fn modify_time(files: &[PathBuf]) -> Option<SystemTime> {
+    files
+        .iter()
+        .filter_map(|path| path.metadata().ok()?.modified().ok())
+        .max()
+}
+
+// Load a config file and the files it includes. `including` is the chain of files
+// that led to this one, which is used to detect include cycles. The included files are added to `files`.
+fn load_config(
+    filename: &Path,
+    including: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+    rel_aliases: &mut HashMap<String, String>,
+) -> Result<Config, Box<dyn error::Error>> {
+    let config_contents = read_config(filename).map_err(|e| format!("{}: {}", filename.display(), e))?;
+    let mut config = parse_config(filename, &config_contents, rel_aliases)?;
+
+    including.push(if is_stdin(filename) {
+        filename.to_path_buf()
+    } else {
+        filename.canonicalize()?
+    });
+    for include in std::mem::take(&mut config.include) {
+        // Relative paths are resolved from the directory of the including file
+        let include = filename.parent().map_or(include.clone(), |dir| dir.join(&include));
+        let canonical = include
+            .canonicalize()
+            .map_err(|e| format!("Failed to include '{}': {}", include.display(), e))?;
+        if including.contains(&canonical) {
+            return Err(format!("Include cycle detected at '{}'", include.display()).into());
+        }
+        // A file included twice, e.g. by two files that share it, is only merged the first time
+        if files.contains(&canonical) {
+            continue;
+        }
+        files.push(canonical);
+        let c = load_config(&include, including, files, rel_aliases)
+            .map_err(|e| format!("Failed to include '{}': {}", include.display(), e))?;
+        config.merge(c);
+    }
+    including.pop();
 
     Ok(config)
 }
 
+// Parse YAML like a config file read from stdin, without resolving its includes
+pub fn parse_yaml_config(contents: &str) -> Result<Config, Box<dyn error::Error>> {
+    parse_config(Path::new("-"), contents, &mut HashMap::new())
+}
+
+// Parse the contents of a single config file. Its rel_aliases are added to `rel_aliases`
+// for the files parsed after it.
+fn parse_config(
+    filename: &Path,
+    contents: &str,
+    rel_aliases: &mut HashMap<String, String>,
+) -> Result<Config, Box<dyn error::Error>> {
+    let file_aliases: RelAliases = match get_file_ext(filename) {
+        ConfigFiletype::Yaml => serde_yaml::from_str(contents).map_err(|e| yaml_error(filename, e))?,
+        ConfigFiletype::Toml => toml::from_str(contents).map_err(|e| toml_error(filename, contents, e))?,
+    };
+    if file_aliases.rel_aliases.is_none() && rel_aliases.is_empty() {
+        return Ok(match get_file_ext(filename) {
+            ConfigFiletype::Yaml => parse_yaml(contents).map_err(|e| yaml_error(filename, e))?,
+            ConfigFiletype::Toml => toml::from_str(contents).map_err(|e| toml_error(filename, contents, e))?,
+        });
+    }
+    for (name, target) in file_aliases.rel_aliases.unwrap_or_default() {
+        key::check_rel_alias(&name, &target).map_err(|e| format!("{}: {}", filename.display(), e))?;
+        rel_aliases.insert(name.to_uppercase(), target);
+    }
+
+    // Keys are parsed while the config is deserialized, so the aliases are replaced beforehand.
+    // Like merge keys, this loses the line numbers of errors.
+    let mut value: serde_yaml::Value = match get_file_ext(filename) {
+        ConfigFiletype::Yaml => {
+            let mut value: serde_yaml::Value = serde_yaml::from_str(contents).map_err(|e| yaml_error(filename, e))?;
+            value.apply_merge().map_err(|e| yaml_error(filename, e))?;
+            value
+        }
+        ConfigFiletype::Toml => toml::from_str(contents).map_err(|e| toml_error(filename, contents, e))?,
+    };
+    if let Some(mapping) = value.as_mapping_mut() {
+        mapping.remove("rel_aliases");
+        for section in ["modmap", "keymap"] {
+            if let Some(section) = mapping.get_mut(section) {
+                key::resolve_rel_aliases(section, rel_aliases);
+            }
+        }
+    }
+    Ok(serde_yaml::from_value(value).map_err(|e| yaml_error(filename, e))?)
+}
+
+// A config path that reads YAML from stdin, whose includes are resolved from the current directory
+pub fn is_stdin(filename: &Path) -> bool {
+    filename == Path::new("-")
+}
+
+// stdin is read fully before the config is parsed
+fn read_config(filename: &Path) -> io::Result<String> {
+    if is_stdin(filename) {
+        io::read_to_string(io::stdin())
+    } else {
+        fs::read_to_string(filename)
+    }
+}
+
+#[derive(Deserialize)]
+struct RelAliases {
+    #[serde(default)]
+    rel_aliases: Option<HashMap<String, String>>,
+}
+
+// Like serde_yaml::from_str, but expands merge keys (`<<: *anchor`) first so that
+// an anchored block can be reused and partially overridden.
+fn parse_yaml(contents: &str) -> Result<Config, serde_yaml::Error> {
+    let value: serde_yaml::Value = serde_yaml::from_str(contents)?;
+    let mut merged = value.clone();
+    merged.apply_merge()?;
+    if merged == value {
+        // Deserialize the text again for errors with line numbers
+        serde_yaml::from_str(contents)
+    } else {
+        serde_yaml::from_value(merged)
+    }
+}
+
+// Format errors like "path:line:column: message" so that editors can jump to the location
+fn yaml_error(filename: &Path, error: serde_yaml::Error) -> String {
+    match error.location() {
+        Some(location) => {
+            let message = error.to_string();
+            let suffix = format!(" at line {} column {}", location.line(), location.column());
+            let message = message.strip_suffix(&suffix).unwrap_or(&message);
+            format!("{}:{}:{}: {}", filename.display(), location.line(), location.column(), message)
+        }
+        None => format!("{}: {}", filename.display(), error),
+    }
+}
+
+fn toml_error(filename: &Path, contents: &str, error: toml::de::Error) -> String {
+    match error.span() {
+        Some(span) => {
+            let before = &contents[..span.start];
+            let line = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+            format!("{}:{}:{}: {}", filename.display(), line, column, error.message())
+        }
+        None => format!("{}: {}", filename.display(), error.message()),
+    }
+}
+
+impl Config {
+    // Append the remaps of another config. Other settings of the other config are ignored.
+    fn merge(&mut self, other: Config) {
+        self.modmap.extend(other.modmap);
+        self.keymap.extend(other.keymap);
+        self.virtual_modifiers.extend(other.virtual_modifiers);
+    }
+
+    // Whether the `device` filter of any modmap or keymap (or a remap of it) accepts the device
+    pub fn remaps_device(&self, device: &InputDeviceInfo) -> bool {
+        let modmap_devices = self.modmap.iter().map(|modmap| modmap.device.as_ref());
+        let keymap_devices = self.keymap.iter().flat_map(|keymap| {
+            keymap
+                .remap
+                .values()
+                .map(|binding| binding.device.as_ref().or(keymap.device.as_ref()))
+        });
+        modmap_devices
+            .chain(keymap_devices)
+            .any(|filter| filter.is_none_or(|filter| filter.matches(device)))
+    }
+
+    // Whether the events of the device are sent to the output device without remapping
+    pub fn is_passthrough_device(&self, device: &InputDeviceInfo) -> bool {
+        self.passthrough_devices.iter().any(|filter| device.matches(filter))
+            || (self.unmatched_devices == UnmatchedDevices::Passthrough && !self.remaps_device(device))
+    }
+
+    pub fn rel_scale(&self, device: &InputDeviceInfo) -> Option<f64> {
+        self.rel_scale
+            .iter()
+            .find(|rel_scale| rel_scale.device.matches(device))
+            .map(|rel_scale| rel_scale.scale)
+    }
+
+    // Whether rel_invert flips the sign of the axis of the device
+    pub fn rel_inverted(&self, device: &InputDeviceInfo, code: u16) -> bool {
+        self.rel_invert
+            .iter()
+            .find(|rel_invert| rel_invert.device.matches(device))
+            .is_some_and(|rel_invert| rel_invert.invert.contains(&code))
+    }
+
+    // Whether the device should be left ungrabbed
+    pub fn ignores_device(&self, device: &InputDeviceInfo) -> bool {
+        self.unmatched_devices == UnmatchedDevices::Ignore
+            && !self.remaps_device(device)
+            && !self.passthrough_devices.iter().any(|filter| device.matches(filter))
+    }
+}
+
 pub fn config_watcher(watch: bool, files: &Vec<PathBuf>) -> anyhow::Result<Option<Inotify>> {
     if watch {
         let inotify = Inotify::init(InitFlags::IN_NONBLOCK)?;
-        for file in files {
-            inotify.add_watch(
-                file.parent().expect("config file has a parent directory"),
-                AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO,
-            )?;
-            inotify.add_watch(file, AddWatchFlags::IN_MODIFY)?;
-        }
+        watch_config_files(&inotify, files)?;
         Ok(Some(inotify))
     } else {
         Ok(None)
     }
 }
 
+// Also called after a reload for files included since. Watching a file again is a no-op.
+pub fn watch_config_files(inotify: &Inotify, files: &Vec<PathBuf>) -> nix::Result<()> {
+    for file in files {
+        inotify.add_watch(
+            file.parent().expect("config file has a parent directory"),
+            AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO,
+        )?;
+        inotify.add_watch(file, AddWatchFlags::IN_MODIFY)?;
+    }
+    Ok(())
+}
+
 fn default_mode() -> String {
     "default".to_string()
 }
 
+fn default_mouse_move_interval() -> Duration {
+    Duration::from_millis(10)
+}
+
+fn default_run_max_concurrency() -> usize {
+    4
+}
+
+fn default_interleave_modifiers() -> bool {
+    true
+}
+
+fn deserialize_on_startup<'de, D>(deserializer: D) -> Result<Vec<KeymapAction>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Actions::deserialize(deserializer)?.into_vec())
+}
+
 fn deserialize_virtual_modifiers<'de, D>(deserializer: D) -> Result<Vec<Key>, D::Error>
 where
     D: Deserializer<'de>,
@@ -136,6 +501,24 @@ where
     return Ok(keys);
 }
 
+fn deserialize_modifier_order<'de, D>(deserializer: D) -> Result<Vec<Key>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let key_strs = Vec::<String>::deserialize(deserializer)?;
+    key_strs
+        .iter()
+        .map(|key_str| parse_modifier_key(key_str).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn default_compose_key() -> KeyPress {
+    KeyPress {
+        key: Key::KEY_COMPOSE,
+        modifiers: vec![],
+    }
+}
+
 fn const_true() -> bool {
     true
 }
@@ -1,9 +1,11 @@
 use crate::config::application::OnlyOrNot;
-use crate::config::key::deserialize_key;
+use crate::config::key::{deserialize_key, parse_key};
 use crate::config::modmap_action::ModmapAction;
 use evdev::Key;
+use serde::de::{self, IgnoredAny, MapAccess, Visitor};
 use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
+use std::fmt;
 
 use super::device::Device;
 
@@ -13,6 +15,8 @@ pub struct Modmap {
     #[allow(dead_code)]
     #[serde(default = "String::new")]
     pub name: String,
+    // Logged and traced when a remap of this modmap matches
+    pub description: Option<String>,
     #[serde(deserialize_with = "deserialize_remap")]
     pub remap: HashMap<Key, ModmapAction>,
     pub application: Option<OnlyOrNot>,
@@ -27,6 +31,76 @@ where
     #[derive(Deserialize, Eq, Hash, PartialEq)]
     struct KeyWrapper(#[serde(deserialize_with = "deserialize_key")] Key);
 
-    let v = HashMap::<KeyWrapper, ModmapAction>::deserialize(deserializer)?;
-    Ok(v.into_iter().map(|(KeyWrapper(k), v)| (k, v)).collect())
+    // `a: x` or `{a, b, c}: x`
+    struct Sources(Vec<Key>);
+
+    impl<'de> Deserialize<'de> for Sources {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            struct SourcesVisitor;
+
+            impl<'de> Visitor<'de> for SourcesVisitor {
+                type Value = Sources;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_str("a key or a set of keys like {a, b}")
+                }
+
+                fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+                where
+                    E: de::Error,
+                {
+                    Ok(Sources(vec![parse_key(value).map_err(de::Error::custom)?]))
+                }
+
+                // Number keys like `1` are integers in YAML
+                fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+                where
+                    E: de::Error,
+                {
+                    self.visit_str(&value.to_string())
+                }
+
+                fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
+                where
+                    M: MapAccess<'de>,
+                {
+                    let mut keys = vec![];
+                    while let Some((KeyWrapper(key), IgnoredAny)) = map.next_entry()? {
+                        keys.push(key);
+                    }
+                    Ok(Sources(keys))
+                }
+            }
+
+            deserializer.deserialize_any(SourcesVisitor)
+        }
+    }
+
+    struct RemapVisitor;
+
+    impl<'de> Visitor<'de> for RemapVisitor {
+        type Value = HashMap<Key, ModmapAction>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map from keys to modmap actions")
+        }
+
+        fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
+        where
+            M: MapAccess<'de>,
+        {
+            let mut remap = HashMap::new();
+            while let Some((Sources(keys), action)) = map.next_entry::<Sources, ModmapAction>()? {
+                for key in keys {
+                    remap.insert(key, action.clone());
+                }
+            }
+            Ok(remap)
+        }
+    }
+
+    deserializer.deserialize_map(RemapVisitor)
 }
@@ -1,11 +1,13 @@
 use crate::config::key::deserialize_key;
 use evdev::Key;
-use serde::{Deserialize, Deserializer};
-use serde_with::{serde_as, DurationMilliSeconds};
+use serde::{de, Deserialize, Deserializer};
+use serde_with::serde_as;
+use std::collections::BTreeMap;
 use std::time::Duration;
 
 use super::{
     deserialize_virtual_modifiers,
+    duration::Millis,
     keymap_action::{Actions, KeymapAction},
 };
 
@@ -15,8 +17,13 @@ use super::{
 pub enum ModmapAction {
     #[serde(deserialize_with = "deserialize_key")]
     Key(Key),
+    // Press in order, and release in reverse
+    #[serde(deserialize_with = "deserialize_virtual_modifiers")]
+    Keys(Vec<Key>),
     MultiPurposeKey(MultiPurposeKey),
     PressReleaseKey(PressReleaseKey),
+    DoubleTapKey(DoubleTapKey),
+    TapCountKey(TapCountKey),
 }
 
 #[serde_as]
@@ -24,9 +31,48 @@ pub enum ModmapAction {
 pub struct MultiPurposeKey {
     pub held: Keys,
     pub alone: Keys,
-    #[serde_as(as = "DurationMilliSeconds")]
+    #[serde_as(as = "Millis")]
     #[serde(default = "default_alone_timeout", rename = "alone_timeout_millis")]
     pub alone_timeout: Duration,
+    #[serde(default)]
+    pub tap_hold_policy: TapHoldPolicy,
+}
+
+// How a MultiPurposeKey pressed with other keys within alone_timeout is resolved
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TapHoldPolicy {
+    // Held as soon as another key is pressed
+    #[default]
+    HoldPreferred,
+    // Held if another key is pressed and released before it. Other keys are delayed until then.
+    Permissive,
+    // Only alone_timeout decides it. Other keys are dispatched right away.
+    TimeoutOnly,
+}
+
+// The key works as usual, but pressing it again within the timeout after a tap emits double_tap instead
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DoubleTapKey {
+    pub double_tap: Keys,
+    #[serde_as(as = "Millis")]
+    #[serde(default = "default_double_tap_timeout", rename = "double_tap_timeout_millis")]
+    pub double_tap_timeout: Duration,
+}
+
+// Tapping the key a number of times, each within tap_timeout of the previous one, dispatches the actions
+// for the count. They're dispatched once tap_timeout passes, or right away on the largest count.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TapCountKey {
+    #[serde(deserialize_with = "deserialize_taps")]
+    pub taps: BTreeMap<usize, Vec<KeymapAction>>,
+    #[serde_as(as = "Millis")]
+    #[serde(default = "default_tap_timeout", rename = "tap_timeout_millis")]
+    pub tap_timeout: Duration,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -65,6 +111,28 @@ where
     return Ok(actions.into_vec());
 }
 
+fn deserialize_taps<'de, D>(deserializer: D) -> Result<BTreeMap<usize, Vec<KeymapAction>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let taps = BTreeMap::<usize, Actions>::deserialize(deserializer)?;
+    if taps.contains_key(&0) {
+        return Err(de::Error::custom("the count of taps must be 1 or more"));
+    }
+    Ok(taps
+        .into_iter()
+        .map(|(count, actions)| (count, actions.into_vec()))
+        .collect())
+}
+
 fn default_alone_timeout() -> Duration {
     Duration::from_millis(1000)
 }
+
+fn default_double_tap_timeout() -> Duration {
+    Duration::from_millis(300)
+}
+
+fn default_tap_timeout() -> Duration {
+    Duration::from_millis(300)
+}
@@ -0,0 +1,30 @@
+use serde::Deserialize;
+use serde_with::serde_as;
+use std::time::Duration;
+
+use super::duration::Millis;
+
+// Sum mouse movements within a window before remapping them, to save CPU with high polling rate mice
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MouseMovementCoalescing {
+    #[serde(default)]
+    pub enable: bool,
+    #[serde_as(as = "Millis")]
+    #[serde(default = "default_window", rename = "window_millis")]
+    pub window: Duration,
+}
+
+impl Default for MouseMovementCoalescing {
+    fn default() -> Self {
+        MouseMovementCoalescing {
+            enable: false,
+            window: default_window(),
+        }
+    }
+}
+
+fn default_window() -> Duration {
+    Duration::from_millis(4)
+}
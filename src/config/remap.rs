@@ -1,11 +1,13 @@
 use evdev::Key;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_with::serde_as;
 
 use crate::config::key_press::KeyPress;
 use crate::config::keymap_action::KeymapAction;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::time::Duration;
 
+use super::duration::{format_duration, Millis};
 use super::keymap_action::Actions;
 
 #[derive(Clone, Debug)]
@@ -13,12 +15,84 @@ pub struct Remap {
     pub remap: HashMap<KeyPress, Vec<KeymapAction>>,
     pub timeout: Option<Duration>,
     pub timeout_key: Option<Key>,
+    // Emit the keys of the sequence if it's not completed, instead of timeout_key
+    pub replay: bool,
+    // Dispatched after timeout_key when the timeout is reached
+    pub on_timeout: Vec<KeymapAction>,
+    pub on_unmatched: OnUnmatched,
+    // Emit the key that entered the remap if it's released within this duration before any other key
+    pub literal_timeout: Option<Duration>,
+}
+
+// What a key that isn't in the nested remap does
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnUnmatched {
+    // Emit timeout_key, or the keys of the sequence with { replay: true }, and remap the key by keymap
+    #[default]
+    Replay,
+    // Remap the key by keymap as if the nested remap weren't active
+    Passthrough,
+    // Abort the nested remap and drop the key
+    Swallow,
 }
 
 // USed only for deserialization
+#[serde_as]
 #[derive(Debug, Deserialize)]
 pub struct RemapActions {
     pub remap: HashMap<KeyPress, Actions>,
-    pub timeout_millis: Option<u64>,
+    #[serde_as(as = "Option<Millis>")]
+    #[serde(default)]
+    pub timeout_millis: Option<Duration>,
     pub timeout_key: Option<String>,
+    #[serde(default)]
+    pub replay: bool,
+    #[serde(default)]
+    pub on_timeout: Option<Actions>,
+    #[serde(default)]
+    pub on_unmatched: OnUnmatched,
+    #[serde_as(as = "Option<Millis>")]
+    #[serde(default)]
+    pub literal_timeout_millis: Option<Duration>,
+}
+
+// Same as RemapActions, with the remaps sorted for --dump-config
+#[derive(Serialize)]
+struct RemapConfig<'a> {
+    remap: BTreeMap<String, &'a Vec<KeymapAction>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout_millis: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout_key: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    replay: bool,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    on_timeout: &'a [KeymapAction],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    on_unmatched: Option<OnUnmatched>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    literal_timeout_millis: Option<String>,
+}
+
+impl Serialize for Remap {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        RemapConfig {
+            remap: self
+                .remap
+                .iter()
+                .map(|(key_press, actions)| (key_press.to_string(), actions))
+                .collect(),
+            timeout_millis: self.timeout.as_ref().map(format_duration),
+            timeout_key: self.timeout_key.map(|key| format!("{:?}", key)),
+            replay: self.replay,
+            on_timeout: &self.on_timeout,
+            on_unmatched: Some(self.on_unmatched).filter(|on_unmatched| *on_unmatched != OnUnmatched::default()),
+            literal_timeout_millis: self.literal_timeout.as_ref().map(format_duration),
+        }
+        .serialize(serializer)
+    }
 }
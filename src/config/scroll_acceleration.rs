@@ -0,0 +1,46 @@
+use serde::{de, Deserialize, Deserializer};
+use serde_with::serde_as;
+use std::time::Duration;
+
+use super::duration::Millis;
+
+// Emit a mapped scroll more than once when scrolling fast
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScrollAcceleration {
+    // Scrolls within this window are counted to measure the scroll rate
+    #[serde_as(as = "Millis")]
+    #[serde(default = "default_window", rename = "window_millis")]
+    pub window: Duration,
+    #[serde(default = "default_min_multiplier")]
+    pub min_multiplier: u32,
+    #[serde(default = "default_max_multiplier")]
+    pub max_multiplier: u32,
+}
+
+pub fn deserialize_scroll_acceleration<'de, D>(deserializer: D) -> Result<Option<ScrollAcceleration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let acceleration = ScrollAcceleration::deserialize(deserializer)?;
+    if acceleration.min_multiplier == 0 {
+        return Err(de::Error::custom("scroll_acceleration.min_multiplier must be greater than 0"));
+    }
+    if acceleration.min_multiplier > acceleration.max_multiplier {
+        return Err(de::Error::custom("scroll_acceleration.min_multiplier must not be greater than max_multiplier"));
+    }
+    Ok(Some(acceleration))
+}
+
+fn default_window() -> Duration {
+    Duration::from_millis(200)
+}
+
+fn default_min_multiplier() -> u32 {
+    1
+}
+
+fn default_max_multiplier() -> u32 {
+    4
+}
@@ -0,0 +1,14 @@
+use serde::Deserialize;
+
+// How often a SYN_REPORT is emitted to the output device
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SynReport {
+    // After every event. Mouse movements along both axes move the cursor differently when split.
+    Event,
+    // After all events of an action, e.g. a key press
+    #[default]
+    Action,
+    // After all actions created by an input event. Delays report the pending events first.
+    Batch,
+}
@@ -1,5 +1,18 @@
+use crate::config::dump::dump_config;
+use crate::config::key::parse_key;
+use crate::config::keymap::{build_keymap_table, find_conflicts};
+use crate::config::keymap_action::KeymapAction;
+use crate::config::syn_report::SynReport;
+use crate::config::virtual_device::Capability;
+use crate::config::{check_self_key, load_configs, parse_yaml};
+use crate::event::InputDeviceInfo;
 use crate::Config;
+use evdev::{BusType, Key};
 use indoc::indoc;
+use std::env::temp_dir;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 extern crate serde_yaml;
 extern crate toml;
@@ -101,6 +114,51 @@ fn test_yaml_modmap_press_release_key() {
     "#})
 }
 
+#[test]
+fn test_yaml_modmap_many_to_one_and_one_to_many() {
+    yaml_assert_parse(indoc! {"
+    modmap:
+      - remap:
+          {a, b, c}: x
+          1: [Shift_L, KEY_2]
+    "})
+}
+
+#[test]
+fn test_yaml_modmap_invalid_key_in_set() {
+    let config: Result<Config, _> = serde_yaml::from_str(indoc! {"
+    modmap:
+      - remap:
+          {a, unknown_key}: x
+    "});
+    assert!(config.is_err());
+}
+
+#[test]
+fn test_yaml_keymap_any_modifiers() {
+    yaml_assert_parse(indoc! {"
+    keymap:
+      - remap:
+          '*-f': down
+          '*-C-g': up
+    "})
+}
+
+#[test]
+fn test_media_key_aliases() {
+    use crate::config::key::parse_key;
+    assert_eq!(Key::KEY_VOLUMEUP, parse_key("volumeup").unwrap());
+    assert_eq!(Key::KEY_VOLUMEUP, parse_key("Volume_Up").unwrap());
+    assert_eq!(Key::KEY_BRIGHTNESSDOWN, parse_key("brightnessdown").unwrap());
+    assert_eq!(Key::KEY_BRIGHTNESSDOWN, parse_key("brightness_down").unwrap());
+    assert_eq!(Key::KEY_PLAYPAUSE, parse_key("playpause").unwrap());
+    assert_eq!(Key::KEY_PLAYPAUSE, parse_key("play_pause").unwrap());
+    assert_eq!(Key::KEY_PREVIOUSSONG, parse_key("prev_track").unwrap());
+    // evdev names still take precedence over aliases
+    assert_eq!(Key::KEY_PAUSE, parse_key("pause").unwrap());
+    assert!(parse_key("volume_sideways").is_err());
+}
+
 #[test]
 fn test_yaml_keymap_basic() {
     yaml_assert_parse(indoc! {"
@@ -199,6 +257,19 @@ fn test_yaml_keymap_mode() {
     "})
 }
 
+#[test]
+fn test_yaml_keymap_led() {
+    yaml_assert_parse(indoc! {"
+    keymap:
+      - led: numlock
+        remap:
+          KP1: End
+      - led: [LED_NUML, CapsL]
+        remap:
+          KP2: Down
+    "})
+}
+
 #[test]
 fn test_yaml_keymap_mark() {
     yaml_assert_parse(indoc! {"
@@ -231,6 +302,43 @@ fn test_yaml_shared_data_anchor() {
     "})
 }
 
+#[test]
+fn test_yaml_shared_remap_merge() {
+    let config = parse_yaml(indoc! {"
+    shared:
+      vim: &vim
+        C-h: left
+        C-l: right
+
+    keymap:
+      - application:
+          only: Firefox
+        remap:
+          <<: *vim
+          C-l: C-l
+      - remap: *vim
+    "})
+    .unwrap();
+
+    // Overriding C-l in the first keymap doesn't affect the other one sharing the anchor
+    let table = build_keymap_table(&config.keymap, config.default_exact_match, config.merge_strategy);
+    let actions: Vec<Vec<Key>> = table[&Key::KEY_L]
+        .iter()
+        .map(|entry| {
+            entry
+                .actions
+                .iter()
+                .map(|action| match action {
+                    KeymapAction::KeyPress(key_press) => key_press.key,
+                    action => panic!("unexpected action: {:?}", action),
+                })
+                .collect()
+        })
+        .collect();
+    assert_eq!(vec![vec![Key::KEY_L], vec![Key::KEY_RIGHT]], actions);
+    assert_eq!(2, table[&Key::KEY_H].len());
+}
+
 #[test]
 fn test_yaml_fail_on_data_outside_of_config_model() {
     let errmsg = serde_yaml::from_str::<Config>(indoc! {"
@@ -553,6 +661,433 @@ fn test_toml_no_keymap_action() {
     "})
 }
 
+#[test]
+fn test_include() {
+    let dir = config_dir("include");
+    fs::write(
+        dir.join("config.yml"),
+        indoc! {"
+        include: [base.yml, gaming.toml]
+        keymap:
+          - remap:
+              C-x:
+                remap:
+                  h: C-a
+        "},
+    )
+    .unwrap();
+    fs::write(
+        dir.join("base.yml"),
+        indoc! {"
+        keymap:
+          - remap:
+              C-x:
+                remap:
+                  k: C-w
+        "},
+    )
+    .unwrap();
+    fs::write(
+        dir.join("gaming.toml"),
+        indoc! {"
+        [[modmap]]
+        [modmap.remap]
+        CapsLock = \"Esc\"
+        "},
+    )
+    .unwrap();
+
+    let config = load_configs(&vec![dir.join("config.yml")]).unwrap();
+    assert_eq!(config.keymap.len(), 2);
+    assert_eq!(config.modmap.len(), 1);
+    // Nested remaps of all files are merged
+    assert_eq!(config.keymap_table.values().next().unwrap().len(), 2);
+    // Included files are watched by --watch=config too
+    assert_eq!(
+        config.files,
+        vec![
+            dir.join("config.yml"),
+            dir.join("base.yml").canonicalize().unwrap(),
+            dir.join("gaming.toml").canonicalize().unwrap(),
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_self_key() {
+    let check = |yaml| check_self_key(&parse_yaml(yaml).unwrap());
+    assert!(check("keymap: [{ remap: { ANY: { repeat: { key: C-<self>, count: 1 } } } }]").is_ok());
+    assert!(check("keymap: [{ remap: { <self>: a } }]").is_err());
+    assert!(check("keymap: [{ remap: { C-x: { remap: { <self>: a } } } }]").is_err());
+    assert!(check("modmap: [{ remap: { <self>: a } }]").is_err());
+    assert!(check("on_startup: { if: \"true\", then: [<self>] }").is_err());
+}
+
+#[test]
+fn test_include_cycle() {
+    let dir = config_dir("include_cycle");
+    fs::write(dir.join("a.yml"), "include: [b.yml]").unwrap();
+    fs::write(dir.join("b.yml"), "include: [a.yml]").unwrap();
+
+    let errmsg = load_configs(&vec![dir.join("a.yml")]).unwrap_err().to_string();
+    assert!(errmsg.contains("Include cycle detected at"), "{}", errmsg);
+    assert!(errmsg.contains("a.yml"), "{}", errmsg);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_include_diamond() {
+    let dir = config_dir("include_diamond");
+    fs::write(dir.join("a.yml"), "strict: true\ninclude: [b.yml, c.yml]").unwrap();
+    fs::write(dir.join("b.yml"), "include: [d.yml]").unwrap();
+    fs::write(dir.join("c.yml"), "include: [d.yml]").unwrap();
+    fs::write(dir.join("d.yml"), "keymap: [{ remap: { a: b } }]").unwrap();
+
+    // d.yml is merged once, so its keymap doesn't conflict with itself
+    let config = load_configs(&vec![dir.join("a.yml")]).unwrap();
+    assert_eq!(config.keymap.len(), 1);
+    assert_eq!(config.files.len(), 4);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_rel_aliases() {
+    let dir = config_dir("rel_aliases");
+    fs::write(
+        dir.join("config.yml"),
+        indoc! {"
+        rel_aliases:
+          scroll_up: XUPSCROLL
+        keymap:
+          - remap:
+              C-scroll_up: C-equal
+        "},
+    )
+    .unwrap();
+    // A file loaded after it can use the alias too, even in TOML
+    fs::write(dir.join("later.toml"), "[[modmap]]\nremap = { scroll_up = \"XDOWNSCROLL\" }\n").unwrap();
+    let config = load_configs(&vec![dir.join("config.yml"), dir.join("later.toml")]).unwrap();
+    assert!(config.keymap_table.contains_key(&parse_key("XUPSCROLL").unwrap()));
+    assert!(config.modmap[0].remap.contains_key(&parse_key("XUPSCROLL").unwrap()));
+
+    // but not a config loaded separately, like on a reload
+    assert!(load_configs(&vec![dir.join("later.toml")]).is_err());
+
+    fs::write(dir.join("unknown.yml"), "rel_aliases: { scroll_up: KEY_A }").unwrap();
+    let errmsg = load_configs(&vec![dir.join("unknown.yml")]).unwrap_err().to_string();
+    assert!(errmsg.contains("Use one of XRIGHTCURSOR, XLEFTCURSOR"), "{}", errmsg);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_yaml_passthrough_devices() {
+    let config: Config = serde_yaml::from_str(indoc! {"
+        passthrough_devices:
+          - Some Mouse
+          - /dev/input/event5
+    "})
+    .unwrap();
+    assert_eq!(config.passthrough_devices, vec!["Some Mouse", "/dev/input/event5"]);
+}
+
+#[test]
+fn test_yaml_syn_report() {
+    let config: Config = serde_yaml::from_str("").unwrap();
+    assert_eq!(config.syn_report, SynReport::Action);
+    let config: Config = serde_yaml::from_str("syn_report: batch").unwrap();
+    assert_eq!(config.syn_report, SynReport::Batch);
+}
+
+#[test]
+fn test_yaml_compose() {
+    yaml_assert_parse(indoc! {"
+    compose_key: Scrolllock
+    keymap:
+      - remap:
+          F1: { compose: [grave, a] }
+          F2: { compose: [Shift-apostrophe, e] }
+    "});
+    assert!(serde_yaml::from_str::<Config>("keymap: [{ remap: { F1: { compose: [] } } }]").is_err());
+}
+
+#[test]
+fn test_yaml_sticky_mode() {
+    yaml_assert_parse(indoc! {"
+    keymap:
+      - remap:
+          F1: { set_mode: numpad }
+          F2: { set_mode: { mode: numpad, sticky: false } }
+      - mode: numpad
+        remap:
+          Esc: { clear_mode: true }
+    "})
+}
+
+#[test]
+fn test_yaml_rel_invert() {
+    let config: Config = serde_yaml::from_str(indoc! {"
+        rel_invert:
+          - device:
+              only: Some Mouse
+            invert: [REL_HWHEEL_HI_RES, x]
+    "})
+    .unwrap();
+    assert_eq!(config.rel_invert[0].invert, vec![12, 6, 0]);
+    assert!(serde_yaml::from_str::<Config>("rel_invert: [{ device: { only: a }, invert: [REL_FOO] }]").is_err());
+}
+
+#[test]
+fn test_yaml_virtual_device_bus_type() {
+    let config: Config = serde_yaml::from_str("").unwrap();
+    assert_eq!(config.virtual_device.bus_type, None);
+    let config: Config = serde_yaml::from_str("virtual_device: { bus_type: i2c }").unwrap();
+    assert_eq!(config.virtual_device.bus_type, Some(BusType::BUS_I2C));
+    let config: Config = serde_yaml::from_str("virtual_device: { bus_type: BUS_BLUETOOTH }").unwrap();
+    assert_eq!(config.virtual_device.bus_type, Some(BusType::BUS_BLUETOOTH));
+    assert!(serde_yaml::from_str::<Config>("virtual_device: { bus_type: firewire }").is_err());
+}
+
+#[test]
+fn test_yaml_virtual_devices() {
+    let config: Config = serde_yaml::from_str(indoc! {"
+        virtual_devices:
+          - name: gaming-kbd
+            capabilities: [keyboard]
+          - name: tablet
+    "})
+    .unwrap();
+    assert_eq!(config.virtual_devices[0].capabilities, vec![Capability::Keyboard]);
+    assert_eq!(config.virtual_devices[1].capabilities, vec![Capability::Keyboard, Capability::Pointer]);
+    assert!(serde_yaml::from_str::<Config>("virtual_devices: [{ name: pointer }]").is_err());
+    assert!(serde_yaml::from_str::<Config>("virtual_devices: [{ name: a }, { name: a }]").is_err());
+    assert!(serde_yaml::from_str::<Config>("virtual_devices: [{ name: a, capabilities: [] }]").is_err());
+}
+
+#[test]
+fn test_yaml_on_device() {
+    yaml_assert_parse(indoc! {"
+    keymap:
+      - remap:
+          F1: { device: gaming-kbd, action: [a, b] }
+          F2: { device: gaming-kbd, action: { mouse_move: { x: 10, y: 0 } } }
+    "})
+}
+
+#[test]
+fn test_yaml_layer() {
+    yaml_assert_parse(indoc! {"
+    keymap:
+      - remap:
+          F1:
+            layer:
+              remap:
+                h: left
+                C-l: [right, { set_mode: default }]
+              timeout_ms: 2s
+    "});
+    // The timeout is required
+    assert!(serde_yaml::from_str::<Config>("keymap: [{ remap: { F1: { layer: { remap: { h: left } } } } }]").is_err());
+}
+
+#[test]
+fn test_duration_units() {
+    let config: Config = serde_yaml::from_str(indoc! {"
+        keypress_delay_ms: 5ms
+        mouse_move_interval_ms: 0.5s
+        client_cache_ttl_ms: 1m
+        modmap:
+          - remap:
+              CapsLock:
+                held: Ctrl_L
+                alone: Esc
+                alone_timeout_millis: 1s
+        keymap:
+          - remap:
+              C-x:
+                remap:
+                  a: b
+                timeout_millis: 1.5s
+    "})
+    .unwrap();
+    assert_eq!(config.keypress_delay, Duration::from_millis(5));
+    assert_eq!(config.mouse_move_interval, Duration::from_millis(500));
+    assert_eq!(config.client_cache_ttl, Some(Duration::from_secs(60)));
+
+    // Numbers without a unit are milliseconds
+    let config: Config = serde_yaml::from_str("keypress_delay_ms: 5").unwrap();
+    assert_eq!(config.keypress_delay, Duration::from_millis(5));
+    assert_eq!(config.modifier_delay, None);
+    let config: Config = serde_yaml::from_str("modifier_delay_ms: 20\nkey_delay_ms: 1ms").unwrap();
+    assert_eq!(config.modifier_delay, Some(Duration::from_millis(20)));
+    assert_eq!(config.key_delay, Some(Duration::from_millis(1)));
+    let config: Config = toml::from_str("mouse_move_interval_ms = \"20ms\"").unwrap();
+    assert_eq!(config.mouse_move_interval, Duration::from_millis(20));
+    assert!(serde_yaml::from_str::<Config>("keypress_delay_ms: 5 seconds").is_err());
+    assert!(toml::from_str::<Config>("keypress_delay_ms = -1").is_err());
+}
+
+#[test]
+fn test_dump_config() {
+    let mut config: Config = serde_yaml::from_str(indoc! {"
+        keymap:
+          - remap:
+              C-x:
+                remap:
+                  h: C-a
+          - remap:
+              C-x:
+                remap:
+                  h: C-b
+                  c: C-q
+                timeout_millis: 1s
+          - application:
+              only: /^Fire\\/fox/
+            remap:
+              M-f: { press: ctrl }
+    "})
+    .unwrap();
+    config.keymap_table = build_keymap_table(&config.keymap, config.default_exact_match, config.merge_strategy);
+    let dump = dump_config(&config).unwrap();
+    // Nested remaps of the same key press are merged, and the first one wins
+    assert_eq!(
+        dump,
+        indoc! {r"
+        keymap:
+        - remap:
+            M-KEY_F:
+            - press: KEY_LEFTCTRL
+          application:
+            only:
+            - /^Fire\/fox/
+          exact_match: false
+          on: press
+          repeat: include
+          on_cooldown: swallow
+        - remap:
+            C-KEY_X:
+            - remap:
+                KEY_C:
+                - C-KEY_Q
+                KEY_H:
+                - C-KEY_A
+          exact_match: false
+          on: press
+          repeat: include
+          on_cooldown: swallow
+        "}
+    );
+    // It can be loaded again
+    let mut reloaded: Config = serde_yaml::from_str(&dump).unwrap();
+    reloaded.keymap_table = build_keymap_table(&reloaded.keymap, reloaded.default_exact_match, reloaded.merge_strategy);
+    assert_eq!(dump_config(&reloaded).unwrap(), dump);
+}
+
+#[test]
+fn test_yaml_error_location() {
+    let dir = config_dir("yaml_error_location");
+    let path = dir.join("config.yml");
+    fs::write(
+        &path,
+        indoc! {"
+        keymap:
+          - remap:
+              C-x: nothing_like_an_action
+    "},
+    )
+    .unwrap();
+
+    let errmsg = load_configs(&vec![path.clone()]).unwrap_err().to_string();
+    assert!(errmsg.starts_with(&format!("{}:3:", path.display())), "{}", errmsg);
+    assert!(!errmsg.contains(" at line "), "{}", errmsg);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_toml_error_location() {
+    let dir = config_dir("toml_error_location");
+    let path = dir.join("config.toml");
+    fs::write(&path, "default_mode = \"default\"\nunknown_field = 1\n").unwrap();
+
+    let errmsg = load_configs(&vec![path.clone()]).unwrap_err().to_string();
+    assert!(errmsg.starts_with(&format!("{}:2:1: ", path.display())), "{}", errmsg);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_unmatched_devices() {
+    let keyboard = InputDeviceInfo {
+        name: "Some Keyboard",
+        path: Path::new("/dev/input/event0"),
+    };
+    let mouse = InputDeviceInfo {
+        name: "Some Mouse",
+        path: Path::new("/dev/input/event1"),
+    };
+    let config = |unmatched_devices| -> Config {
+        serde_yaml::from_str(&format!(
+            indoc! {"
+            unmatched_devices: {}
+            keymap:
+              - device:
+                  only: Keyboard
+                remap:
+                  C-b: left
+            "},
+            unmatched_devices
+        ))
+        .unwrap()
+    };
+
+    let remap = config("remap");
+    assert!(remap.remaps_device(&keyboard));
+    assert!(!remap.remaps_device(&mouse));
+    assert!(!remap.is_passthrough_device(&mouse));
+    assert!(!remap.ignores_device(&mouse));
+
+    let passthrough = config("passthrough");
+    assert!(!passthrough.is_passthrough_device(&keyboard));
+    assert!(passthrough.is_passthrough_device(&mouse));
+    assert!(!passthrough.ignores_device(&mouse));
+
+    let ignore = config("ignore");
+    assert!(!ignore.ignores_device(&keyboard));
+    assert!(ignore.ignores_device(&mouse));
+    assert!(!ignore.is_passthrough_device(&mouse));
+}
+
+#[test]
+fn test_remaps_device_binding() {
+    let keyboard = InputDeviceInfo {
+        name: "Some Keyboard",
+        path: Path::new("/dev/input/event0"),
+    };
+    let mouse = InputDeviceInfo {
+        name: "Some Mouse",
+        path: Path::new("/dev/input/event1"),
+    };
+    let config: Config = serde_yaml::from_str(indoc! {"
+        keymap:
+          - device:
+              only: Keyboard
+            remap:
+              C-b: left
+              BTN_SIDE: { device: { only: [Mouse] }, actions: [C-c, C-v] }
+    "})
+    .unwrap();
+    assert!(config.remaps_device(&keyboard));
+    assert!(config.remaps_device(&mouse));
+    // Two actions, not a device and actions
+    let config: Config = serde_yaml::from_str("keymap: [{ remap: { a: [b, c] } }]").unwrap();
+    assert!(config.remaps_device(&mouse));
+}
+
+fn config_dir(name: &str) -> PathBuf {
+    let dir = temp_dir().join(format!("xremap-test-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
 fn toml_assert_parse(toml: &str) {
     let result: Result<Config, toml::de::Error> = toml::from_str(toml);
     if let Err(e) = result {
@@ -566,3 +1101,105 @@ fn yaml_assert_parse(yaml: &str) {
         panic!("{}", e)
     }
 }
+
+#[test]
+fn test_find_conflicts() {
+    let config: Config = serde_yaml::from_str(indoc! {"
+        keymap:
+          - name: emacs
+            application:
+              only: firefox
+            remap:
+              C-M-a: home
+              C-x:
+                remap:
+                  h: C-a
+          - description: more emacs
+            application:
+              only: firefox
+            remap:
+              M-C-a: end
+              C-x:
+                remap:
+                  k: C-w
+          - name: everywhere
+            remap:
+              C-M-a: end
+    "})
+    .unwrap();
+    let table = build_keymap_table(&config.keymap, config.default_exact_match, config.merge_strategy);
+    // Nested remaps are merged, and a keymap for another application doesn't conflict
+    assert_eq!(
+        find_conflicts(&table),
+        vec!["M-C-KEY_A of keymap 'more emacs' is never used since keymap 'emacs' remaps it first"]
+    );
+}
+
+#[test]
+fn test_strict_conflicts() {
+    let dir = config_dir("strict_conflicts");
+    let path = dir.join("config.yml");
+    let config = indoc! {"
+        keymap:
+          - remap:
+              a: b
+          - remap:
+              a: c
+    "};
+    fs::write(&path, config).unwrap();
+    assert!(load_configs(&vec![path.clone()]).is_ok());
+
+    fs::write(&path, format!("strict: true\n{}", config)).unwrap();
+    let errmsg = load_configs(&vec![path.clone()]).unwrap_err().to_string();
+    assert!(errmsg.contains("KEY_A of an unnamed keymap is never used"), "{}", errmsg);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_merge_strategy_error() {
+    let dir = config_dir("merge_strategy_error");
+    let path = dir.join("config.yml");
+    let config = indoc! {"
+        keymap:
+          - name: emacs
+            remap:
+              C-x:
+                remap:
+                  h: C-a
+                  k:
+                    remap:
+                      j: b
+          - name: more emacs
+            remap:
+              C-x:
+                remap:
+                  h: C-b
+                  c: C-q
+                  k:
+                    remap:
+                      j: c
+                      l: d
+    "};
+    fs::write(&path, config).unwrap();
+    assert!(load_configs(&vec![path.clone()]).is_ok());
+
+    fs::write(&path, format!("merge_strategy: error\n{}", config)).unwrap();
+    let errmsg = load_configs(&vec![path.clone()]).unwrap_err().to_string();
+    assert!(
+        errmsg.ends_with(
+            "merge_strategy: error: C-KEY_X KEY_H is remapped by both keymap 'emacs' and keymap 'more emacs', \
+             C-KEY_X KEY_K KEY_J is remapped by both keymap 'emacs' and keymap 'more emacs'"
+        ),
+        "{}",
+        errmsg
+    );
+
+    // Nested remaps that don't share a key are fine
+    fs::write(
+        &path,
+        format!("merge_strategy: error\n{}", config.replace("h: C-b", "g: C-b").replace("j: c", "m: c")),
+    )
+    .unwrap();
+    assert!(load_configs(&vec![path.clone()]).is_ok());
+    fs::remove_dir_all(&dir).unwrap();
+}
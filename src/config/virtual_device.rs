@@ -0,0 +1,74 @@
+use evdev::BusType;
+use serde::{de, Deserialize, Deserializer};
+use std::str::FromStr;
+
+// Settings of the uinput devices that xremap emits events on
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualDevice {
+    // The bus type reported by the devices. Defaults to the one of the first input device, or USB.
+    #[serde(default, deserialize_with = "deserialize_bus_type")]
+    pub bus_type: Option<BusType>,
+}
+
+// Accepts the names of the BUS_* constants with or without the prefix, e.g. usb or BUS_USB
+fn deserialize_bus_type<'de, D>(deserializer: D) -> Result<Option<BusType>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    parse_bus_type(&name).map(Some).map_err(de::Error::custom)
+}
+
+fn parse_bus_type(name: &str) -> Result<BusType, String> {
+    let name = name.to_uppercase();
+    let name = if name.starts_with("BUS_") {
+        name
+    } else {
+        format!("BUS_{}", name)
+    };
+    BusType::from_str(&name).map_err(|_| format!("unknown bus type: {}", name))
+}
+
+// An output device in addition to the default ones, which { device: ..., action: ... } emits on
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NamedVirtualDevice {
+    pub name: String,
+    #[serde(default = "default_capabilities")]
+    pub capabilities: Vec<Capability>,
+}
+
+// Events that a named virtual device declares
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    // Keys other than mouse buttons
+    Keyboard,
+    // Mouse buttons, movements, and wheels
+    Pointer,
+}
+
+fn default_capabilities() -> Vec<Capability> {
+    vec![Capability::Keyboard, Capability::Pointer]
+}
+
+// Names are unique, and "pointer" is taken by the default pointer device
+pub fn deserialize_virtual_devices<'de, D>(deserializer: D) -> Result<Vec<NamedVirtualDevice>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let devices = Vec::<NamedVirtualDevice>::deserialize(deserializer)?;
+    for (i, device) in devices.iter().enumerate() {
+        if device.name.is_empty() || device.name == "pointer" {
+            return Err(de::Error::custom(format!("invalid virtual device name: {:?}", device.name)));
+        }
+        if devices[..i].iter().any(|other| other.name == device.name) {
+            return Err(de::Error::custom(format!("duplicate virtual device name: {}", device.name)));
+        }
+        if device.capabilities.is_empty() {
+            return Err(de::Error::custom(format!("virtual device {} has no capabilities", device.name)));
+        }
+    }
+    Ok(devices)
+}
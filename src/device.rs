@@ -1,18 +1,29 @@
 extern crate evdev;
 extern crate nix;
 
+use crate::config::virtual_device::{Capability, NamedVirtualDevice};
+use crate::config::Config;
+use crate::event::{InputDeviceInfo, KEY_MAX};
 use anyhow::bail;
 use derive_where::derive_where;
 use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
-use evdev::{AttributeSet, BusType, Device, FetchEventsSynced, InputId, Key, RelativeAxisType};
+use evdev::{
+    AttributeSet, BusType, Device, EventType, FetchEventsSynced, InputEvent, InputId, Key, LedType, MiscType,
+    RelativeAxisType,
+};
 use log::{debug, info};
 use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs::read_dir;
+use std::io::{Read, Write};
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixStream;
 use std::os::unix::prelude::AsRawFd;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
 use std::{io, process};
 
 static MOUSE_BTNS: [&str; 20] = [
@@ -41,16 +52,146 @@ static MOUSE_BTNS: [&str; 20] = [
 static mut DEVICE_NAME: Option<String> = None;
 
 // Credit: https://github.com/mooz/xkeysnail/blob/bf3c93b4fe6efd42893db4e6588e5ef1c4909cfb/xkeysnail/output.py#L10-L32
-pub fn output_device(bus_type: Option<BusType>, enable_wheel: bool) -> Result<VirtualDevice, Box<dyn Error>> {
+pub fn output_device(
+    bus_type: Option<BusType>,
+    enable_wheel: bool,
+    enable_hires_wheel: bool,
+    emit_scancodes: bool,
+) -> Result<VirtualDevice, Box<dyn Error>> {
     let mut keys: AttributeSet<Key> = AttributeSet::new();
-    for code in Key::KEY_RESERVED.code()..Key::BTN_TRIGGER_HAPPY40.code() {
+    for code in Key::KEY_RESERVED.code()..=KEY_MAX {
         let key = Key::new(code);
-        let name = format!("{:?}", key);
-        if name.starts_with("KEY_") || MOUSE_BTNS.contains(&&*name) {
+        if is_output_key(key) {
             keys.insert(key);
         }
     }
 
+    let device = VirtualDeviceBuilder::new()?
+        // These are taken from https://docs.rs/evdev/0.12.0/src/evdev/uinput.rs.html#183-188
+        .input_id(InputId::new(bus_type.unwrap_or(BusType::BUS_USB), 0x1234, 0x5678, 0x111))
+        .name(&InputDevice::current_name())
+        .with_keys(&keys)?
+        .with_relative_axes(&output_relative_axes(enable_wheel, enable_hires_wheel))?
+        .with_msc(&output_misc(emit_scancodes))?
+        .build()?;
+    Ok(device)
+}
+
+// With a pointer device, the output device emits only keys, and mouse buttons and movements go here.
+// Some applications get confused by a device that reports both of them.
+pub fn output_devices(
+    bus_type: Option<BusType>,
+    enable_wheel: bool,
+    enable_hires_wheel: bool,
+    emit_scancodes: bool,
+) -> Result<(VirtualDevice, VirtualDevice), Box<dyn Error>> {
+    let bus_type = bus_type.unwrap_or(BusType::BUS_USB);
+    let mut keys: AttributeSet<Key> = AttributeSet::new();
+    let mut buttons: AttributeSet<Key> = AttributeSet::new();
+    for code in Key::KEY_RESERVED.code()..=KEY_MAX {
+        let key = Key::new(code);
+        if is_mouse_button(key) {
+            buttons.insert(key);
+        } else if is_output_key(key) {
+            keys.insert(key);
+        }
+    }
+
+    let keyboard = VirtualDeviceBuilder::new()?
+        .input_id(InputId::new(bus_type, 0x1234, 0x5678, 0x111))
+        .name(&InputDevice::current_name())
+        .with_keys(&keys)?
+        .with_msc(&output_misc(emit_scancodes))?
+        .build()?;
+    let pointer = VirtualDeviceBuilder::new()?
+        .input_id(InputId::new(bus_type, 0x1234, 0x5679, 0x111))
+        .name(&InputDevice::pointer_name())
+        .with_keys(&buttons)?
+        .with_relative_axes(&output_relative_axes(enable_wheel, enable_hires_wheel))?
+        .build()?;
+    Ok((keyboard, pointer))
+}
+
+// A device of `virtual_devices`, named after the output device
+pub fn named_output_device(
+    device: &NamedVirtualDevice,
+    bus_type: Option<BusType>,
+    enable_wheel: bool,
+    enable_hires_wheel: bool,
+    emit_scancodes: bool,
+) -> Result<VirtualDevice, Box<dyn Error>> {
+    let keyboard = device.capabilities.contains(&Capability::Keyboard);
+    let pointer = device.capabilities.contains(&Capability::Pointer);
+    let mut keys: AttributeSet<Key> = AttributeSet::new();
+    for code in Key::KEY_RESERVED.code()..=KEY_MAX {
+        let key = Key::new(code);
+        if (pointer && is_mouse_button(key)) || (keyboard && !is_mouse_button(key) && is_output_key(key)) {
+            keys.insert(key);
+        }
+    }
+
+    let name = format!("{} {}", InputDevice::current_name(), device.name);
+    let mut builder = VirtualDeviceBuilder::new()?
+        .input_id(InputId::new(bus_type.unwrap_or(BusType::BUS_USB), 0x1234, 0x567a, 0x111))
+        .name(&name)
+        .with_keys(&keys)?;
+    if keyboard {
+        builder = builder.with_msc(&output_misc(emit_scancodes))?;
+    }
+    if pointer {
+        builder = builder.with_relative_axes(&output_relative_axes(enable_wheel, enable_hires_wheel))?;
+    }
+    Ok(builder.build()?)
+}
+
+// Keys declared by the output devices, which drop any other key.
+// Codes without a name are declared too for { raw_code: ... }. BTN_* other than mouse buttons
+// are skipped since they'd make the device look like a joystick.
+pub fn is_output_key(key: Key) -> bool {
+    key.code() <= KEY_MAX && (!format!("{:?}", key).starts_with("BTN_") || is_mouse_button(key))
+}
+
+pub fn is_mouse_button(key: Key) -> bool {
+    MOUSE_BTNS.contains(&&*format!("{:?}", key))
+}
+
+// Key codes of the USB HID usages 0x00-0x94 on the keyboard page, as drivers/hid/hid-input.c maps them.
+// 0 for usages without a key code.
+#[rustfmt::skip]
+const HID_KEYBOARD_USAGES: [u16; 0x95] = [
+      0,   0,   0,   0,  30,  48,  46,  32,  18,  33,  34,  35,  23,  36,  37,  38,
+     50,  49,  24,  25,  16,  19,  31,  20,  22,  47,  17,  45,  21,  44,   2,   3,
+      4,   5,   6,   7,   8,   9,  10,  11,  28,   1,  14,  15,  57,  12,  13,  26,
+     27,  43,  43,  39,  40,  41,  51,  52,  53,  58,  59,  60,  61,  62,  63,  64,
+     65,  66,  67,  68,  87,  88,  99,  70, 119, 110, 102, 104, 111, 107, 109, 106,
+    105, 108, 103,  69,  98,  55,  74,  78,  96,  79,  80,  81,  75,  76,  77,  71,
+     72,  73,  82,  83,  86, 127, 116, 117, 183, 184, 185, 186, 187, 188, 189, 190,
+    191, 192, 193, 194, 134, 138, 130, 132, 128, 129, 131, 137, 133, 135, 136, 113,
+    115, 114,   0,   0,   0, 121,   0,  89,  93, 124,  92,  94,  95,   0,   0,   0,
+    122, 123,  90,  91,  85,
+];
+
+// Key codes of the HID usages 0xe0-0xe7, the modifier keys
+const HID_KEYBOARD_MODIFIER_USAGES: [u16; 8] = [29, 42, 56, 125, 97, 54, 100, 126];
+
+// The MSC_SCAN value a USB keyboard reports along with the key, i.e. the HID usage on the
+// keyboard page (0x07). None for keys that aren't on a keyboard, like mouse buttons.
+pub fn hid_scancode(key: Key) -> Option<i32> {
+    let usage = match HID_KEYBOARD_USAGES
+        .iter()
+        .position(|code| *code == key.code() && *code != 0)
+    {
+        Some(usage) => usage,
+        None => {
+            0xe0 + HID_KEYBOARD_MODIFIER_USAGES
+                .iter()
+                .position(|code| *code == key.code())?
+        }
+    };
+    Some(0x70000 | usage as i32)
+}
+
+fn output_relative_axes(enable_wheel: bool, enable_hires_wheel: bool) -> AttributeSet<RelativeAxisType> {
     let mut relative_axes: AttributeSet<RelativeAxisType> = AttributeSet::new();
     relative_axes.insert(RelativeAxisType::REL_X);
     relative_axes.insert(RelativeAxisType::REL_Y);
@@ -58,16 +199,26 @@ pub fn output_device(bus_type: Option<BusType>, enable_wheel: bool) -> Result<Vi
         relative_axes.insert(RelativeAxisType::REL_HWHEEL);
         relative_axes.insert(RelativeAxisType::REL_WHEEL);
     }
+    if enable_hires_wheel {
+        relative_axes.insert(RelativeAxisType::REL_HWHEEL_HI_RES);
+        relative_axes.insert(RelativeAxisType::REL_WHEEL_HI_RES);
+    }
     relative_axes.insert(RelativeAxisType::REL_MISC);
+    relative_axes
+}
 
-    let device = VirtualDeviceBuilder::new()?
-        // These are taken from https://docs.rs/evdev/0.12.0/src/evdev/uinput.rs.html#183-188
-        .input_id(InputId::new(bus_type.unwrap_or(BusType::BUS_USB), 0x1234, 0x5678, 0x111))
-        .name(&InputDevice::current_name())
-        .with_keys(&keys)?
-        .with_relative_axes(&relative_axes)?
-        .build()?;
-    Ok(device)
+fn output_misc(emit_scancodes: bool) -> AttributeSet<MiscType> {
+    let mut misc: AttributeSet<MiscType> = AttributeSet::new();
+    if emit_scancodes {
+        misc.insert(MiscType::MSC_SCAN);
+    }
+    misc
+}
+
+// uinput can't set udev properties, so the output device is named "xremap seat=SEAT" for --seat
+// to let a udev rule give it ID_SEAT. This must be called before any device is selected.
+pub fn set_seat(seat: &str) {
+    InputDevice::set_name(&format!("xremap seat={}", seat));
 }
 
 pub fn device_watcher(watch: bool) -> anyhow::Result<Option<Inotify>> {
@@ -85,6 +236,7 @@ pub fn get_input_devices(
     ignore_opts: &[String],
     mouse: bool,
     watch: bool,
+    config: &Config,
 ) -> anyhow::Result<HashMap<PathBuf, InputDevice>> {
     let mut devices: Vec<_> = InputDevice::devices()?.collect();
     devices.sort();
@@ -115,48 +267,137 @@ pub fn get_input_devices(
         // alternative is `Vec::retain_mut` whenever that gets stabilized
         .filter_map(|mut device| {
             // filter out any not matching devices and devices that error on grab
-            (device.is_input_device(device_opts, ignore_opts, mouse) && device.grab()).then(|| device)
+            (device.is_input_device(device_opts, ignore_opts, mouse) && !device.is_ignored(config) && device.grab())
+                .then(|| device)
         })
         .collect();
 
     info!("{}", SEPARATOR);
     if devices.is_empty() {
         if watch {
-            println!("warning: No device was selected, but --watch is waiting for new devices.");
+            eprintln!("warning: No device was selected, but --watch is waiting for new devices.");
         } else {
             bail!("No device was selected!");
         }
     } else {
-        devices.iter().for_each(InputDevice::print);
+        devices.iter().for_each(|device| device.print_selected(config));
     }
     info!("{}", SEPARATOR);
 
     Ok(devices.into_iter().map(From::from).collect())
 }
 
-#[derive(Debug)]
-pub struct InputDeviceInfo<'a> {
-    pub name: &'a str,
-    pub path: &'a Path,
+// Devices added and removed by rescan_input_devices, as "/dev/input/event3 (AT Keyboard)"
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DeviceChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
 }
 
-impl<'a> InputDeviceInfo<'a> {
-    pub fn matches(&self, filter: &String) -> bool {
-        let filter = filter.as_str();
-        // Check exact matches for explicit selection
-        if self.path.as_os_str() == filter || self.name == filter {
-            return true;
-        }
-        // eventXX shorthand for /dev/input/eventXX
-        if filter.starts_with("event") && self.path.file_name().expect("every device path has a file name") == filter {
-            return true;
+// Select devices again like get_input_devices, for devices that --watch=device didn't notice.
+// New matches are grabbed, and devices that are gone or no longer match are released and returned,
+// so that the keys held on them can be released too.
+pub fn rescan_input_devices(
+    input_devices: &mut HashMap<PathBuf, InputDevice>,
+    device_opts: &[String],
+    ignore_opts: &[String],
+    mouse: bool,
+    grab: bool,
+    config: &Config,
+) -> anyhow::Result<(DeviceChanges, Vec<InputDevice>)> {
+    let mut devices: Vec<_> = InputDevice::devices()?.collect();
+    devices.sort();
+
+    let mut changes = DeviceChanges::default();
+    let mut removed_paths: Vec<PathBuf> = input_devices
+        .iter()
+        .filter(|(path, input_device)| {
+            !devices.iter().any(|device| device.path == **path)
+                || !input_device.is_input_device(device_opts, ignore_opts, mouse)
+                || input_device.is_ignored(config)
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
+    removed_paths.sort();
+    let mut removed_devices = vec![];
+    for path in removed_paths {
+        if let Some(mut input_device) = input_devices.remove(&path) {
+            input_device.ungrab();
+            changes.removed.push(input_device.description());
+            removed_devices.push(input_device);
         }
-        // Allow partial matches for device names
-        if self.name.contains(filter) {
-            return true;
+    }
+    for mut device in devices {
+        if !input_devices.contains_key(&device.path)
+            && device.is_input_device(device_opts, ignore_opts, mouse)
+            && !device.is_ignored(config)
+            && (!grab || device.grab())
+        {
+            device.print_selected(config);
+            changes.added.push(device.description());
+            input_devices.insert(device.path.clone(), device);
         }
-        return false;
     }
+    Ok((changes, removed_devices))
+}
+
+// Where request_rescan sends its reply channel, and the stream it writes a byte to for waking up DeviceRescanner
+type RescanRequester = (Sender<Sender<DeviceChanges>>, UnixStream);
+static RESCAN_REQUESTER: Mutex<Option<RescanRequester>> = Mutex::new(None);
+
+// Receives requests of request_rescan from other threads, e.g. the DBus server of the KDE client,
+// and wakes up select(2) of the main loop, which rescans the devices and replies to them
+pub struct DeviceRescanner {
+    receiver: Receiver<Sender<DeviceChanges>>,
+    wakee: UnixStream,
+}
+
+impl DeviceRescanner {
+    pub fn new() -> io::Result<DeviceRescanner> {
+        let (sender, receiver) = mpsc::channel();
+        let (waker, wakee) = UnixStream::pair()?;
+        wakee.set_nonblocking(true)?;
+        *RESCAN_REQUESTER.lock().unwrap() = Some((sender, waker));
+        Ok(DeviceRescanner { receiver, wakee })
+    }
+
+    // Where the changes are sent, one for each request since the last call
+    pub fn take_requests(&mut self) -> Vec<Sender<DeviceChanges>> {
+        let mut buffer = [0; 64];
+        while matches!(self.wakee.read(&mut buffer), Ok(n) if n > 0) {}
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl AsRawFd for DeviceRescanner {
+    fn as_raw_fd(&self) -> std::os::unix::prelude::RawFd {
+        self.wakee.as_raw_fd()
+    }
+}
+
+// Ask the main loop to rescan the devices, and wait for the changes. None if nothing rescans them in time.
+pub fn request_rescan(timeout: Duration) -> Option<DeviceChanges> {
+    let (sender, receiver) = mpsc::channel();
+    {
+        let mut requester = RESCAN_REQUESTER.lock().unwrap();
+        let (requests, waker) = requester.as_mut()?;
+        requests.send(sender).ok()?;
+        waker.write_all(&[0]).ok()?;
+    }
+    receiver.recv_timeout(timeout).ok()
+}
+
+// Print every readable device for --list-devices, without grabbing any of them
+pub fn list_devices() -> anyhow::Result<()> {
+    let mut devices: Vec<_> = InputDevice::devices()?.collect();
+    devices.sort();
+    if devices.is_empty() {
+        bail!("No readable device was found in /dev/input. Try running it with sudo.");
+    }
+    for device in devices {
+        device.print_details();
+    }
+    Ok(())
 }
 
 #[derive_where(PartialEq, PartialOrd, Ord)]
@@ -164,6 +405,9 @@ pub struct InputDevice {
     path: PathBuf,
     #[derive_where(skip)]
     device: Device,
+    // evdev only reports LED changes, so the LEDs that are already on are reported once
+    #[derive_where(skip)]
+    initial_leds_reported: bool,
 }
 
 impl Eq for InputDevice {}
@@ -179,6 +423,7 @@ impl TryFrom<PathBuf> for InputDevice {
             Ok(Self {
                 device: Device::open(&path)?,
                 path,
+                initial_leds_reported: false,
             })
         } else {
             Err(io::ErrorKind::InvalidInput.into())
@@ -202,7 +447,7 @@ impl AsRawFd for InputDevice {
 impl InputDevice {
     pub fn grab(&mut self) -> bool {
         if let Err(error) = self.device.grab() {
-            println!("Failed to grab device '{}' at '{}' due to: {error}", self.device_name(), self.path.display());
+            eprintln!("Failed to grab device '{}' at '{}' due to: {error}", self.device_name(), self.path.display());
             false
         } else {
             true
@@ -211,7 +456,25 @@ impl InputDevice {
 
     pub fn ungrab(&mut self) {
         if let Err(error) = self.device.ungrab() {
-            println!("Failed to ungrab device '{}' at '{}' due to: {error}", self.device_name(), self.path.display());
+            eprintln!("Failed to ungrab device '{}' at '{}' due to: {error}", self.device_name(), self.path.display());
+        }
+    }
+
+    // Turn an LED of the device on or off, e.g. for { set_led: ... }. Devices without the LED are left alone.
+    pub fn set_led(&mut self, led: LedType, on: bool) {
+        if !self.device.supported_leds().is_some_and(|leds| leds.contains(led)) {
+            return;
+        }
+        if let Err(error) = self
+            .device
+            .send_events(&[InputEvent::new(EventType::LED, led.0, on as i32)])
+        {
+            eprintln!(
+                "Failed to set {:?} of device '{}' at '{}' due to: {error}",
+                led,
+                self.device_name(),
+                self.path.display()
+            );
         }
     }
 
@@ -219,14 +482,41 @@ impl InputDevice {
         self.device.fetch_events()
     }
 
+    // LED events for the LEDs that are on. Returns nothing after the first call.
+    pub fn initial_led_events(&mut self) -> Vec<InputEvent> {
+        if self.initial_leds_reported {
+            return vec![];
+        }
+        self.initial_leds_reported = true;
+        match self.device.get_led_state() {
+            Ok(leds) => leds
+                .iter()
+                .map(|led| InputEvent::new(EventType::LED, led.0, 1))
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
     fn device_name(&self) -> &str {
         self.device.name().unwrap_or("<Unnamed device>")
     }
 
+    fn description(&self) -> String {
+        format!("{} ({})", self.path.display(), self.device_name())
+    }
+
     pub fn bus_type(&self) -> BusType {
         self.device.input_id().bus_type()
     }
 
+    // Keys pressed on the device as of the last fetched events
+    pub fn held_keys(&self) -> Vec<Key> {
+        match self.device.cached_state().key_vals() {
+            Some(keys) => keys.iter().collect(),
+            None => vec![],
+        }
+    }
+
     pub fn to_info(&self) -> InputDeviceInfo {
         InputDeviceInfo {
             name: self.device_name(),
@@ -237,7 +527,7 @@ impl InputDevice {
 
 impl InputDevice {
     pub fn is_input_device(&self, device_filter: &[String], ignore_filter: &[String], mouse: bool) -> bool {
-        if self.device_name() == Self::current_name() {
+        if self.is_output_device() {
             return false;
         }
         (if device_filter.is_empty() {
@@ -258,18 +548,32 @@ impl InputDevice {
     #[allow(static_mut_refs)]
     fn current_name() -> &'static str {
         if unsafe { DEVICE_NAME.is_none() } {
-            let device_name = if Self::has_device_name("xremap") {
-                format!("xremap pid={}", process::id())
-            } else {
-                "xremap".to_string()
-            };
-            unsafe {
-                DEVICE_NAME = Some(device_name);
-            }
+            Self::set_name("xremap");
         }
         unsafe { DEVICE_NAME.as_ref() }.unwrap()
     }
 
+    fn pointer_name() -> String {
+        format!("{} pointer", Self::current_name())
+    }
+
+    fn is_output_device(&self) -> bool {
+        // The pointer device and the ones of `virtual_devices` are named "<name> <suffix>"
+        self.device_name() == Self::current_name()
+            || self.device_name().starts_with(&format!("{} ", Self::current_name()))
+    }
+
+    fn set_name(name: &str) {
+        let device_name = if Self::has_device_name(name) {
+            format!("{} pid={}", name, process::id())
+        } else {
+            name.to_string()
+        };
+        unsafe {
+            DEVICE_NAME = Some(device_name);
+        }
+    }
+
     fn has_device_name(device_name: &str) -> bool {
         let devices: Vec<_> = match Self::devices() {
             Ok(devices) => devices.collect(),
@@ -282,7 +586,7 @@ impl InputDevice {
 
     fn matches_any(&self, filter: &[String]) -> bool {
         // Force unmatch its own device
-        if self.device_name() == Self::current_name() {
+        if self.is_output_device() {
             return false;
         }
         return filter.iter().any(|f| self.to_info().matches(f));
@@ -310,6 +614,81 @@ impl InputDevice {
     pub fn print(&self) {
         info!("{:18}: {}", self.path.display(), self.device_name())
     }
+
+    // Line of --list-devices, e.g. "/dev/input/event3: AT Keyboard (0001:0001) [KEY]"
+    fn print_details(&self) {
+        let input_id = self.device.input_id();
+        let event_types: Vec<&str> = [
+            (EventType::KEY, "KEY"),
+            (EventType::RELATIVE, "REL"),
+            (EventType::ABSOLUTE, "ABS"),
+        ]
+        .into_iter()
+        .filter(|(event_type, _)| self.device.supported_events().contains(*event_type))
+        .map(|(_, name)| name)
+        .collect();
+        println!(
+            "{:18}: {} ({:04x}:{:04x}) [{}]",
+            self.path.display(),
+            self.device_name(),
+            input_id.vendor(),
+            input_id.product(),
+            event_types.join(", ")
+        )
+    }
+
+    // Like print, but tells whether its events are remapped
+    pub fn print_selected(&self, config: &Config) {
+        if config.is_passthrough_device(&self.to_info()) {
+            info!("{:18}: {} (passthrough)", self.path.display(), self.device_name())
+        } else {
+            self.print()
+        }
+    }
+
+    // Skip devices by unmatched_devices: ignore
+    pub fn is_ignored(&self, config: &Config) -> bool {
+        let ignored = config.ignores_device(&self.to_info());
+        if ignored {
+            info!("Skipped {} ({}) since no modmap or keymap applies to it", self.path.display(), self.device_name());
+        }
+        ignored
+    }
 }
 
 const SEPARATOR: &str = "------------------------------------------------------------------------------";
+
+#[cfg(test)]
+mod tests {
+    use super::{request_rescan, DeviceChanges, DeviceRescanner};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_request_rescan() {
+        let mut rescanner = DeviceRescanner::new().unwrap();
+        let request = thread::spawn(|| request_rescan(Duration::from_secs(5)));
+        let changes = DeviceChanges {
+            added: vec!["/dev/input/event3 (AT Keyboard)".into()],
+            removed: vec![],
+        };
+        // The main loop is woken up by the request
+        let mut requests = vec![];
+        while requests.is_empty() {
+            requests = rescanner.take_requests();
+        }
+        for request in requests {
+            request.send(changes.clone()).unwrap();
+        }
+        assert_eq!(request.join().unwrap(), Some(changes));
+
+        // Requests that aren't replied to fail
+        let request = thread::spawn(|| request_rescan(Duration::from_secs(5)));
+        let mut requests = vec![];
+        while requests.is_empty() {
+            requests = rescanner.take_requests();
+        }
+        drop(requests);
+        assert_eq!(request.join().unwrap(), None);
+    }
+}
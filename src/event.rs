@@ -1,33 +1,55 @@
-use evdev::{EventType, InputEvent, Key};
+use evdev::{EventType, InputEvent, Key, LedType};
+use serde::{Serialize, Serializer};
+use std::path::Path;
 
-use crate::device::InputDeviceInfo;
-
-// Input to EventHandler. This should only contain things that are easily testable.
+/// Input to EventHandler. This should only contain things that are easily testable.
 #[derive(Debug)]
 pub enum Event<'a> {
-    // InputEvent (EventType::KEY) sent from evdev
+    /// InputEvent (EventType::KEY) sent from evdev
     KeyEvent(InputDeviceInfo<'a>, KeyEvent),
-    // InputEvent (EventType::Relative) sent from evdev
+    /// InputEvent (EventType::Relative) sent from evdev
     RelativeEvent(InputDeviceInfo<'a>, RelativeEvent),
-    // Any other InputEvent type sent from evdev
+    /// InputEvent (EventType::LED) sent from evdev
+    LedEvent(InputDeviceInfo<'a>, LedEvent),
+    /// Any other InputEvent type sent from evdev
     OtherEvents(InputEvent),
-    // Timer for nested override reached its timeout
+    /// Timer reached its timeout, of nested override, mouse_move, taps and the like
     OverrideTimeout,
+    /// xremap started. Dispatch on_startup actions
+    Startup,
+    /// Config is reloaded. Release keys left pressed by { press: key }
+    ConfigReload,
+    /// SIGTERM or SIGINT is received. Release keys pressed on the output device
+    Shutdown,
+    /// SIGUSR1 is received. Print how many times each keymap entry has matched
+    PrintMatchCounts,
+    /// The command of Action::Predicate exited, with status 0 or not
+    PredicateResult(String, bool),
+    /// Interval of Action::PollTitle elapsed
+    TitlePoll,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct KeyEvent {
+    #[serde(serialize_with = "serialize_key")]
     pub key: Key,
     value: KeyValue,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct RelativeEvent {
     pub code: u16,
     pub value: i32,
 }
 
 #[derive(Debug)]
+pub struct LedEvent {
+    pub led: LedType,
+    pub on: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum KeyValue {
     Press,
     Release,
@@ -39,6 +61,7 @@ impl<'a> Event<'a> {
         let event = match event.event_type() {
             EventType::KEY => Event::KeyEvent(device, KeyEvent::new_with(event.code(), event.value())),
             EventType::RELATIVE => Event::RelativeEvent(device, RelativeEvent::new_with(event.code(), event.value())),
+            EventType::LED => Event::LedEvent(device, LedEvent::new_with(event.code(), event.value())),
             _ => Event::OtherEvents(event),
         };
         event
@@ -74,6 +97,15 @@ impl RelativeEvent {
     }
 }
 
+impl LedEvent {
+    pub fn new_with(code: u16, value: i32) -> LedEvent {
+        LedEvent {
+            led: LedType(code),
+            on: value != 0,
+        }
+    }
+}
+
 impl KeyValue {
     fn new(value: i32) -> Option<KeyValue> {
         let event_value = match value {
@@ -93,3 +125,39 @@ impl KeyValue {
         }
     }
 }
+
+// Key names like "KEY_A"
+pub fn serialize_key<S>(key: &Key, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_str(&format_args!("{:?}", key))
+}
+
+// The largest key code uinput accepts
+pub const KEY_MAX: u16 = 0x2ff;
+
+#[derive(Debug)]
+pub struct InputDeviceInfo<'a> {
+    pub name: &'a str,
+    pub path: &'a Path,
+}
+
+impl<'a> InputDeviceInfo<'a> {
+    pub fn matches(&self, filter: &String) -> bool {
+        let filter = filter.as_str();
+        // Check exact matches for explicit selection
+        if self.path.as_os_str() == filter || self.name == filter {
+            return true;
+        }
+        // eventXX shorthand for /dev/input/eventXX
+        if filter.starts_with("event") && self.path.file_name().expect("every device path has a file name") == filter {
+            return true;
+        }
+        // Allow partial matches for device names
+        if self.name.contains(filter) {
+            return true;
+        }
+        return false;
+    }
+}
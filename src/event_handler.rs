@@ -2,96 +2,356 @@ use crate::action::Action;
 use crate::client::WMClient;
 use crate::config::application::OnlyOrNot;
 use crate::config::key_press::{KeyPress, Modifier};
-use crate::config::keymap::{build_override_table, OverrideEntry};
-use crate::config::keymap_action::KeymapAction;
-use crate::config::modmap_action::{Keys, ModmapAction, MultiPurposeKey, PressReleaseKey};
-use crate::config::remap::Remap;
-use crate::device::InputDeviceInfo;
-use crate::event::{Event, KeyEvent, RelativeEvent};
+use crate::config::keymap::{
+    build_override_table, Condition, KeymapEntry, KeymapRepeat, KeymapTrigger, OnCooldown, OverrideEntry,
+};
+use crate::config::keymap_action::{Conditional, KeymapAction, MouseMove};
+use crate::config::modmap_action::{
+    DoubleTapKey, Keys, ModmapAction, MultiPurposeKey, PressReleaseKey, TapCountKey, TapHoldPolicy,
+};
+use crate::config::remap::{OnUnmatched, Remap};
+use crate::config::scroll_acceleration::ScrollAcceleration;
+use crate::event::{Event, KeyEvent, LedEvent, RelativeEvent};
+use crate::event::{InputDeviceInfo, KEY_MAX};
+use crate::timer::Timer;
 use crate::{config, Config};
-use evdev::Key;
+use evdev::{EventType, Key, LedType};
 use lazy_static::lazy_static;
 use log::debug;
 use log::error;
-use nix::sys::time::TimeSpec;
-use nix::sys::timerfd::{Expiration, TimerFd, TimerSetTimeFlags};
+use log::warn;
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::error::Error;
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
+// How many RELATIVE event codes can be disguised as keys, i.e. REL_CNT of the kernel.
+// Each of them takes two keycodes, one for positive and one for negative values.
+pub const DISGUISED_EVENT_AXES: u16 = 0x10;
+
 // This const is a value used to offset RELATIVE events' scancodes
 // so that they correspond to the custom aliases created in config::key::parse_key.
-// This offset also prevents resulting scancodes from corresponding to non-Xremap scancodes,
-// to prevent conflating disguised relative events with other events.
-pub const DISGUISED_EVENT_OFFSETTER: u16 = 59974;
+// The disguised events, KEY_MATCH_ANY, KEY_MATCH_ANY_INCLUDING_MODIFIERS, and KEY_SELF take the top of the u16 range,
+// as far as possible from the real scancodes, which grow from 0 as evdev adds keys.
+pub const DISGUISED_EVENT_OFFSETTER: u16 = u16::MAX - (DISGUISED_EVENT_AXES * 2 + 3) + 1;
+
+// Real scancodes go up to KEY_MAX, so they never collide with the disguised events
+const _: () = assert!(KEY_MAX < DISGUISED_EVENT_OFFSETTER);
 
 // This const is defined a keycode for a configuration key used to match any key.
-// It's the offset of XHIRES_LEFTSCROLL + 1
-pub const KEY_MATCH_ANY: Key = Key(DISGUISED_EVENT_OFFSETTER + 26);
+// It's right after the codes of the disguised events.
+pub const KEY_MATCH_ANY: Key = Key(DISGUISED_EVENT_OFFSETTER + DISGUISED_EVENT_AXES * 2);
+
+// Like KEY_MATCH_ANY, but also matches modifier keys
+pub const KEY_MATCH_ANY_INCLUDING_MODIFIERS: Key = Key(KEY_MATCH_ANY.0 + 1);
 
+// `<self>` in an action, which is replaced with the key that triggered it, e.g. `ANY: C-<self>`
+pub const KEY_SELF: Key = Key(KEY_MATCH_ANY.0 + 2);
+
+// How long the result of an { if: ... } predicate is reused, so that autorepeat doesn't run it on every repeat
+const PREDICATE_CACHE_DURATION: Duration = Duration::from_millis(500);
+
+/// Turns Events into Actions following a Config. It keeps the state across calls, such as
+/// pressed keys, the mode, and nested remaps, so a single instance should see all the events.
 pub struct EventHandler {
     // Currently pressed modifier keys
     modifiers: Vec<Key>,
     // Modifiers that are currently pressed but not in the source KeyPress
     extra_modifiers: HashSet<Key>,
-    // Make sure the original event is released even if remapping changes while holding the key
-    pressed_keys: HashMap<Key, Key>,
+    // Make sure the original events are released even if remapping changes while holding the key,
+    // so that a modifier modmapped to a non-modifier, or the other way around, isn't left held
+    pressed_keys: HashMap<Key, Vec<Key>>,
     // Check the currently active application
     application_client: WMClient,
     application_cache: Option<String>,
+    // Sequence number of the current key event for application_client
+    event: u64,
     title_cache: Option<String>,
+    process_cache: Option<String>,
     // State machine for multi-purpose keys
     multi_purpose_keys: HashMap<Key, MultiPurposeKeyState>,
+    // Keys delayed until permissive multi-purpose keys are resolved
+    multi_purpose_delayed_keys: Vec<(Key, i32)>,
+    // Taps of keys with double_tap
+    double_tap_keys: HashMap<Key, DoubleTapState>,
+    // Taps of keys with taps, counted until tap_timeout passes without another tap
+    tap_counts: HashMap<Key, TapCountState>,
+    // The held key whose actions are emitted again at its repeat_rate_ms
+    repeating: Option<RepeatingKey>,
+    // repeat_rate_ms of the keymap entry just matched, picked up by on_key_event
+    matched_repeat_rate: Option<Duration>,
+    // Remaps of { layer: ... }, which apply to every key until Deadline::Layer
+    layer: Option<HashMap<Key, Vec<OverrideEntry>>>,
     // Current nested remaps
     override_remaps: Vec<HashMap<Key, Vec<OverrideEntry>>>,
     // Key triggered on a timeout of nested remaps
     override_timeout_key: Option<Key>,
-    // Trigger a timeout of nested remaps through select(2)
-    override_timer: TimerFd,
+    // Actions dispatched on a timeout of nested remaps
+    override_timeout_actions: Vec<KeymapAction>,
+    // What a key that isn't in the nested remaps does
+    override_on_unmatched: OnUnmatched,
+    // Key press that entered nested remaps with literal_timeout_millis, emitted if it's released by the deadline
+    override_literal: Option<(KeyPress, Instant)>,
+    // Keys of nested remaps with { replay: true } to be emitted if the sequence isn't completed
+    leader_sequence: Option<LeaderSequence>,
+    // Fires at the earliest of deadlines through select(2), and tells the current time
+    timer: Box<dyn Timer>,
+    // When each timed feature is due next
+    deadlines: HashMap<Deadline, Instant>,
+    // modifier_order of the config
+    modifier_order: Vec<Key>,
+    // Index of the actions dispatched next by each { cycle: [...] }, by Cycle::id
+    cycle_indices: HashMap<usize, usize>,
+    // Results of { if: ... } predicates and when they were received, by the command
+    predicate_results: HashMap<String, (bool, Instant)>,
+    // { if: ... } waiting for their predicate, which is running, by the command
+    pending_conditionals: HashMap<String, Vec<Conditional>>,
+    // { await_title_change: ... } waiting for the title of the active window to change
+    awaiting_title: Option<AwaitingTitle>,
+    // Device of the innermost { device: ..., action: ... } being dispatched
+    selected_device: Option<String>,
     // { set_mode: String }
     mode: String,
+    // false for a mode set with { sticky: false }, which ends after the next key press
+    mode_sticky: bool,
+    // Kept for { clear_mode: true } and one-shot modes, which don't see the config
+    default_mode: String,
+    // compose_key, kept for { compose: [...] }
+    compose_key: KeyPress,
     // { set_mark: true }
     mark_set: bool,
+    // The key that triggered the actions being dispatched, which `<self>` is sent as. None for keyless actions.
+    self_key: Option<Key>,
     // { escape_next_key: true }
     escape_next_key: bool,
     // keypress_delay_ms
     keypress_delay: Duration,
+    // modifier_delay_ms and key_delay_ms, which default to keypress_delay
+    modifier_delay: Duration,
+    key_delay: Duration,
+    // The delay before the key of a key press, only when modifier_delay_ms is set explicitly
+    pre_key_delay: Duration,
+    // { mouse_move: ... } actions of currently held keys
+    mouse_moves: HashMap<Key, MouseMove>,
+    // Keys pressed by { press: key } and not released by { release: key } yet
+    held_keys: HashSet<Key>,
+    // Keys that dispatched { run: ... }, to skip it on autorepeat
+    run_keys: HashSet<Key>,
+    // When each keymap entry with cooldown_ms was triggered, by the key and its index in keymap_table
+    cooldowns: HashMap<(Key, usize), Instant>,
+    // Fractions of movements scaled by rel_scale, carried over to the next event of the device
+    rel_remainders: HashMap<(PathBuf, u16), f64>,
+    // How many times each entry of keymap_table has matched, for SIGUSR1
+    match_counts: HashMap<(Key, usize), usize>,
+    // The non-modifier key pressed before the current one and when, for keymaps with after
+    previous_key: Option<(Key, Instant)>,
+    // The last non-modifier key pressed, which becomes previous_key on the next press
+    last_key: Option<(Key, Instant)>,
+    // Actions of keymaps with `on: release` and releases of hold_while_pressed, dispatched when the key is released
+    release_actions: HashMap<Key, Vec<TaggedAction>>,
+    // mouse_move_interval_ms
+    mouse_move_interval: Duration,
+    // Mouse movements summed by mouse_movement_coalescing
+    coalesced_movement: Option<CoalescedMovement>,
+    // LEDs that are currently on, per device
+    led_state: HashMap<PathBuf, Vec<LedType>>,
+    // { grab: false } stops emitting anything until { grab: true }
+    grabbed: bool,
+    // Recent scrolls per wheel axis for scroll_acceleration
+    scroll_rates: HashMap<u16, ScrollRate>,
+    // How many times a keymap matching the current scroll is dispatched
+    scroll_multiplier: u32,
     // Buffered actions to be dispatched. TODO: Just return actions from each function instead of using this.
     actions: Vec<Action>,
 }
 
+// What a deadline of EventHandler::deadlines is for. They're served in this order when due at the same time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Deadline {
+    // timeout_millis of nested remaps
+    Override,
+    // mouse_move_interval_ms of held { mouse_move: ... }
+    MouseMove,
+    // window_ms of mouse_movement_coalescing
+    Coalesce,
+    // The earliest tap_timeout of tap_counts
+    Tap,
+    // repeat_rate_ms of repeating
+    Repeat,
+    // timeout_ms of layer
+    Layer,
+}
+
+struct AwaitingTitle {
+    // The title before the key was pressed
+    title: Option<String>,
+    deadline: Instant,
+    interval: Duration,
+    // The actions after { await_title_change: ... }, dispatched once the title changes
+    actions: Vec<KeymapAction>,
+}
+
+struct LeaderSequence {
+    keys: Vec<Key>,
+    timeout: Option<Duration>,
+    on_timeout: Vec<KeymapAction>,
+}
+
 struct TaggedAction {
     action: KeymapAction,
     exact_match: bool,
 }
 
+struct RepeatingKey {
+    key: Key,
+    matched_key: Key,
+    actions: Vec<TaggedAction>,
+    rate: Duration,
+}
+
 impl EventHandler {
-    pub fn new(timer: TimerFd, mode: &str, keypress_delay: Duration, application_client: WMClient) -> EventHandler {
+    /// `timer` is armed for the earliest timeout of nested remaps, mouse_move, taps and the like.
+    /// The caller is responsible for sending Event::OverrideTimeout when it expires.
+    pub fn new(
+        timer: impl Timer + 'static,
+        mode: &str,
+        keypress_delay: Duration,
+        mouse_move_interval: Duration,
+        application_client: WMClient,
+    ) -> EventHandler {
         EventHandler {
             modifiers: vec![],
             extra_modifiers: HashSet::new(),
             pressed_keys: HashMap::new(),
             application_client,
             application_cache: None,
+            event: 0,
             title_cache: None,
+            process_cache: None,
+            modifier_order: vec![],
+            cycle_indices: HashMap::new(),
+            predicate_results: HashMap::new(),
+            pending_conditionals: HashMap::new(),
+            awaiting_title: None,
+            selected_device: None,
             multi_purpose_keys: HashMap::new(),
+            multi_purpose_delayed_keys: vec![],
+            double_tap_keys: HashMap::new(),
+            tap_counts: HashMap::new(),
+            repeating: None,
+            matched_repeat_rate: None,
+            layer: None,
             override_remaps: vec![],
             override_timeout_key: None,
-            override_timer: timer,
+            override_on_unmatched: OnUnmatched::default(),
+            override_timeout_actions: vec![],
+            override_literal: None,
+            leader_sequence: None,
+            timer: Box::new(timer),
+            deadlines: HashMap::new(),
             mode: mode.to_string(),
+            mode_sticky: true,
+            default_mode: mode.to_string(),
+            compose_key: KeyPress {
+                key: Key::KEY_COMPOSE,
+                modifiers: vec![],
+            },
             mark_set: false,
+            self_key: None,
             escape_next_key: false,
             keypress_delay,
+            modifier_delay: keypress_delay,
+            pre_key_delay: Duration::ZERO,
+            key_delay: keypress_delay,
+            mouse_moves: HashMap::new(),
+            cooldowns: HashMap::new(),
+            rel_remainders: HashMap::new(),
+            match_counts: HashMap::new(),
+            previous_key: None,
+            last_key: None,
+            release_actions: HashMap::new(),
+            held_keys: HashSet::new(),
+            run_keys: HashSet::new(),
+            mouse_move_interval,
+            coalesced_movement: None,
+            led_state: HashMap::new(),
+            grabbed: true,
+            scroll_rates: HashMap::new(),
+            scroll_multiplier: 1,
             actions: vec![],
         }
     }
 
-    // Handle an Event and return Actions. This should be the only public method of EventHandler.
+    /// Handle Events and return Actions. This should be the only public method of EventHandler.
+    /// `config.keymap_table` must be built with build_keymap_table beforehand.
     pub fn on_events(&mut self, events: &Vec<Event>, config: &Config) -> Result<Vec<Action>, Box<dyn Error>> {
         // a vector to collect mouse movement events to be able to send them all at once as one MouseMovementEventCollection.
         let mut mouse_movement_collection: Vec<RelativeEvent> = Vec::new();
+        // Kept for send_key_press, which doesn't see the config
+        self.modifier_order.clone_from(&config.modifier_order);
+        self.default_mode.clone_from(&config.default_mode);
+        self.compose_key.clone_from(&config.compose_key);
+        self.modifier_delay = config.modifier_delay.unwrap_or(self.keypress_delay);
+        self.key_delay = config.key_delay.unwrap_or(self.keypress_delay);
+        self.pre_key_delay = config.modifier_delay.unwrap_or_default();
         for event in events {
+            // rel_invert applies first, so that rel_scale and the remaps see the flipped value
+            let inverted_event;
+            let event = match event {
+                Event::RelativeEvent(device, relative_event) if config.rel_inverted(device, relative_event.code) => {
+                    inverted_event = Event::RelativeEvent(
+                        InputDeviceInfo {
+                            name: device.name,
+                            path: device.path,
+                        },
+                        RelativeEvent::new_with(relative_event.code, relative_event.value.saturating_neg()),
+                    );
+                    &inverted_event
+                }
+                _ => event,
+            };
+            // rel_scale applies before the movements are coalesced or collected
+            let scaled_event;
+            let event = match event {
+                Event::RelativeEvent(device, relative_event) if matches!(relative_event.code, REL_X | REL_Y) => {
+                    match config.rel_scale(device) {
+                        Some(scale) => match self.scale_relative_event(relative_event, device, scale) {
+                            Some(relative_event) => {
+                                scaled_event = Event::RelativeEvent(
+                                    InputDeviceInfo {
+                                        name: device.name,
+                                        path: device.path,
+                                    },
+                                    relative_event,
+                                );
+                                &scaled_event
+                            }
+                            None => continue,
+                        },
+                        None => event,
+                    }
+                }
+                _ => event,
+            };
+            if config.mouse_movement_coalescing.enable {
+                match event {
+                    Event::RelativeEvent(device, relative_event) if matches!(relative_event.code, REL_X | REL_Y) => {
+                        self.coalesce_mouse_movement(relative_event, device, &mut mouse_movement_collection, config)?;
+                        continue;
+                    }
+                    // Keep SYN_REPORTs from flushing the movements of every report
+                    Event::OtherEvents(event) if event.event_type() == EventType::SYNCHRONIZATION => {}
+                    // Keep the order of movements and other events
+                    _ => {
+                        self.flush_coalesced_movement(&mut mouse_movement_collection, config)?;
+                        if !mouse_movement_collection.is_empty() {
+                            let collection = std::mem::take(&mut mouse_movement_collection);
+                            self.send_action(Action::MouseMovementEventCollection(collection));
+                        }
+                    }
+                }
+            }
             match event {
                 Event::KeyEvent(device, key_event) => {
                     self.on_key_event(key_event, config, device)?;
@@ -100,9 +360,38 @@ impl EventHandler {
                 Event::RelativeEvent(device, relative_event) => {
                     self.on_relative_event(relative_event, &mut mouse_movement_collection, config, device)?
                 }
-
+                Event::LedEvent(device, led_event) => self.on_led_event(led_event, device),
                 Event::OtherEvents(event) => self.send_action(Action::InputEvent(*event)),
-                Event::OverrideTimeout => self.timeout_override()?,
+                Event::OverrideTimeout => self.on_timeout(&mut mouse_movement_collection, config)?,
+                Event::Startup => self.on_startup(config)?,
+                Event::ConfigReload => {
+                    self.release_held_keys();
+                    // Entries are numbered differently in the new config
+                    self.match_counts.clear();
+                    self.cycle_indices.clear();
+                    self.cooldowns.clear();
+                }
+                Event::PrintMatchCounts => self.print_match_counts(config),
+                Event::PredicateResult(predicate, success) => {
+                    self.predicate_results
+                        .insert(predicate.clone(), (*success, self.timer.now()));
+                    let actions: Vec<KeymapAction> = self
+                        .pending_conditionals
+                        .remove(predicate)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .flat_map(|conditional| {
+                            if *success {
+                                conditional.then
+                            } else {
+                                conditional.otherwise
+                            }
+                        })
+                        .collect();
+                    self.dispatch_keyless_actions(&actions)?;
+                }
+                Event::TitlePoll => self.poll_title()?,
+                Event::Shutdown => self.release_pressed_keys(),
             };
         }
         // if there is at least one mouse movement event, sending all of them as one MouseMovementEventCollection
@@ -112,6 +401,25 @@ impl EventHandler {
         Ok(self.actions.drain(..).collect())
     }
 
+    // Return None when the movement is smaller than a unit, which is added to the next one
+    fn scale_relative_event(
+        &mut self,
+        event: &RelativeEvent,
+        device: &InputDeviceInfo,
+        scale: f64,
+    ) -> Option<RelativeEvent> {
+        let remainder = self
+            .rel_remainders
+            .entry((device.path.to_path_buf(), event.code))
+            .or_default();
+        let value = event.value as f64 * scale + *remainder;
+        *remainder = value.fract();
+        match value.trunc() as i32 {
+            0 => None,
+            value => Some(RelativeEvent::new_with(event.code, value)),
+        }
+    }
+
     // Handle EventType::KEY
     fn on_key_event(
         &mut self,
@@ -121,9 +429,32 @@ impl EventHandler {
     ) -> Result<bool, Box<dyn Error>> {
         self.application_cache = None; // expire cache
         self.title_cache = None; // expire cache
+        self.process_cache = None; // expire cache
+        self.event = self.application_client.begin_event(config.client_cache_ttl);
         let key = Key::new(event.code());
         debug!("=> {}: {:?}", event.value(), &key);
 
+        // Pressing another key, e.g. for a combo with a modifier, cancels taps
+        if event.value() == PRESS {
+            for (_, state) in self.double_tap_keys.iter_mut().filter(|(tap_key, _)| **tap_key != key) {
+                state.cancel_tap();
+            }
+            // and settles the counts of taps so that their actions come before the key
+            let mut tap_keys: Vec<(Key, Instant)> = self
+                .tap_counts
+                .iter()
+                .filter(|(tap_key, _)| **tap_key != key)
+                .map(|(tap_key, state)| (*tap_key, state.deadline))
+                .collect();
+            if !tap_keys.is_empty() {
+                tap_keys.sort_by_key(|(_, deadline)| *deadline);
+                for (tap_key, _) in tap_keys {
+                    self.finish_taps(&tap_key)?;
+                }
+                self.set_tap_deadline()?;
+            }
+        }
+
         // Apply modmap
         let mut key_values = if let Some(key_action) = self.find_modmap(config, &key, device) {
             self.dispatch_keys(key_action, key, event.value())?
@@ -131,13 +462,46 @@ impl EventHandler {
             vec![(key, event.value())]
         };
         self.maintain_pressed_keys(key, event.value(), &mut key_values);
-        if !self.multi_purpose_keys.is_empty() {
+        if !self.multi_purpose_keys.is_empty() || !self.multi_purpose_delayed_keys.is_empty() {
             key_values = self.flush_timeout_keys(key_values);
         }
 
         let mut send_original_relative_event = false;
         // Apply keymap
         for (key, value) in key_values.into_iter() {
+            if value == PRESS && self.match_panic_key(config, &key) {
+                self.release_all(config)?;
+                continue;
+            }
+            if value == PRESS && !MODIFIER_KEYS.contains(&key) && !config.virtual_modifiers.contains(&key) {
+                self.previous_key = self.last_key.replace((key, self.timer.now()));
+            }
+            if value == RELEASE {
+                if let Some(actions) = self.release_actions.remove(&key) {
+                    self.dispatch_actions(&actions, &key)?;
+                    // A run dispatched on release has to be able to run again on the next release
+                    self.run_keys.remove(&key);
+                    continue;
+                }
+            }
+            if value == RELEASE && self.release_literal(&key)? {
+                continue;
+            }
+            if value == RELEASE {
+                self.run_keys.remove(&key);
+            }
+            // The actions are repeated by Deadline::Repeat instead of the kernel's autorepeat
+            if self.repeating.as_ref().is_some_and(|repeating| repeating.key == key) {
+                if value == REPEAT {
+                    continue;
+                } else if value == RELEASE {
+                    self.repeating = None;
+                    self.unset_deadline(Deadline::Repeat)?;
+                }
+            }
+            if value == RELEASE && self.mouse_moves.remove(&key).is_some() && self.mouse_moves.is_empty() {
+                self.unset_deadline(Deadline::MouseMove)?;
+            }
             if config.virtual_modifiers.contains(&key) {
                 self.update_modifier(key, value);
                 continue;
@@ -145,20 +509,59 @@ impl EventHandler {
                 // Let modifiers be eligible for matching in keymap. If a modifier is terminal,
                 //  its action will be emitted, but itself will not be emitted,
                 //  therefore it must not be added to self.modifiers.
-                if let Some(actions) = self.find_keymap(config, &key, device)? {
+                if let Some(actions) = self.find_keymap(config, &key, value, device)? {
+                    self.self_key = Some(key);
                     self.dispatch_actions(&actions, &key)?;
                     continue;
                 }
+                // Like terminal modifiers, this is skipped in nested remaps so that they're kept
+                if is_pressed(value) && self.override_remaps.is_empty() {
+                    if let Some(actions) =
+                        self.find_keymap(config, &KEY_MATCH_ANY_INCLUDING_MODIFIERS, value, device)?
+                    {
+                        self.self_key = Some(key);
+                        self.dispatch_actions(&actions, &KEY_MATCH_ANY_INCLUDING_MODIFIERS)?;
+                        continue;
+                    }
+                }
 
                 self.update_modifier(key, value);
             } else if is_pressed(value) {
-                if self.escape_next_key {
-                    self.escape_next_key = false
-                } else if let Some(actions) = self.find_keymap(config, &key, device)? {
-                    self.dispatch_actions(&actions, &key)?;
-                    continue;
-                } else if let Some(actions) = self.find_keymap(config, &KEY_MATCH_ANY, device)? {
-                    self.dispatch_actions(&actions, &KEY_MATCH_ANY)?;
+                self.matched_repeat_rate = None;
+                let matched = if self.escape_next_key {
+                    self.escape_next_key = false;
+                    None
+                } else if let Some(actions) = self.find_keymap(config, &key, value, device)? {
+                    let times = if key.code() >= DISGUISED_EVENT_OFFSETTER {
+                        self.scroll_multiplier
+                    } else {
+                        1
+                    };
+                    Some((actions, key, times))
+                } else if let Some(actions) = self.find_keymap(config, &KEY_MATCH_ANY, value, device)? {
+                    Some((actions, KEY_MATCH_ANY, 1))
+                } else {
+                    self.find_keymap(config, &KEY_MATCH_ANY_INCLUDING_MODIFIERS, value, device)?
+                        .map(|actions| (actions, KEY_MATCH_ANY_INCLUDING_MODIFIERS, 1))
+                };
+                // A one-shot mode ends with the lookup, so that the actions can set a mode again
+                if value == PRESS && !self.mode_sticky {
+                    self.set_mode(&self.default_mode.clone(), true);
+                }
+                if let Some((actions, matched_key, times)) = matched {
+                    self.self_key = Some(key);
+                    for _ in 0..times {
+                        self.dispatch_actions(&actions, &matched_key)?;
+                    }
+                    if let Some(rate) = self.matched_repeat_rate.take().filter(|_| value == PRESS) {
+                        self.repeating = Some(RepeatingKey {
+                            key,
+                            matched_key,
+                            actions,
+                            rate,
+                        });
+                        self.set_deadline(Deadline::Repeat, self.timer.now() + rate)?;
+                    }
                     continue;
                 }
             }
@@ -195,6 +598,12 @@ impl EventHandler {
         const RELEASE: i32 = 0;
         const PRESS: i32 = 1;
 
+        // The kernel doesn't have codes beyond them, but they couldn't be disguised anyway
+        if event.code >= DISGUISED_EVENT_AXES {
+            self.send_action(Action::RelativeEvent(RelativeEvent::new_with(event.code, event.value)));
+            return Ok(());
+        }
+
         // All relative events (except maybe those i haven't found information about (REL_DIAL, REL_MISC and REL_RESERVED))
         // can have either a positive value or a negative value.
         // A negative value is associated with a different action than the positive value.
@@ -216,7 +625,7 @@ impl EventHandler {
             i32::MIN..=-1 => (event.code * 2) + 1 + DISGUISED_EVENT_OFFSETTER,
 
             0 => {
-                println!("This event has a value of zero : {:?}", event);
+                eprintln!("This event has a value of zero : {:?}", event);
                 // A value of zero would be unexpected for a relative event,
                 // since changing something by zero is kinda useless.
                 // Just in case it can actually happen (and also because match arms need the same output type),
@@ -225,53 +634,227 @@ impl EventHandler {
             }
         };
 
+        // Scrolling fast may dispatch a matching keymap multiple times
+        if let Some(acceleration) = &config.scroll_acceleration {
+            self.scroll_multiplier = self.track_scroll_rate(event, acceleration);
+        }
+
         // Sending a RELATIVE event "disguised" as a "fake" KEY event press to on_key_event.
-        match self.on_key_event(&KeyEvent::new_with(key, PRESS), config, &device)? {
-            // the boolean value is from a variable at the end of on_key_event from event_handler,
-            // used to indicate whether the event got through unchanged.
-            true => {
-                // Sending the original RELATIVE event if the "press" version of the "fake" KEY event got through on_key_event unchanged.
-                let action = RelativeEvent::new_with(event.code, event.value);
-                if event.code <= 2 {
-                    // If it's a mouse movement event (event.code <= 2),
-                    // it is added to mouse_movement_collection to later be sent alongside all other mouse movement event,
-                    // as a single MouseMovementEventCollection instead of potentially multiple RelativeEvent .
-
-                    // Mouse movement events need to be sent all at once because they would otherwise be separated by a synchronization event¹,
-                    // which the OS handles differently from two unseparated mouse movement events.
-                    // For example, a REL_X event², followed by a SYNCHRONIZATION event, followed by a REL_Y event³, followed by a SYNCHRONIZATION event,
-                    // will move the mouse cursor by a different amount than a REL_X followed by a REL_Y followed by a SYNCHRONIZATION.
-
-                    // ¹Because Xremap usually sends events one by one through evdev's "emit" function, which adds a synchronization event during each call.
-                    // ²Mouse movement along the X (horizontal) axis.
-                    // ³Mouse movement along the Y (vertical) axis.
-                    mouse_movement_collection.push(action);
-                } else {
-                    // Otherwise, the event is directly sent as a relative event, to be dispatched like other events.
-                    self.send_action(Action::RelativeEvent(action));
-                }
+        let sent_original = self.on_key_event(&KeyEvent::new_with(key, PRESS), config, device);
+        self.scroll_multiplier = 1;
+        // The boolean value is from a variable at the end of on_key_event,
+        // used to indicate whether the event got through unchanged.
+        if sent_original? {
+            // Sending the original RELATIVE event if the "press" version of the "fake" KEY event got through on_key_event unchanged.
+            let action = RelativeEvent::new_with(event.code, event.value);
+            if event.code <= 2 {
+                // If it's a mouse movement event (event.code <= 2),
+                // it is added to mouse_movement_collection to later be sent alongside all other mouse movement event,
+                // as a single MouseMovementEventCollection instead of potentially multiple RelativeEvent .
+
+                // Mouse movement events need to be sent all at once because they would otherwise be separated by a synchronization event¹,
+                // which the OS handles differently from two unseparated mouse movement events.
+                // For example, a REL_X event², followed by a SYNCHRONIZATION event, followed by a REL_Y event³, followed by a SYNCHRONIZATION event,
+                // will move the mouse cursor by a different amount than a REL_X followed by a REL_Y followed by a SYNCHRONIZATION.
+
+                // ¹Because Xremap usually sends events one by one through evdev's "emit" function, which adds a synchronization event during each call.
+                // ²Mouse movement along the X (horizontal) axis.
+                // ³Mouse movement along the Y (vertical) axis.
+                mouse_movement_collection.push(action);
+            } else {
+                // Otherwise, the event is directly sent as a relative event, to be dispatched like other events.
+                self.send_action(Action::RelativeEvent(action));
             }
-            false => {}
         }
 
         // Sending the "unpressed" version of the "fake" KEY event.
-        self.on_key_event(&KeyEvent::new_with(key, RELEASE), config, &device)?;
+        self.on_key_event(&KeyEvent::new_with(key, RELEASE), config, device)?;
+
+        Ok(())
+    }
 
+    // Sum a mouse movement into the pending one, which is remapped when the window elapses
+    fn coalesce_mouse_movement(
+        &mut self,
+        event: &RelativeEvent,
+        device: &InputDeviceInfo,
+        mouse_movement_collection: &mut Vec<RelativeEvent>,
+        config: &Config,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(movement) = &self.coalesced_movement {
+            if movement.path != device.path {
+                self.flush_coalesced_movement(mouse_movement_collection, config)?;
+            }
+        }
+        if self.coalesced_movement.is_none() {
+            self.set_deadline(Deadline::Coalesce, self.timer.now() + config.mouse_movement_coalescing.window)?;
+        }
+        let movement = self.coalesced_movement.get_or_insert_with(|| CoalescedMovement {
+            name: device.name.to_string(),
+            path: device.path.to_path_buf(),
+            x: 0,
+            y: 0,
+        });
+        match event.code {
+            REL_X => movement.x = movement.x.saturating_add(event.value),
+            _ => movement.y = movement.y.saturating_add(event.value),
+        }
         Ok(())
     }
 
+    fn flush_coalesced_movement(
+        &mut self,
+        mouse_movement_collection: &mut Vec<RelativeEvent>,
+        config: &Config,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(movement) = self.coalesced_movement.take() {
+            self.unset_deadline(Deadline::Coalesce)?;
+            let device = InputDeviceInfo {
+                name: &movement.name,
+                path: &movement.path,
+            };
+            for (code, value) in [(REL_X, movement.x), (REL_Y, movement.y)] {
+                if value != 0 {
+                    let event = RelativeEvent::new_with(code, value);
+                    self.on_relative_event(&event, mouse_movement_collection, config, &device)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Record a scroll and return how many times a keymap should be dispatched for it.
+    // Rates are measured in 1/120 of a notch, using the hi-res axis once a device has shown it.
+    fn track_scroll_rate(&mut self, event: &RelativeEvent, acceleration: &ScrollAcceleration) -> u32 {
+        let (axis, hires) = match event.code {
+            REL_WHEEL => (REL_WHEEL, false),
+            REL_WHEEL_HI_RES => (REL_WHEEL, true),
+            REL_HWHEEL => (REL_HWHEEL, false),
+            REL_HWHEEL_HI_RES => (REL_HWHEEL, true),
+            _ => return 1,
+        };
+        let now = self.timer.now();
+        let rate = self.scroll_rates.entry(axis).or_default();
+        if hires && !rate.hires {
+            // Drop low-res samples so the first hi-res scrolls aren't counted twice
+            rate.hires = true;
+            rate.samples.clear();
+        }
+        if hires == rate.hires {
+            let value = if hires { event.value } else { event.value * 120 };
+            // Reset on a direction reversal
+            if rate
+                .samples
+                .back()
+                .is_some_and(|(_, last)| last.signum() != value.signum())
+            {
+                rate.samples.clear();
+            }
+            rate.samples.push_back((now, value));
+        }
+        while rate
+            .samples
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > acceleration.window)
+        {
+            rate.samples.pop_front();
+        }
+        if hires {
+            // Only low-res scrolls are remapped with a multiplier
+            return 1;
+        }
+        let units: u32 = rate.samples.iter().map(|(_, value)| value.unsigned_abs()).sum();
+        units
+            .div_ceil(120)
+            .clamp(acceleration.min_multiplier, acceleration.max_multiplier)
+    }
+
+    // Handle EventType::LED
+    fn on_led_event(&mut self, event: &LedEvent, device: &InputDeviceInfo) {
+        let leds = self.led_state.entry(device.path.to_path_buf()).or_default();
+        leds.retain(|led| *led != event.led);
+        if event.on {
+            leds.push(event.led);
+        }
+    }
+
     fn timeout_override(&mut self) -> Result<(), Box<dyn Error>> {
-        if let Some(key) = self.override_timeout_key {
+        if let Some(sequence) = self.leader_sequence.take() {
+            for key in sequence.keys {
+                self.send_key(&key, PRESS);
+                self.send_key(&key, RELEASE);
+            }
+        } else if let Some(key) = self.override_timeout_key {
             self.send_key(&key, PRESS);
             self.send_key(&key, RELEASE);
         }
         self.remove_override()
     }
 
+    // Leave nested remaps and emit the key press that entered them, if it's released quickly enough
+    fn release_literal(&mut self, key: &Key) -> Result<bool, Box<dyn Error>> {
+        let now = self.timer.now();
+        match self.override_literal.take() {
+            Some((key_press, deadline)) if key_press.key == *key && now <= deadline => {
+                self.leader_sequence = None;
+                self.remove_override()?;
+                self.send_key_press(&key_press);
+                Ok(true)
+            }
+            // Held longer, so it stays a prefix
+            Some((key_press, _)) if key_press.key == *key => Ok(false),
+            override_literal => {
+                self.override_literal = override_literal;
+                Ok(false)
+            }
+        }
+    }
+
+    // Emit the actions of the held key again, and schedule the next tick
+    fn tick_repeat(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(repeating) = self.repeating.take() else {
+            return Ok(());
+        };
+        self.self_key = Some(repeating.key);
+        self.dispatch_actions(&repeating.actions, &repeating.matched_key)?;
+        self.set_deadline(Deadline::Repeat, self.timer.now() + repeating.rate)?;
+        self.repeating = Some(repeating);
+        Ok(())
+    }
+
+    // Move the pointer by the sum of held mouse_moves, and schedule the next tick
+    fn tick_mouse_move(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.mouse_moves.is_empty() {
+            return Ok(());
+        }
+        let x: i32 = self.mouse_moves.values().map(|mouse_move| mouse_move.x).sum();
+        let y: i32 = self.mouse_moves.values().map(|mouse_move| mouse_move.y).sum();
+        self.send_mouse_movement(x, y);
+        self.set_deadline(Deadline::MouseMove, self.timer.now() + self.mouse_move_interval)?;
+        Ok(())
+    }
+
+    // Both axes are sent in one collection to move the cursor diagonally
+    fn send_mouse_movement(&mut self, x: i32, y: i32) {
+        let mut movement = vec![];
+        if x != 0 {
+            movement.push(RelativeEvent::new_with(REL_X, x));
+        }
+        if y != 0 {
+            movement.push(RelativeEvent::new_with(REL_Y, y));
+        }
+        if !movement.is_empty() {
+            self.send_action(Action::MouseMovementEventCollection(movement));
+        }
+    }
+
     fn remove_override(&mut self) -> Result<(), Box<dyn Error>> {
-        self.override_timer.unset()?;
+        self.unset_deadline(Deadline::Override)?;
         self.override_remaps.clear();
         self.override_timeout_key = None;
+        self.override_timeout_actions.clear();
+        self.override_on_unmatched = OnUnmatched::default();
+        self.override_literal = None;
         Ok(())
     }
 
@@ -282,28 +865,57 @@ impl EventHandler {
     }
 
     fn send_key(&mut self, key: &Key, value: i32) {
+        let key = if *key == KEY_SELF {
+            match self.self_key {
+                Some(self_key) => self_key,
+                None => {
+                    warn!("<self> is skipped since the actions aren't triggered by a key");
+                    return;
+                }
+            }
+        } else {
+            *key
+        };
         // let event = InputEvent::new(EventType::KEY, key.code(), value);
         let event = KeyEvent::new_with(key.code(), value);
         self.send_action(Action::KeyEvent(event));
     }
 
     fn send_action(&mut self, action: Action) {
+        // Input devices are not grabbed, so the original events already reach the applications.
+        // Polls go on so that { await_title_change: ... } finishes, and the device selection stays balanced.
+        // LEDs are still set, since they are indicators rather than input.
+        if !self.grabbed
+            && !matches!(
+                action,
+                Action::SetGrab(_) | Action::PollTitle(_) | Action::SelectDevice(_) | Action::SetLed { .. }
+            )
+        {
+            return;
+        }
         self.actions.push(action);
     }
 
     // Repeat/Release what's originally pressed even if remapping changes while holding it
     fn maintain_pressed_keys(&mut self, key: Key, value: i32, events: &mut Vec<(Key, i32)>) {
         // Not handling multi-purpose keysfor now; too complicated
-        if events.len() != 1 || value != events[0].1 {
+        if events.is_empty() || events.iter().any(|event| event.1 != value) {
             return;
         }
 
-        let event = events[0];
         if value == PRESS {
-            self.pressed_keys.insert(key, event.0);
+            self.pressed_keys
+                .insert(key, events.iter().map(|event| event.0).collect());
         } else {
-            if let Some(original_key) = self.pressed_keys.get(&key) {
-                events[0].0 = *original_key;
+            if let Some(original_keys) = self.pressed_keys.get(&key) {
+                *events = original_keys
+                    .iter()
+                    .map(|original_key| (*original_key, value))
+                    .collect();
+                // Released in reverse like ModmapAction::Keys
+                if value == RELEASE {
+                    events.reverse();
+                }
             }
             if value == RELEASE {
                 self.pressed_keys.remove(&key);
@@ -319,10 +931,22 @@ impl EventHandler {
     ) -> Result<Vec<(Key, i32)>, Box<dyn Error>> {
         let keys = match key_action {
             ModmapAction::Key(modmap_key) => vec![(modmap_key, value)],
+            ModmapAction::Keys(modmap_keys) => {
+                if value == RELEASE {
+                    modmap_keys
+                        .into_iter()
+                        .rev()
+                        .map(|modmap_key| (modmap_key, value))
+                        .collect()
+                } else {
+                    modmap_keys.into_iter().map(|modmap_key| (modmap_key, value)).collect()
+                }
+            }
             ModmapAction::MultiPurposeKey(MultiPurposeKey {
                 held,
                 alone,
                 alone_timeout,
+                tap_hold_policy,
             }) => {
                 if value == PRESS {
                     self.multi_purpose_keys.insert(
@@ -330,17 +954,18 @@ impl EventHandler {
                         MultiPurposeKeyState {
                             held,
                             alone,
-                            alone_timeout_at: Some(Instant::now() + alone_timeout),
+                            alone_timeout_at: Some(self.timer.now() + alone_timeout),
+                            tap_hold_policy,
                         },
                     );
                     return Ok(vec![]); // delay the press
                 } else if value == REPEAT {
                     if let Some(state) = self.multi_purpose_keys.get_mut(&key) {
-                        return Ok(state.repeat());
+                        return Ok(state.repeat(self.timer.now()));
                     }
                 } else if value == RELEASE {
                     if let Some(state) = self.multi_purpose_keys.remove(&key) {
-                        return Ok(state.release());
+                        return Ok(state.release(self.timer.now()));
                     }
                 } else {
                     panic!("unexpected key event value: {}", value);
@@ -348,6 +973,57 @@ impl EventHandler {
                 // fallthrough on state discrepancy
                 vec![(key, value)]
             }
+            ModmapAction::DoubleTapKey(DoubleTapKey {
+                double_tap,
+                double_tap_timeout,
+            }) => {
+                let now = self.timer.now();
+                let state = self.double_tap_keys.entry(key).or_default();
+                if value == PRESS {
+                    // Emit the key right away so that single taps aren't delayed
+                    state.morphed = state
+                        .tapped_at
+                        .is_some_and(|at| now.duration_since(at) <= double_tap_timeout);
+                    state.tapped_at = None;
+                    state.clean = !state.morphed;
+                } else if value == RELEASE && state.clean {
+                    state.tapped_at = Some(now);
+                }
+                if state.morphed {
+                    let mut keys = double_tap.into_vec();
+                    if value == RELEASE {
+                        keys.reverse();
+                    }
+                    keys.into_iter().map(|key| (key, value)).collect()
+                } else {
+                    vec![(key, value)]
+                }
+            }
+            ModmapAction::TapCountKey(TapCountKey { taps, tap_timeout }) => {
+                // Both the press and the release are swallowed, and the actions are dispatched for the count
+                if value == PRESS {
+                    let deadline = self.timer.now() + tap_timeout;
+                    let state = self.tap_counts.entry(key).or_insert_with(|| TapCountState {
+                        count: 0,
+                        deadline,
+                        taps: BTreeMap::new(),
+                    });
+                    state.count += 1;
+                    state.deadline = deadline;
+                    state.taps = taps;
+                    // No need to wait for more taps
+                    if state
+                        .taps
+                        .keys()
+                        .next_back()
+                        .is_none_or(|max_count| state.count >= *max_count)
+                    {
+                        self.finish_taps(&key)?;
+                    }
+                    self.set_tap_deadline()?;
+                }
+                vec![]
+            }
             ModmapAction::PressReleaseKey(PressReleaseKey {
                 skip_key_event,
                 press,
@@ -356,6 +1032,7 @@ impl EventHandler {
                 // Just hook actions, and then emit the original event. We might want to
                 // support reordering the key event and dispatched actions later.
                 if value == PRESS || value == RELEASE {
+                    self.self_key = Some(key);
                     self.dispatch_actions(
                         &(if value == PRESS { press } else { release })
                             .into_iter()
@@ -363,7 +1040,7 @@ impl EventHandler {
                                 action,
                                 exact_match: false,
                             })
-                            .collect(),
+                            .collect::<Vec<_>>(),
                         &key,
                     )?;
                 }
@@ -389,16 +1066,63 @@ impl EventHandler {
             }
         }
 
-        if flush {
-            let mut flushed: Vec<(Key, i32)> = vec![];
-            for (_, state) in self.multi_purpose_keys.iter_mut() {
+        let mut flushed: Vec<(Key, i32)> = vec![];
+        // Permissive keys held past their alone timeout are held, even if the other key isn't tapped.
+        // Their delayed keys come before the keys of this event.
+        let now = self.timer.now();
+        let mut timed_out = false;
+        for state in self.multi_purpose_keys.values_mut() {
+            if state.tap_hold_policy == TapHoldPolicy::Permissive
+                && state
+                    .alone_timeout_at
+                    .is_some_and(|alone_timeout_at| now >= alone_timeout_at)
+            {
                 flushed.extend(state.force_held());
+                timed_out = true;
+            }
+        }
+        if timed_out && !self.has_undecided_permissive_keys() {
+            flushed.append(&mut self.multi_purpose_delayed_keys);
+        }
+
+        if flush {
+            for state in self.multi_purpose_keys.values_mut() {
+                if state.tap_hold_policy == TapHoldPolicy::HoldPreferred {
+                    flushed.extend(state.force_held());
+                }
             }
-            flushed.extend(key_values);
-            flushed
-        } else {
-            key_values
         }
+
+        for (key, value) in key_values.into_iter() {
+            if !self.has_undecided_permissive_keys() {
+                flushed.push((key, value));
+            } else if value == RELEASE && self.multi_purpose_delayed_keys.contains(&(key, PRESS)) {
+                // Another key was tapped while permissive keys were pressed
+                for state in self.multi_purpose_keys.values_mut() {
+                    if state.tap_hold_policy == TapHoldPolicy::Permissive {
+                        flushed.extend(state.force_held());
+                    }
+                }
+                flushed.append(&mut self.multi_purpose_delayed_keys);
+                flushed.push((key, value));
+            } else if value == PRESS || self.multi_purpose_delayed_keys.contains(&(key, PRESS)) {
+                self.multi_purpose_delayed_keys.push((key, value));
+            } else {
+                flushed.push((key, value));
+            }
+        }
+
+        // Permissive keys were released alone or timed out
+        if !self.has_undecided_permissive_keys() {
+            flushed.append(&mut self.multi_purpose_delayed_keys);
+        }
+        flushed
+    }
+
+    fn has_undecided_permissive_keys(&self) -> bool {
+        self.multi_purpose_keys
+            .values()
+            .any(|state| state.tap_hold_policy == TapHoldPolicy::Permissive && state.alone_timeout_at.is_some())
     }
 
     fn find_modmap(&mut self, config: &Config, key: &Key, device: &InputDeviceInfo) -> Option<ModmapAction> {
@@ -419,16 +1143,34 @@ impl EventHandler {
                         continue;
                     }
                 }
+                if let Some(description) = &modmap.description {
+                    self.on_match(description);
+                }
                 return Some(key_action.clone());
             }
         }
         None
     }
 
+    // Count a match of keymap_table[key][index] for SIGUSR1
+    fn on_keymap_match(&mut self, key: Key, index: usize, entry: &KeymapEntry) {
+        *self.match_counts.entry((key, index)).or_default() += 1;
+        if let Some(description) = &entry.description {
+            self.on_match(description);
+        }
+    }
+
+    // Tell which entry is responsible for the following actions
+    fn on_match(&mut self, description: &str) {
+        debug!("Matched: {}", description);
+        self.send_action(Action::Matched(description.to_string()));
+    }
+
     fn find_keymap(
         &mut self,
         config: &Config,
         key: &Key,
+        value: i32,
         device: &InputDeviceInfo,
     ) -> Result<Option<Vec<TaggedAction>>, Box<dyn Error>> {
         if !self.override_remaps.is_empty() {
@@ -453,76 +1195,101 @@ impl EventHandler {
             if !entries.is_empty() {
                 self.remove_override()?;
 
-                for exact_match in [true, false] {
-                    let mut remaps = vec![];
-                    for entry in &entries {
-                        if entry.exact_match && !exact_match {
-                            continue;
-                        }
-                        let (extra_modifiers, missing_modifiers) = self.diff_modifiers(&entry.modifiers);
-                        if (exact_match && extra_modifiers.len() > 0) || missing_modifiers.len() > 0 {
-                            continue;
-                        }
-
-                        let actions = with_extra_modifiers(&entry.actions, &extra_modifiers, entry.exact_match);
-                        let is_remap = is_remap(&entry.actions);
-
-                        // If the first/top match was a remap, continue to find rest of the eligible remaps for this key
-                        if remaps.is_empty() && !is_remap {
-                            return Ok(Some(actions));
-                        } else if is_remap {
-                            remaps.extend(actions);
-                        }
+                if let Some((actions, is_remap)) = self.match_override_entries(config, &entries) {
+                    if !is_remap {
+                        // The sequence is completed
+                        self.leader_sequence = None;
                     }
-                    if !remaps.is_empty() {
-                        return Ok(Some(remaps));
+                    return Ok(Some(actions));
+                }
+            }
+            // An override remap is set but not used. Flush the pending key unless on_unmatched says otherwise.
+            match self.override_on_unmatched {
+                OnUnmatched::Replay => self.timeout_override()?,
+                on_unmatched => {
+                    self.leader_sequence = None;
+                    self.remove_override()?;
+                    if on_unmatched == OnUnmatched::Swallow {
+                        return Ok(Some(vec![]));
                     }
                 }
             }
-            // An override remap is set but not used. Flush the pending key.
-            self.timeout_override()?;
+        }
+
+        // A layer stays until its timeout, and keys it doesn't map fall through to the keymaps
+        let layer_entries = match &self.layer {
+            Some(layer) if !config.virtual_modifiers.contains(key) && !MODIFIER_KEYS.contains(key) => {
+                layer.get(key).cloned()
+            }
+            _ => None,
+        };
+        if let Some(entries) = layer_entries {
+            if let Some((actions, _)) = self.match_override_entries(config, &entries) {
+                return Ok(Some(actions));
+            }
         }
 
         if let Some(entries) = config.keymap_table.get(key) {
             for exact_match in [true, false] {
                 let mut remaps = vec![];
-                for entry in entries {
+                for (index, entry) in entries.iter().enumerate() {
                     if entry.exact_match && !exact_match {
                         continue;
                     }
                     let (extra_modifiers, missing_modifiers) = self.diff_modifiers(&entry.modifiers);
-                    if (exact_match && extra_modifiers.len() > 0) || missing_modifiers.len() > 0 {
+                    let any_modifiers = entry.modifiers.contains(&Modifier::Any);
+                    if (exact_match && !any_modifiers && extra_modifiers.len() > 0) || missing_modifiers.len() > 0 {
                         continue;
                     }
-                    if let Some(window_matcher) = &entry.title {
-                        if !self.match_window(window_matcher) {
-                            continue;
-                        }
-                    }
-
-                    if let Some(application_matcher) = &entry.application {
-                        if !self.match_application(application_matcher) {
-                            continue;
-                        }
+                    if !match_repeat(entry.repeat, value) {
+                        continue;
                     }
-                    if let Some(device_matcher) = &entry.device {
-                        if !self.match_device(device_matcher, device) {
-                            continue;
-                        }
+                    if !entry
+                        .conditions
+                        .iter()
+                        .all(|condition| self.match_condition(condition, device))
+                    {
+                        continue;
                     }
-                    if let Some(modes) = &entry.mode {
-                        if !modes.contains(&self.mode) {
-                            continue;
+
+                    if let Some(cooldown) = entry.cooldown {
+                        let now = self.timer.now();
+                        let triggered_at = self.cooldowns.get(&(*key, index));
+                        if triggered_at.is_some_and(|triggered_at| now < *triggered_at + cooldown) {
+                            return Ok(match entry.on_cooldown {
+                                OnCooldown::Swallow => Some(vec![]),
+                                OnCooldown::Passthrough => None,
+                            });
                         }
+                        self.cooldowns.insert((*key, index), now);
                     }
 
+                    // Held modifiers are left alone if they're all extra
+                    let extra_modifiers = if entry.interleave_modifiers.unwrap_or(config.interleave_modifiers) {
+                        extra_modifiers
+                    } else {
+                        self.modifiers.clone()
+                    };
                     let actions = with_extra_modifiers(&entry.actions, &extra_modifiers, entry.exact_match);
                     let is_remap = is_remap(&entry.actions);
 
+                    if entry.on == KeymapTrigger::Release {
+                        if !remaps.is_empty() {
+                            continue;
+                        }
+                        // Hold the actions until the key is released, and swallow the press and repeats
+                        self.on_keymap_match(*key, index, entry);
+                        self.release_actions.insert(*key, actions);
+                        return Ok(Some(vec![]));
+                    }
+
                     // If the first/top match was a remap, continue to find rest of the eligible remaps for this key
                     if remaps.is_empty() && !is_remap {
+                        self.on_keymap_match(*key, index, entry);
+                        self.matched_repeat_rate = entry.repeat_rate;
                         return Ok(Some(actions));
                     } else if is_remap {
+                        self.on_keymap_match(*key, index, entry);
                         remaps.extend(actions)
                     }
                 }
@@ -534,9 +1301,77 @@ impl EventHandler {
         Ok(None)
     }
 
-    fn dispatch_actions(&mut self, actions: &Vec<TaggedAction>, key: &Key) -> Result<(), Box<dyn Error>> {
-        for action in actions {
+    // The actions of the first matching entry of a nested remap or a layer, or all of the matching ones
+    // merged if the first one is a nested remap itself. The bool tells if they're nested remaps.
+    fn match_override_entries(&self, config: &Config, entries: &[OverrideEntry]) -> Option<(Vec<TaggedAction>, bool)> {
+        for exact_match in [true, false] {
+            let mut remaps = vec![];
+            for entry in entries {
+                if entry.exact_match && !exact_match {
+                    continue;
+                }
+                let (extra_modifiers, missing_modifiers) = self.diff_modifiers(&entry.modifiers);
+                let any_modifiers = entry.modifiers.contains(&Modifier::Any);
+                if (exact_match && !any_modifiers && extra_modifiers.len() > 0) || missing_modifiers.len() > 0 {
+                    continue;
+                }
+
+                // Held modifiers are left alone if they're all extra
+                let extra_modifiers = if config.interleave_modifiers {
+                    extra_modifiers
+                } else {
+                    self.modifiers.clone()
+                };
+                let actions = with_extra_modifiers(&entry.actions, &extra_modifiers, entry.exact_match);
+                let is_remap = is_remap(&entry.actions);
+
+                // If the first/top match was a remap, continue to find rest of the eligible remaps for this key
+                if remaps.is_empty() && !is_remap {
+                    return Some((actions, false));
+                } else if is_remap {
+                    remaps.extend(actions);
+                }
+            }
+            if !remaps.is_empty() {
+                return Some((remaps, true));
+            }
+        }
+        None
+    }
+
+    fn on_startup(&mut self, config: &Config) -> Result<(), Box<dyn Error>> {
+        self.dispatch_keyless_actions(&config.on_startup)
+    }
+
+    // Actions like on_startup aren't triggered by a key, so KEY_RESERVED stands for it and is released right away
+    fn dispatch_keyless_actions(&mut self, actions: &[KeymapAction]) -> Result<(), Box<dyn Error>> {
+        let key = Key::KEY_RESERVED;
+        self.self_key = None;
+        let actions: Vec<TaggedAction> = actions
+            .iter()
+            .map(|action| TaggedAction {
+                action: action.clone(),
+                exact_match: false,
+            })
+            .collect();
+        self.dispatch_actions(&actions, &key)?;
+        if let Some(actions) = self.release_actions.remove(&key) {
+            self.dispatch_actions(&actions, &key)?;
+        }
+        self.run_keys.remove(&key);
+        if self.mouse_moves.remove(&key).is_some() && self.mouse_moves.is_empty() {
+            self.unset_deadline(Deadline::MouseMove)?;
+        }
+        Ok(())
+    }
+
+    fn dispatch_actions(&mut self, actions: &[TaggedAction], key: &Key) -> Result<(), Box<dyn Error>> {
+        for (i, action) in actions.iter().enumerate() {
             self.dispatch_action(action, key)?;
+            if let (KeymapAction::AwaitTitleChange(_), Some(awaiting)) = (&action.action, &mut self.awaiting_title) {
+                awaiting.actions = actions[i + 1..].iter().map(|action| action.action.clone()).collect();
+                break;
+            }
         }
         Ok(())
     }
@@ -548,6 +1383,10 @@ impl EventHandler {
                 remap,
                 timeout,
                 timeout_key,
+                replay,
+                on_timeout,
+                on_unmatched,
+                literal_timeout,
             }) => {
                 let set_timeout = self.override_remaps.is_empty();
                 self.override_remaps
@@ -556,24 +1395,206 @@ impl EventHandler {
                 // Set timeout only if this is the first of multiple eligible remaps,
                 // so the behaviour is consistent with how current normal keymap override works
                 if set_timeout {
-                    if let Some(timeout) = timeout {
-                        let expiration = Expiration::OneShot(TimeSpec::from_duration(*timeout));
+                    self.override_on_unmatched = *on_unmatched;
+                    // The modifiers that are held now make the key press, like C-x for a C-x prefix
+                    self.override_literal = literal_timeout.map(|literal_timeout| {
+                        let key_press = KeyPress {
+                            key: *key,
+                            modifiers: self
+                                .modifiers
+                                .iter()
+                                .filter(|modifier| MODIFIER_KEYS.contains(modifier))
+                                .map(|modifier| Modifier::Key(*modifier))
+                                .collect(),
+                        };
+                        (key_press, self.timer.now() + literal_timeout)
+                    });
+                    // Later steps of a sequence with { replay: true } keep its timeout unless they have their own
+                    let mut timeout = *timeout;
+                    let mut on_timeout = on_timeout.clone();
+                    if *replay || self.leader_sequence.is_some() {
+                        let sequence = self.leader_sequence.get_or_insert(LeaderSequence {
+                            keys: vec![],
+                            timeout,
+                            on_timeout: on_timeout.clone(),
+                        });
+                        sequence.keys.push(*key);
+                        timeout = timeout.or(sequence.timeout);
+                        if on_timeout.is_empty() {
+                            on_timeout = sequence.on_timeout.clone();
+                        }
+                    }
+                    if let Some(timeout) = &timeout {
                         // TODO: Consider handling the timer in ActionDispatcher
-                        self.override_timer.unset()?;
-                        self.override_timer.set(expiration, TimerSetTimeFlags::empty())?;
+                        self.set_deadline(Deadline::Override, self.timer.now() + *timeout)?;
                         self.override_timeout_key = timeout_key.or_else(|| Some(*key));
+                        self.override_timeout_actions = on_timeout;
                     }
                 }
             }
             KeymapAction::Launch(command) => self.run_command(command.clone()),
-            KeymapAction::SetMode(mode) => {
-                self.mode = mode.clone();
-                println!("mode: {}", mode);
+            KeymapAction::Run(run) => {
+                // Holding the key doesn't start a process per autorepeat unless { repeat: true }
+                if self.run_keys.insert(*key) || run.repeat {
+                    self.send_action(Action::Run(run.command.clone()));
+                }
+            }
+            KeymapAction::SetMode(set_mode) => self.set_mode(&set_mode.mode, set_mode.sticky),
+            KeymapAction::ClearMode(clear) => {
+                if *clear {
+                    self.set_mode(&self.default_mode.clone(), true);
+                }
             }
             KeymapAction::SetMark(set) => self.mark_set = *set,
             KeymapAction::WithMark(key_press) => self.send_key_press(&self.with_mark(key_press)),
             KeymapAction::EscapeNextKey(escape_next_key) => self.escape_next_key = *escape_next_key,
             KeymapAction::Sleep(millis) => self.send_action(Action::Delay(Duration::from_millis(*millis))),
+            KeymapAction::RawCode(code) => {
+                self.send_action(Action::RawCode(*code));
+                self.send_action(Action::Delay(self.key_delay));
+            }
+            KeymapAction::MouseMove(mouse_move) => {
+                // Move right away, and keep moving until the key is released. Autorepeat doesn't move it again.
+                if self.mouse_moves.insert(*key, mouse_move.clone()).is_none() {
+                    self.tick_mouse_move()?;
+                }
+            }
+            KeymapAction::MoveRelative(mouse_move) => self.send_mouse_movement(mouse_move.x, mouse_move.y),
+            KeymapAction::Scroll(scroll) => self.send_action(Action::Scroll {
+                x: scroll.x,
+                y: scroll.y,
+            }),
+            KeymapAction::Layer(layer) => {
+                // Activating it again replaces it and restarts the timeout
+                self.layer = Some(build_override_table(&layer.remap, action.exact_match));
+                self.set_deadline(Deadline::Layer, self.timer.now() + layer.timeout)?;
+            }
+            KeymapAction::SetGrab(grab) => {
+                self.grabbed = *grab;
+                self.send_action(Action::SetGrab(*grab));
+            }
+            KeymapAction::SetLed(set_led) => self.send_action(Action::SetLed {
+                led: set_led.led,
+                on: set_led.on,
+            }),
+            KeymapAction::Repeat(repeat) => {
+                for _ in 0..repeat.count {
+                    self.send_key_press(&repeat.key);
+                }
+            }
+            KeymapAction::KeysRaw(events) => {
+                // Emit exactly what's configured, skipping the modifier emulation of send_key_press
+                for (key, value) in events {
+                    self.send_key(key, *value);
+                }
+            }
+            KeymapAction::Chord(keys) => self.send_chord(keys),
+            KeymapAction::Compose(key_presses) => {
+                // Each key is pressed and released in turn, with keypress_delay_ms in between
+                self.send_key_press(&self.compose_key.clone());
+                for key_press in key_presses {
+                    self.send_key_press(key_press);
+                }
+            }
+            KeymapAction::Cycle(cycle) => {
+                let index = self.cycle_indices.entry(cycle.id).or_default();
+                let next_actions = &cycle.actions[*index % cycle.actions.len()];
+                *index = (*index + 1) % cycle.actions.len();
+                for next_action in next_actions {
+                    self.dispatch_action(
+                        &TaggedAction {
+                            action: next_action.clone(),
+                            exact_match: action.exact_match,
+                        },
+                        key,
+                    )?;
+                }
+            }
+            KeymapAction::If(conditional) => {
+                let now = self.timer.now();
+                let cached = self
+                    .predicate_results
+                    .get(&conditional.predicate)
+                    .filter(|(_, received_at)| now.duration_since(*received_at) < PREDICATE_CACHE_DURATION)
+                    .map(|(success, _)| *success);
+                match cached {
+                    Some(success) => {
+                        let next_actions = if success {
+                            &conditional.then
+                        } else {
+                            &conditional.otherwise
+                        };
+                        let next_actions: Vec<TaggedAction> = next_actions
+                            .iter()
+                            .map(|next_action| TaggedAction {
+                                action: next_action.clone(),
+                                exact_match: action.exact_match,
+                            })
+                            .collect();
+                        self.dispatch_actions(&next_actions, key)?;
+                    }
+                    // Dispatched on Event::PredicateResult. Presses until then wait for the same process.
+                    None => {
+                        let pending = self
+                            .pending_conditionals
+                            .entry(conditional.predicate.clone())
+                            .or_default();
+                        let running = !pending.is_empty();
+                        pending.push(conditional.clone());
+                        if !running {
+                            self.send_action(Action::Predicate(conditional.predicate.clone()));
+                        }
+                    }
+                }
+            }
+            KeymapAction::AwaitTitleChange(await_title_change) => {
+                // One at a time. The actions waiting for the previous one are dispatched first.
+                self.finish_awaiting_title()?;
+                let title = self.application_client.current_window();
+                self.send_key_press(&await_title_change.key);
+                // Polled on Event::TitlePoll, and the actions after this one are set by dispatch_actions
+                self.awaiting_title = Some(AwaitingTitle {
+                    title,
+                    deadline: self.timer.now() + await_title_change.timeout,
+                    interval: await_title_change.interval,
+                    actions: vec![],
+                });
+                self.send_action(Action::PollTitle(await_title_change.interval.min(await_title_change.timeout)));
+            }
+            KeymapAction::OnDevice(on_device) => {
+                let previous = self.selected_device.replace(on_device.device.clone());
+                self.send_action(Action::SelectDevice(Some(on_device.device.clone())));
+                let next_actions: Vec<TaggedAction> = on_device
+                    .actions
+                    .iter()
+                    .map(|next_action| TaggedAction {
+                        action: next_action.clone(),
+                        exact_match: action.exact_match,
+                    })
+                    .collect();
+                self.dispatch_actions(&next_actions, key)?;
+                // Nested ones go back to the device of the outer one
+                self.selected_device = previous.clone();
+                self.send_action(Action::SelectDevice(previous));
+            }
+            KeymapAction::HoldWhilePressed(target) => {
+                // Autorepeat of the trigger doesn't press it again
+                if self.held_keys.insert(*target) {
+                    self.send_key(target, PRESS);
+                    self.release_actions.entry(*key).or_default().push(TaggedAction {
+                        action: KeymapAction::Release(*target),
+                        exact_match: action.exact_match,
+                    });
+                }
+            }
+            KeymapAction::Press(key) => {
+                self.held_keys.insert(*key);
+                self.send_key(key, PRESS);
+            }
+            KeymapAction::Release(key) => {
+                self.held_keys.remove(key);
+                self.send_key(key, RELEASE);
+            }
             KeymapAction::SetExtraModifiers(keys) => {
                 self.extra_modifiers.clear();
                 for key in keys {
@@ -584,29 +1605,286 @@ impl EventHandler {
         Ok(())
     }
 
+    fn release_held_keys(&mut self) {
+        let mut keys: Vec<Key> = self.held_keys.drain().collect();
+        keys.sort_by_key(|key| key.code());
+        for key in keys {
+            self.send_key(&key, RELEASE);
+        }
+    }
+
+    // Entries are listed in the order they're looked up, and a count of 0 likely means dead config
+    fn print_match_counts(&self, config: &Config) {
+        let mut keys: Vec<&Key> = config.keymap_table.keys().collect();
+        keys.sort_by_key(|key| key.code());
+        eprintln!("{:>7}  keymap", "count");
+        for key in keys {
+            for (index, entry) in config.keymap_table[key].iter().enumerate() {
+                let key_press = KeyPress {
+                    key: *key,
+                    modifiers: entry.modifiers.clone(),
+                };
+                let count = self.match_counts.get(&(*key, index)).copied().unwrap_or(0);
+                let name = if entry.name.is_empty() { "-" } else { &entry.name };
+                match &entry.description {
+                    Some(description) => eprintln!("{:>7}  {}: {} ({})", count, name, key_press, description),
+                    None => eprintln!("{:>7}  {}: {}", count, name, key_press),
+                }
+            }
+        }
+    }
+
+    // The conditions of a keymap are ANDed, and stop being evaluated at the first one that doesn't match
+    fn match_condition(&mut self, condition: &Condition, device: &InputDeviceInfo) -> bool {
+        match condition {
+            Condition::Window(window_matcher) => self.match_window(window_matcher),
+            Condition::Application(application_matcher) => self.match_application(application_matcher),
+            Condition::Process(process_matcher) => self.match_process(process_matcher),
+            Condition::Device(device_matcher) => self.match_device(device_matcher, device),
+            Condition::Mode(modes) => modes.contains(&self.mode),
+            Condition::Led(leds) => self.match_led(leds, device),
+            Condition::Held(keys) => keys.iter().all(|key| self.modifiers.contains(key)),
+            Condition::After(after, timeout) => self.match_after(*after, *timeout),
+        }
+    }
+
+    fn match_after(&self, after: Key, timeout: Option<Duration>) -> bool {
+        match self.previous_key {
+            Some((key, pressed_at)) if key == after => {
+                timeout.is_none_or(|timeout| self.timer.now() <= pressed_at + timeout)
+            }
+            _ => false,
+        }
+    }
+
+    // Release modifiers and { press: key }, which may be pressed on the output device
+    fn release_pressed_keys(&mut self) {
+        let mut keys: Vec<Key> = self.modifiers.drain(..).chain(self.held_keys.drain()).collect();
+        keys.sort_by_key(|key| key.code());
+        keys.dedup();
+        for key in keys {
+            self.send_key(&key, RELEASE);
+        }
+    }
+
+    fn match_panic_key(&self, config: &Config, key: &Key) -> bool {
+        match &config.panic_key {
+            Some(panic_key) if panic_key.key == *key => {
+                let (extra_modifiers, missing_modifiers) = self.diff_modifiers(&panic_key.modifiers);
+                extra_modifiers.is_empty() && missing_modifiers.is_empty()
+            }
+            _ => false,
+        }
+    }
+
+    // Dispatch the actions of the keys whose tap_timeout has passed
+    fn timeout_taps(&mut self) -> Result<(), Box<dyn Error>> {
+        let now = self.timer.now();
+        let mut tap_keys: Vec<(Key, Instant)> = self
+            .tap_counts
+            .iter()
+            .filter(|(_, state)| state.deadline <= now)
+            .map(|(tap_key, state)| (*tap_key, state.deadline))
+            .collect();
+        tap_keys.sort_by_key(|(_, deadline)| *deadline);
+        for (tap_key, _) in tap_keys {
+            self.finish_taps(&tap_key)?;
+        }
+        self.set_tap_deadline()
+    }
+
+    // Dispatch the actions for the count of taps, if any
+    fn finish_taps(&mut self, key: &Key) -> Result<(), Box<dyn Error>> {
+        if let Some(state) = self.tap_counts.remove(key) {
+            match state.taps.get(&state.count) {
+                Some(actions) => self.dispatch_keyless_actions(actions)?,
+                None => debug!("No actions for {} taps of {:?}", state.count, key),
+            }
+        }
+        Ok(())
+    }
+
+    // Set Deadline::Tap to the earliest tap_timeout, or unset it if no key is being tapped
+    fn set_tap_deadline(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.tap_counts.values().map(|state| state.deadline).min() {
+            Some(deadline) => self.set_deadline(Deadline::Tap, deadline),
+            None => self.unset_deadline(Deadline::Tap),
+        }
+    }
+
+    fn set_deadline(&mut self, deadline: Deadline, at: Instant) -> Result<(), Box<dyn Error>> {
+        self.deadlines.insert(deadline, at);
+        self.arm_timer()
+    }
+
+    fn unset_deadline(&mut self, deadline: Deadline) -> Result<(), Box<dyn Error>> {
+        match self.deadlines.remove(&deadline) {
+            Some(_) => self.arm_timer(),
+            None => Ok(()),
+        }
+    }
+
+    // Arm the timer for the earliest deadline, or disarm it if there's none
+    fn arm_timer(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.deadlines.values().min() {
+            // A zero duration would disarm the timer
+            Some(at) => self.timer.set(
+                at.saturating_duration_since(self.timer.now())
+                    .max(Duration::from_nanos(1)),
+            ),
+            None => self.timer.unset(),
+        }
+    }
+
+    // Handle the deadlines that have passed, and arm the timer for the next one.
+    // It's armed again even if nothing was due, since an expired timerfd stays readable until then.
+    fn on_timeout(
+        &mut self,
+        mouse_movement_collection: &mut Vec<RelativeEvent>,
+        config: &Config,
+    ) -> Result<(), Box<dyn Error>> {
+        let now = self.timer.now();
+        let mut due: Vec<(Instant, Deadline)> = self
+            .deadlines
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(deadline, at)| (*at, *deadline))
+            .collect();
+        due.sort();
+        for (_, deadline) in due {
+            // Handling an earlier one may have unset or moved it
+            if self.deadlines.get(&deadline).is_none_or(|at| *at > now) {
+                continue;
+            }
+            self.deadlines.remove(&deadline);
+            match deadline {
+                Deadline::Override => {
+                    let actions = std::mem::take(&mut self.override_timeout_actions);
+                    self.timeout_override()?;
+                    self.dispatch_keyless_actions(&actions)?;
+                }
+                Deadline::MouseMove => self.tick_mouse_move()?,
+                // Flushed by other events, unless mouse_movement_coalescing was disabled by a reload meanwhile
+                Deadline::Coalesce => self.flush_coalesced_movement(mouse_movement_collection, config)?,
+                Deadline::Tap => self.timeout_taps()?,
+                Deadline::Repeat => self.tick_repeat()?,
+                Deadline::Layer => self.layer = None,
+            }
+        }
+        self.arm_timer()
+    }
+
+    // Dispatch the actions waiting for the title once it changes or the timeout is reached
+    fn poll_title(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(awaiting) = &self.awaiting_title else {
+            // Already finished by another { await_title_change: ... } or panic_key
+            return Ok(());
+        };
+        let now = self.timer.now();
+        if self.application_client.current_window() != awaiting.title {
+            debug!("The title changed from {:?}", awaiting.title);
+        } else if now >= awaiting.deadline {
+            debug!("The title stayed {:?} until the timeout", awaiting.title);
+        } else {
+            self.send_action(Action::PollTitle(awaiting.interval.min(awaiting.deadline - now)));
+            return Ok(());
+        }
+        self.finish_awaiting_title()
+    }
+
+    fn finish_awaiting_title(&mut self) -> Result<(), Box<dyn Error>> {
+        while let Some(awaiting) = self.awaiting_title.take() {
+            self.dispatch_keyless_actions(&awaiting.actions)?;
+        }
+        Ok(())
+    }
+
+    // panic_key: Release every key xremap has pressed, and reset nested remaps and the mode
+    fn release_all(&mut self, config: &Config) -> Result<(), Box<dyn Error>> {
+        self.release_pressed_keys();
+        self.extra_modifiers.clear();
+        self.multi_purpose_keys.clear();
+        self.multi_purpose_delayed_keys.clear();
+        self.double_tap_keys.clear();
+        self.tap_counts.clear();
+        self.unset_deadline(Deadline::Tap)?;
+        if self.repeating.take().is_some() {
+            self.unset_deadline(Deadline::Repeat)?;
+        }
+        if self.layer.take().is_some() {
+            self.unset_deadline(Deadline::Layer)?;
+        }
+        self.release_actions.clear();
+        self.run_keys.clear();
+        if !self.mouse_moves.is_empty() {
+            self.mouse_moves.clear();
+            self.unset_deadline(Deadline::MouseMove)?;
+        }
+        self.leader_sequence = None;
+        self.awaiting_title = None;
+        self.remove_override()?;
+        self.mark_set = false;
+        self.escape_next_key = false;
+        self.set_mode(&config.default_mode, true);
+        Ok(())
+    }
+
+    fn set_mode(&mut self, mode: &str, sticky: bool) {
+        self.mode = mode.to_string();
+        self.mode_sticky = sticky;
+        eprintln!("mode: {}", mode);
+    }
+
     fn send_key_press(&mut self, key_press: &KeyPress) {
         // Build extra or missing modifiers. Note that only MODIFIER_KEYS are handled
         // because logical modifiers shouldn't make an impact outside xremap.
         let (mut extra_modifiers, mut missing_modifiers) = self.diff_modifiers(&key_press.modifiers);
         extra_modifiers.retain(|key| MODIFIER_KEYS.contains(&key) && !self.extra_modifiers.contains(&key));
         missing_modifiers.retain(|key| MODIFIER_KEYS.contains(&key));
+        // A stable sort keeps the order of the key press for unlisted modifiers
+        missing_modifiers.sort_by_key(|key| {
+            self.modifier_order
+                .iter()
+                .position(|modifier| modifier == key)
+                .unwrap_or(self.modifier_order.len())
+        });
 
         // Emulate the modifiers of KeyPress
         self.send_keys(&missing_modifiers, PRESS);
         self.send_keys(&extra_modifiers, RELEASE);
+        // Give applications time to see the modifiers before the key
+        let toggled = !missing_modifiers.is_empty() || !extra_modifiers.is_empty();
+        if toggled && !self.pre_key_delay.is_zero() {
+            self.send_action(Action::Delay(self.pre_key_delay));
+        }
 
         // Press the main key
         self.send_key(&key_press.key, PRESS);
         self.send_key(&key_press.key, RELEASE);
 
-        self.send_action(Action::Delay(self.keypress_delay));
+        self.send_action(Action::Delay(self.key_delay));
 
         // Resurrect the original modifiers
         self.send_keys(&extra_modifiers, PRESS);
-        self.send_action(Action::Delay(self.keypress_delay));
+        self.send_action(Action::Delay(self.modifier_delay));
         self.send_keys(&missing_modifiers, RELEASE);
     }
 
+    // Press the keys in order and release them in reverse, e.g. Alt+Shift to switch the keyboard layout.
+    // Modifiers being held are released meanwhile so that only the keys of the chord are pressed.
+    fn send_chord(&mut self, keys: &Vec<Key>) {
+        let (mut held_modifiers, _) = self.diff_modifiers(&vec![]);
+        held_modifiers.retain(|key| MODIFIER_KEYS.contains(key) && !self.extra_modifiers.contains(key));
+
+        self.send_keys(&held_modifiers, RELEASE);
+        self.send_keys(keys, PRESS);
+        for key in keys.iter().rev() {
+            self.send_key(key, RELEASE);
+        }
+        self.send_action(Action::Delay(self.key_delay));
+        self.send_keys(&held_modifiers, PRESS);
+    }
+
     fn with_mark(&self, key_press: &KeyPress) -> KeyPress {
         if self.mark_set && !self.match_modifier(&Modifier::Shift) {
             let mut modifiers = key_press.modifiers.clone();
@@ -644,6 +1922,7 @@ impl EventHandler {
                         Modifier::Alt => Some(Key::KEY_LEFTALT),
                         Modifier::Windows => Some(Key::KEY_LEFTMETA),
                         Modifier::Key(key) => Some(*key),
+                        Modifier::Any => None,
                     }
                 }
             })
@@ -664,6 +1943,7 @@ impl EventHandler {
                 self.modifiers.contains(&Key::KEY_LEFTMETA) || self.modifiers.contains(&Key::KEY_RIGHTMETA)
             }
             Modifier::Key(key) => self.modifiers.contains(key),
+            Modifier::Any => true,
         }
     }
     fn match_window(&mut self, window_matcher: &OnlyOrNot) -> bool {
@@ -693,6 +1973,13 @@ impl EventHandler {
                 Some(application) => self.application_cache = Some(application),
                 None => self.application_cache = Some(String::new()),
             }
+            let fresh_as_of = self.application_client.fresh_as_of();
+            if fresh_as_of < self.event {
+                debug!(
+                    "Matching event {} with the application reported as of event {}: {:?}",
+                    self.event, fresh_as_of, self.application_cache
+                );
+            }
         }
 
         if let Some(application) = &self.application_cache {
@@ -706,16 +1993,37 @@ impl EventHandler {
         false
     }
 
-    fn match_device(&self, device_matcher: &config::device::Device, device: &InputDeviceInfo) -> bool {
-        if let Some(device_only) = &device_matcher.only {
-            return device_only.iter().any(|m| device.matches(m));
+    fn match_process(&mut self, process_matcher: &OnlyOrNot) -> bool {
+        // Lazily fill the process cache, falling back to the application for clients that don't know the PID
+        if self.process_cache.is_none() {
+            self.process_cache = Some(match self.application_client.current_process() {
+                Some(process) => process,
+                None => self.application_client.current_application().unwrap_or_default(),
+            });
         }
-        if let Some(device_not) = &device_matcher.not {
-            return device_not.iter().all(|m| !device.matches(m));
+
+        if let Some(process) = &self.process_cache {
+            if let Some(process_only) = &process_matcher.only {
+                return process_only.iter().any(|m| m.matches(process));
+            }
+            if let Some(process_not) = &process_matcher.not {
+                return process_not.iter().all(|m| !m.matches(process));
+            }
         }
         false
     }
 
+    fn match_device(&self, device_matcher: &config::device::Device, device: &InputDeviceInfo) -> bool {
+        device_matcher.matches(device)
+    }
+
+    fn match_led(&self, leds: &[LedType], device: &InputDeviceInfo) -> bool {
+        match self.led_state.get(device.path) {
+            Some(leds_on) => leds.iter().all(|led| leds_on.contains(led)),
+            None => false,
+        }
+    }
+
     fn update_modifier(&mut self, key: Key, value: i32) {
         if value == PRESS {
             self.modifiers.push(key);
@@ -769,6 +2077,14 @@ fn with_extra_modifiers(
     return result;
 }
 
+fn match_repeat(repeat: KeymapRepeat, value: i32) -> bool {
+    match repeat {
+        KeymapRepeat::Include => true,
+        KeymapRepeat::Ignore => value != REPEAT,
+        KeymapRepeat::Only => value == REPEAT,
+    }
+}
+
 fn contains_modifier(modifiers: &Vec<Modifier>, key: &Key) -> bool {
     for modifier in modifiers {
         if match modifier {
@@ -777,6 +2093,8 @@ fn contains_modifier(modifiers: &Vec<Modifier>, key: &Key) -> bool {
             Modifier::Alt => key == &Key::KEY_LEFTALT || key == &Key::KEY_RIGHTALT,
             Modifier::Windows => key == &Key::KEY_LEFTMETA || key == &Key::KEY_RIGHTMETA,
             Modifier::Key(modifier_key) => key == modifier_key,
+            // Keep extra modifiers pressed for the actions
+            Modifier::Any => false,
         } {
             return true;
         }
@@ -812,20 +2130,70 @@ pub static RELEASE: i32 = 0;
 pub static PRESS: i32 = 1;
 pub static REPEAT: i32 = 2;
 
+// RelativeEvent#code of mouse movement and scroll wheels
+const REL_X: u16 = 0;
+const REL_Y: u16 = 1;
+const REL_HWHEEL: u16 = 6;
+const REL_WHEEL: u16 = 8;
+const REL_WHEEL_HI_RES: u16 = 11;
+const REL_HWHEEL_HI_RES: u16 = 12;
+
 // ---
 
+#[derive(Debug, Default)]
+struct ScrollRate {
+    // true once the device has sent a hi-res scroll on this axis
+    hires: bool,
+    samples: VecDeque<(Instant, i32)>,
+}
+
+// Owns the device info since it's kept across on_events
+#[derive(Debug)]
+struct CoalescedMovement {
+    name: String,
+    path: PathBuf,
+    x: i32,
+    y: i32,
+}
+
+#[derive(Debug, Default)]
+struct DoubleTapState {
+    // When the key was last tapped without pressing other keys in between
+    tapped_at: Option<Instant>,
+    // No other key has been pressed since the key was pressed
+    clean: bool,
+    // The current press emits double_tap
+    morphed: bool,
+}
+
+impl DoubleTapState {
+    fn cancel_tap(&mut self) {
+        self.tapped_at = None;
+        self.clean = false;
+    }
+}
+
+#[derive(Debug)]
+struct TapCountState {
+    count: usize,
+    // When the count is settled unless the key is tapped again
+    deadline: Instant,
+    taps: BTreeMap<usize, Vec<KeymapAction>>,
+}
+
 #[derive(Debug)]
 struct MultiPurposeKeyState {
     held: Keys,
     alone: Keys,
     // Some if the first press is still delayed, None if already considered held.
     alone_timeout_at: Option<Instant>,
+    tap_hold_policy: TapHoldPolicy,
 }
 
 impl MultiPurposeKeyState {
-    fn repeat(&mut self) -> Vec<(Key, i32)> {
+    fn repeat(&mut self, now: Instant) -> Vec<(Key, i32)> {
         if let Some(alone_timeout_at) = &self.alone_timeout_at {
-            if Instant::now() < *alone_timeout_at {
+            if now < *alone_timeout_at {
                 vec![] // still delay the press
             } else {
                 self.alone_timeout_at = None; // timeout
@@ -840,9 +2208,9 @@ impl MultiPurposeKeyState {
         }
     }
 
-    fn release(&self) -> Vec<(Key, i32)> {
+    fn release(&self, now: Instant) -> Vec<(Key, i32)> {
         if let Some(alone_timeout_at) = &self.alone_timeout_at {
-            if Instant::now() < *alone_timeout_at {
+            if now < *alone_timeout_at {
                 // dispatch the delayed press and this release
                 let mut release_keys = self.alone.clone().into_vec();
                 release_keys.sort_by(modifiers_last);
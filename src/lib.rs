@@ -0,0 +1,86 @@
+//! The remapping core of xremap, usable without its device and main loop layer.
+//!
+//! A [`Config`] is deserialized from YAML or TOML, and its keymaps are indexed with
+//! [`build_keymap_table`]. An [`EventHandler`] then turns [`Event`]s read from input devices
+//! into [`Action`]s to be emitted. It doesn't touch any device by itself, so an embedder can
+//! feed it events from anywhere and emit the actions however it likes.
+//!
+//! ```
+//! use std::time::{Duration, Instant};
+//! use evdev::Key;
+//! use xremap::event::{KeyEvent, KeyValue};
+//! use xremap::{build_keymap_table, Action, Client, Config, Event, EventHandler, InputDeviceInfo, Timer, WMClient};
+//!
+//! // No window manager to ask for the application
+//! struct NoClient;
+//! impl Client for NoClient {
+//!     fn supported(&mut self) -> bool {
+//!         false
+//!     }
+//!     fn current_application(&mut self) -> Option<String> {
+//!         None
+//!     }
+//!     fn current_window(&mut self) -> Option<String> {
+//!         None
+//!     }
+//! }
+//!
+//! // The timer is only armed by timeouts, which this config doesn't use
+//! struct NoTimer;
+//! impl Timer for NoTimer {
+//!     fn set(&mut self, _: Duration) -> Result<(), Box<dyn std::error::Error>> {
+//!         Ok(())
+//!     }
+//!     fn unset(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+//!         Ok(())
+//!     }
+//!     fn now(&self) -> Instant {
+//!         Instant::now()
+//!     }
+//! }
+//!
+//! let mut config: Config = serde_yaml::from_str("modmap:\n  - remap:\n      CapsLock: Esc\n").unwrap();
+//! config.keymap_table = build_keymap_table(&config.keymap, config.default_exact_match, config.merge_strategy);
+//! let mut handler = EventHandler::new(
+//!     NoTimer,
+//!     "default",
+//!     Duration::ZERO,
+//!     config.mouse_move_interval,
+//!     WMClient::new("none", Box::new(NoClient)),
+//! );
+//!
+//! let device = InputDeviceInfo {
+//!     name: "keyboard",
+//!     path: std::path::Path::new("/dev/input/event0"),
+//! };
+//! let events = vec![Event::KeyEvent(device, KeyEvent::new(Key::KEY_CAPSLOCK, KeyValue::Press))];
+//! let actions = handler.on_events(&events, &config).unwrap();
+//! assert!(actions
+//!     .iter()
+//!     .any(|action| matches!(action, Action::KeyEvent(event) if event.key == Key::KEY_ESC)));
+//! ```
+//!
+//! The rest is the I/O layer of the xremap binary: [`device`] grabs input devices and creates
+//! the uinput output device, and [`action_dispatcher`] emits actions on it. Embedders that
+//! bring their own input and output don't need either of them.
+
+pub mod action;
+pub mod action_dispatcher;
+pub mod client;
+pub mod config;
+pub mod device;
+pub mod event;
+pub mod event_handler;
+pub mod log_file;
+pub mod timer;
+
+#[cfg(test)]
+mod tests;
+
+pub use action::Action;
+pub use client::{Client, WMClient};
+pub use config::keymap::build_keymap_table;
+pub use config::Config;
+pub use event::{Event, InputDeviceInfo};
+pub use event_handler::EventHandler;
+pub use timer::Timer;
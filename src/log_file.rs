@@ -0,0 +1,136 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// A log file that's renamed to FILE.1 when it would grow past max_size, shifting older ones to FILE.2
+// and so on up to FILE.KEEP. It's only rotated between lines, so that a line never spans two files.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+    at_line_start: bool,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, keep: usize) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_size,
+            keep,
+            file,
+            size,
+            at_line_start: true,
+        })
+    }
+
+    fn rotated_path(&self, suffix: impl std::fmt::Display) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{}", suffix));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        // Opened before anything is moved, so that lines never go to a removed file
+        let new_path = self.rotated_path("new");
+        let file = File::create(&new_path)?;
+        if let Err(e) = self.move_files(&new_path) {
+            let _ = fs::remove_file(&new_path);
+            return Err(e);
+        }
+        self.file = file;
+        self.size = 0;
+        Ok(())
+    }
+
+    // Shift FILE.1 and so on, and replace FILE with the new one
+    fn move_files(&self, new_path: &Path) -> io::Result<()> {
+        if self.keep > 0 {
+            for index in (1..self.keep).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        fs::rename(new_path, &self.path)
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.at_line_start && self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            // Failing to rotate shouldn't lose the line, so it's written to the current file instead,
+            // which is only tried to rotate again after another max_size bytes
+            if let Err(e) = self.rotate() {
+                eprintln!("Failed to rotate the log file {}: {}", self.path.display(), e);
+                self.size = 0;
+            }
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        if let Some(last) = buf.last() {
+            self.at_line_start = *last == b'\n';
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RotatingFile;
+    use std::env::temp_dir;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = temp_dir().join(format!("xremap-test-log-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("xremap.log");
+        let mut file = RotatingFile::open(&path, 12, 2).unwrap();
+        for i in 0..7 {
+            // A line written in two parts isn't split across files
+            write!(file, "line {}", i).unwrap();
+            writeln!(file, " of 7").unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 6 of 7\n");
+        assert_eq!(fs::read_to_string(dir.join("xremap.log.1")).unwrap(), "line 5 of 7\n");
+        assert_eq!(fs::read_to_string(dir.join("xremap.log.2")).unwrap(), "line 4 of 7\n");
+        assert!(!dir.join("xremap.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotating_file_failure() {
+        let dir = temp_dir().join(format!("xremap-test-log-file-failure-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("xremap.log");
+        let mut file = RotatingFile::open(&path, 24, 0).unwrap();
+        // The new file can't be created over a directory
+        fs::create_dir(dir.join("xremap.log.new")).unwrap();
+        for i in 0..3 {
+            writeln!(file, "line {} of 5", i).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 0 of 5\nline 1 of 5\nline 2 of 5\n");
+
+        // It isn't tried again until the file grows by max_size
+        fs::remove_dir(dir.join("xremap.log.new")).unwrap();
+        writeln!(file, "line 3 of 5").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+        writeln!(file, "line 4 of 5").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 4 of 5\n");
+        assert!(!dir.join("xremap.log.1").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,34 +1,30 @@
-use crate::config::Config;
-use crate::device::{device_watcher, get_input_devices, output_device};
-use crate::event_handler::EventHandler;
-use action_dispatcher::ActionDispatcher;
 use anyhow::{anyhow, bail, Context};
 use clap::{CommandFactory, Parser, ValueEnum};
 use clap_complete::Shell;
-use client::build_client;
-use config::{config_watcher, load_configs};
-use device::InputDevice;
-use event::Event;
-use nix::libc::ENODEV;
+use evdev::{EventType, InputEvent};
+use nix::libc::{EAGAIN, EINTR, ENODEV};
 use nix::sys::inotify::{AddWatchFlags, Inotify, InotifyEvent};
 use nix::sys::select::select;
 use nix::sys::select::FdSet;
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::{SfdFlags, SignalFd};
 use nix::sys::timerfd::{ClockId, TimerFd, TimerFlags};
 use std::collections::HashMap;
-use std::io::stdout;
+use std::fs::File;
+use std::io::{self, stdout, Write};
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::path::PathBuf;
-use std::time::Duration;
-
-mod action;
-mod action_dispatcher;
-mod client;
-mod config;
-mod device;
-mod event;
-mod event_handler;
-#[cfg(test)]
-mod tests;
+use std::path::{Path, PathBuf};
+use xremap::action::{Action, ActionTrace};
+use xremap::action_dispatcher::{ActionDispatcher, PredicateRunner, TitlePoller};
+use xremap::client::{build_client, build_named_client};
+use xremap::config::{self, config_watcher, load_configs, watch_config_files, Config};
+use xremap::device::{
+    self, device_watcher, get_input_devices, named_output_device, output_device, output_devices, rescan_input_devices,
+    DeviceRescanner, InputDevice,
+};
+use xremap::event::Event;
+use xremap::event_handler::EventHandler;
+use xremap::log_file::RotatingFile;
 
 #[derive(Parser, Debug)]
 #[command(version)]
@@ -46,6 +42,34 @@ struct Args {
     #[arg(long, value_enum, num_args = 0.., value_delimiter = ',', require_equals = true,
            default_missing_value = "device", verbatim_doc_comment)]
     watch: Vec<WatchTargets>,
+    /// Grab matching devices when they're connected, same as --watch=device
+    #[arg(long)]
+    watch_devices: bool,
+    /// Name the output device "xremap seat=SEAT" so that a udev rule can assign it to the seat
+    #[arg(long, value_name = "SEAT")]
+    seat: Option<String>,
+    /// Query the window manager with this client instead of the one xremap is built with.
+    /// none never queries it, so application and window filters see an empty name
+    #[arg(long, value_enum, value_name = "CLIENT")]
+    client: Option<ClientName>,
+    /// Write every emitted action to a file as a line of JSON (NDJSON). Use - for stdout
+    #[arg(long, value_name = "FILE")]
+    trace_json: Option<PathBuf>,
+    /// Write the log to a file instead of stderr, rotating it by size
+    #[arg(long, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+    /// Rotate the log file when it would grow past this many bytes
+    #[arg(long, value_name = "BYTES", default_value_t = 10 * 1024 * 1024)]
+    log_file_max_size: u64,
+    /// Keep this many rotated log files as FILE.1, FILE.2, and so on
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    log_file_keep: usize,
+    /// Print the keymaps resolved from the config files as YAML, and exit
+    #[arg(long)]
+    dump_config: bool,
+    /// List readable devices with their vendor:product IDs and event types, and exit
+    #[arg(long)]
+    list_devices: bool,
     /// Generate shell completions
     ///
     /// You can use them by storing in your shells completion file or by running
@@ -53,8 +77,12 @@ struct Args {
     /// - in fish: xremap --completions fish | source
     #[arg(long, value_enum, display_order = 100, value_name = "SHELL", verbatim_doc_comment)]
     completions: Option<Shell>,
-    /// Config file(s)
-    #[arg(required_unless_present = "completions", num_args = 1..)]
+    /// Config file(s), or - for stdin
+    #[arg(
+        env = "XREMAP_CONFIG",
+        required_unless_present_any = ["completions", "list_devices"],
+        num_args = 1..
+    )]
     configs: Vec<PathBuf>,
 }
 
@@ -66,95 +94,302 @@ enum WatchTargets {
     Config,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ClientName {
+    Gnome,
+    Kde,
+    Hypr,
+    X11,
+    /// also for Sway
+    #[value(alias = "sway")]
+    Wlroots,
+    None,
+}
+
 // TODO: Unify this with Event
 enum ReloadEvent {
     ReloadConfig,
-    ReloadDevices,
 }
 
 fn main() -> anyhow::Result<()> {
-    env_logger::init();
-
     let Args {
         device: device_filter,
         ignore: ignore_filter,
         mouse,
         watch,
+        watch_devices,
         configs,
+        trace_json,
+        log_file,
+        log_file_max_size,
+        log_file_keep,
+        seat,
+        client,
+        dump_config,
+        list_devices,
         completions,
     } = Args::parse();
 
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(path) = &log_file {
+        let file = RotatingFile::open(path, log_file_max_size, log_file_keep)
+            .with_context(|| format!("Failed to open the log file {}", path.display()))?;
+        logger
+            .target(env_logger::Target::Pipe(Box::new(file)))
+            .write_style(env_logger::WriteStyle::Never);
+    }
+    logger.init();
+
     if let Some(shell) = completions {
         clap_complete::generate(shell, &mut Args::command(), "xremap", &mut stdout());
         return Ok(());
     }
 
+    if list_devices {
+        return device::list_devices();
+    }
+
     // Configuration
+    // Config files given as arguments take precedence over XREMAP_CONFIG
     let config_paths = match configs[..] {
-        [] => panic!("config is set, if not completions"),
+        [] => panic!("config is set, if not completions or list_devices"),
         _ => configs,
     };
 
     let mut config = match config::load_configs(&config_paths) {
         Ok(config) => config,
-        Err(e) => bail!(
-            "Failed to load config '{}': {}",
-            config_paths
-                .iter()
-                .map(|p| p.to_string_lossy())
-                .collect::<Vec<_>>()
-                .join("', '"),
-            e
-        ),
+        Err(e) => bail!("Failed to load config: {}", e),
     };
-    let watch_devices = watch.contains(&WatchTargets::Device);
+    if dump_config {
+        print!("{}", config::dump::dump_config(&config)?);
+        return Ok(());
+    }
+    let watch_devices = watch_devices || watch.contains(&WatchTargets::Device);
     let watch_config = watch.contains(&WatchTargets::Config);
+    if watch_config && config_paths.iter().any(|path| config::is_stdin(path)) {
+        bail!("--watch=config can't reload a config read from stdin");
+    }
+    if let Some(seat) = &seat {
+        device::set_seat(seat);
+    }
 
     // Event listeners
     let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::empty())?;
     let timer_fd = timer.as_raw_fd();
-    let delay = Duration::from_millis(config.keypress_delay_ms);
-    let mut input_devices = match get_input_devices(&device_filter, &ignore_filter, mouse, watch_devices) {
+    // Blocked before any thread is spawned so that only the signalfd receives them
+    let mut signal_mask = SigSet::empty();
+    signal_mask.add(Signal::SIGUSR1);
+    signal_mask.add(Signal::SIGTERM);
+    signal_mask.add(Signal::SIGINT);
+    signal_mask.thread_block()?;
+    let mut signal_fd = SignalFd::with_flags(&signal_mask, SfdFlags::SFD_NONBLOCK)?;
+    let signal_fd_raw = signal_fd.as_raw_fd();
+    let client = match client {
+        Some(name) => {
+            let name = name.to_possible_value().unwrap();
+            build_named_client(name.get_name()).ok_or_else(|| {
+                anyhow!("The {0} client isn't built in. Build xremap with --features {0} to use it.", name.get_name())
+            })?
+        }
+        None => build_client(),
+    };
+    let delay = config.keypress_delay;
+    let mouse_move_interval = config.mouse_move_interval;
+    let mut input_devices = match get_input_devices(&device_filter, &ignore_filter, mouse, watch_devices, &config) {
         Ok(input_devices) => input_devices,
         Err(e) => bail!("Failed to prepare input devices: {}", e),
     };
     let device_watcher = device_watcher(watch_devices).context("Setting up device watcher")?;
-    let config_watcher = config_watcher(watch_config, &config_paths).context("Setting up config watcher")?;
+    let config_watcher = config_watcher(watch_config, &config.files).context("Setting up config watcher")?;
     let watchers: Vec<_> = device_watcher.iter().chain(config_watcher.iter()).collect();
-    let mut handler = EventHandler::new(timer, &config.default_mode, delay, build_client());
-    let output_device =
-        match output_device(input_devices.values().next().map(InputDevice::bus_type), config.enable_wheel) {
-            Ok(output_device) => output_device,
-            Err(e) => bail!("Failed to prepare an output device: {}", e),
-        };
+    let mut handler = EventHandler::new(timer, &config.default_mode, delay, mouse_move_interval, client);
+    let bus_type = config
+        .virtual_device
+        .bus_type
+        .or_else(|| input_devices.values().next().map(InputDevice::bus_type));
+    let output_devices = if config.single_output_device {
+        output_device(bus_type, config.enable_wheel, config.enable_hires_wheel, config.emit_scancodes)
+            .map(|device| (device, None))
+    } else {
+        output_devices(bus_type, config.enable_wheel, config.enable_hires_wheel, config.emit_scancodes)
+            .map(|(keyboard, pointer)| (keyboard, Some(pointer)))
+    };
+    let (output_device, pointer_device) = match output_devices {
+        Ok(output_devices) => output_devices,
+        Err(e) => bail!("Failed to prepare an output device: {}", e),
+    };
     let mut dispatcher = ActionDispatcher::new(output_device);
+    if let Some(pointer_device) = pointer_device {
+        dispatcher.set_pointer_device(pointer_device);
+    }
+    for virtual_device in &config.virtual_devices {
+        let device = named_output_device(
+            virtual_device,
+            bus_type,
+            config.enable_wheel,
+            config.enable_hires_wheel,
+            config.emit_scancodes,
+        )
+        .map_err(|e| anyhow!("Failed to prepare the virtual device {}: {}", virtual_device.name, e))?;
+        dispatcher.add_named_device(&virtual_device.name, device);
+    }
+    dispatcher.set_syn_report(config.syn_report);
+    dispatcher.set_run_max_concurrency(config.run_max_concurrency);
+    dispatcher.set_max_output_rate(config.max_output_rate);
+    dispatcher.set_hires_wheel(config.enable_hires_wheel);
+    dispatcher.set_emit_scancodes(config.emit_scancodes);
+    let predicate_runner = PredicateRunner::new()?;
+    let predicate_fd = predicate_runner.as_raw_fd();
+    dispatcher.set_predicate_runner(predicate_runner);
+    let title_poller = TitlePoller::new()?;
+    let title_poller_fd = title_poller.as_raw_fd();
+    dispatcher.set_title_poller(title_poller);
+    let mut device_rescanner = DeviceRescanner::new()?;
+    let device_rescanner_fd = device_rescanner.as_raw_fd();
+    if let Some(path) = trace_json {
+        let writer: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(stdout())
+        } else {
+            Box::new(File::create(&path).with_context(|| format!("Creating --trace-json file '{}'", path.display()))?)
+        };
+        dispatcher.set_trace(ActionTrace::new(writer));
+    }
+    // Toggled by { grab: bool }
+    let mut devices_grabbed = true;
+    // A broken on_startup action shouldn't stop xremap from starting
+    if let Err(error) = handle_events(&mut handler, &mut dispatcher, &mut config, vec![Event::Startup]) {
+        eprintln!("Error on startup actions: {error}")
+    }
 
     // Main loop
     loop {
         match 'event_loop: loop {
-            let readable_fds = select_readable(input_devices.values(), &watchers, timer_fd)?;
+            let readable_fds = select_readable(
+                input_devices.values(),
+                &watchers,
+                &[
+                    timer_fd,
+                    signal_fd_raw,
+                    predicate_fd,
+                    title_poller_fd,
+                    device_rescanner_fd,
+                ],
+            )?;
+            if readable_fds.contains(signal_fd_raw) {
+                while let Ok(Some(signal)) = signal_fd.read_signal() {
+                    if signal.ssi_signo == Signal::SIGUSR1 as u32 {
+                        if let Err(error) =
+                            handle_events(&mut handler, &mut dispatcher, &mut config, vec![Event::PrintMatchCounts])
+                        {
+                            eprintln!("Error on printing match counts: {error}")
+                        }
+                    } else {
+                        shutdown(&mut handler, &mut dispatcher, &mut config, &mut input_devices);
+                        return Ok(());
+                    }
+                }
+            }
             if readable_fds.contains(timer_fd) {
                 if let Err(error) =
                     handle_events(&mut handler, &mut dispatcher, &mut config, vec![Event::OverrideTimeout])
                 {
-                    println!("Error on remap timeout: {error}")
+                    eprintln!("Error on remap timeout: {error}")
+                }
+            }
+            if readable_fds.contains(predicate_fd) {
+                let events = dispatcher
+                    .take_predicate_results()
+                    .into_iter()
+                    .map(|(predicate, success)| Event::PredicateResult(predicate, success))
+                    .collect();
+                if let Err(error) = handle_events(&mut handler, &mut dispatcher, &mut config, events) {
+                    eprintln!("Error on if: {error}")
+                }
+            }
+            if readable_fds.contains(title_poller_fd) && dispatcher.take_title_poll() {
+                if let Err(error) = handle_events(&mut handler, &mut dispatcher, &mut config, vec![Event::TitlePoll]) {
+                    eprintln!("Error on await_title_change: {error}")
                 }
             }
 
-            for input_device in input_devices.values_mut() {
+            let mut removed_devices = vec![];
+            for (path, input_device) in input_devices.iter_mut() {
                 if !readable_fds.contains(input_device.as_raw_fd()) {
                     continue;
                 }
 
                 if !handle_input_events(input_device, &mut handler, &mut dispatcher, &mut config)? {
-                    println!("Found a removed device. Reselecting devices.");
-                    break 'event_loop ReloadEvent::ReloadDevices;
+                    removed_devices.push(path.clone());
+                }
+            }
+            // Keep serving the other devices. A device plugged in again is added by --watch=device.
+            for path in removed_devices {
+                if let Some(input_device) = input_devices.remove(&path) {
+                    eprintln!("Removed device {} ({})", path.display(), input_device.to_info().name);
+                }
+                if input_devices.is_empty() && !watch_devices {
+                    eprintln!(
+                        "warning: No device is left. Use --watch=device to add devices when they are plugged in."
+                    );
+                }
+            }
+
+            for (led, on) in dispatcher.take_led_requests() {
+                for input_device in input_devices.values_mut() {
+                    input_device.set_led(led, on);
+                }
+            }
+
+            if let Some(grab) = dispatcher.take_grab_request() {
+                devices_grabbed = grab;
+                for input_device in input_devices.values_mut() {
+                    if grab {
+                        input_device.grab();
+                    } else {
+                        input_device.ungrab();
+                    }
+                }
+            }
+
+            if readable_fds.contains(device_rescanner_fd) {
+                let requests = device_rescanner.take_requests();
+                if !requests.is_empty() {
+                    // Dropping the requests without a reply tells them the rescan failed
+                    match rescan_input_devices(
+                        &mut input_devices,
+                        &device_filter,
+                        &ignore_filter,
+                        mouse,
+                        devices_grabbed,
+                        &config,
+                    ) {
+                        Ok((changes, removed_devices)) => {
+                            for device in &changes.removed {
+                                eprintln!("Removed device {}", device);
+                            }
+                            for mut input_device in removed_devices {
+                                release_held_keys(&mut input_device, &mut handler, &mut dispatcher, &mut config)?;
+                            }
+                            for request in requests {
+                                let _ = request.send(changes.clone());
+                            }
+                        }
+                        Err(error) => eprintln!("Error on rescanning devices: {error}"),
+                    }
                 }
             }
 
             if let Some(inotify) = device_watcher {
                 if let Ok(events) = inotify.read_events() {
-                    handle_device_changes(events, &mut input_devices, &device_filter, &ignore_filter, mouse)?;
+                    handle_device_changes(
+                        events,
+                        &mut input_devices,
+                        &device_filter,
+                        &ignore_filter,
+                        mouse,
+                        devices_grabbed,
+                        &config,
+                    )?;
                 }
             }
             if let Some(inotify) = config_watcher {
@@ -165,36 +400,38 @@ fn main() -> anyhow::Result<()> {
                         &device_filter,
                         &ignore_filter,
                         mouse,
-                        &config_paths,
+                        devices_grabbed,
+                        &config.files,
                     )? {
                         break 'event_loop ReloadEvent::ReloadConfig;
                     }
                 }
             }
         } {
-            ReloadEvent::ReloadDevices => {
-                for input_device in input_devices.values_mut() {
-                    input_device.ungrab();
-                }
-                input_devices = match get_input_devices(&device_filter, &ignore_filter, mouse, watch_devices) {
-                    Ok(input_devices) => input_devices,
-                    Err(e) => bail!("Failed to prepare input devices: {}", e),
-                };
-            }
             ReloadEvent::ReloadConfig => {
-                match (
-                    config.modify_time,
-                    config_paths
-                        .iter()
-                        .map(|p| p.metadata().ok()?.modified().ok())
-                        .flatten()
-                        .max(),
-                ) {
+                match (config.modify_time, config::modify_time(&config.files)) {
                     (Some(last_mtime), Some(current_mtim)) if last_mtime == current_mtim => continue,
                     _ => {
-                        if let Ok(c) = load_configs(&config_paths) {
-                            println!("Reloading Config");
-                            config = c;
+                        // Keep the current config if the new one is broken
+                        match load_configs(&config_paths) {
+                            Ok(c) => {
+                                eprintln!("Reloading Config");
+                                if let Err(error) =
+                                    handle_events(&mut handler, &mut dispatcher, &mut config, vec![Event::ConfigReload])
+                                {
+                                    eprintln!("Error on releasing held keys: {error}")
+                                }
+                                config = c;
+                                if let Some(inotify) = &config_watcher {
+                                    if let Err(error) = watch_config_files(inotify, &config.files) {
+                                        eprintln!("Error on watching included config files: {error}")
+                                    }
+                                }
+                                dispatcher.set_syn_report(config.syn_report);
+                                dispatcher.set_run_max_concurrency(config.run_max_concurrency);
+                                dispatcher.set_max_output_rate(config.max_output_rate);
+                            }
+                            Err(e) => eprintln!("Failed to reload config: {}", e),
                         }
                     }
                 }
@@ -203,13 +440,35 @@ fn main() -> anyhow::Result<()> {
     }
 }
 
+// Release the keys xremap has pressed and the input devices so that no key is left stuck.
+// The output devices are destroyed when the dispatcher is dropped.
+fn shutdown(
+    handler: &mut EventHandler,
+    dispatcher: &mut ActionDispatcher,
+    config: &mut Config,
+    input_devices: &mut HashMap<PathBuf, InputDevice>,
+) {
+    if let Err(error) = handle_events(handler, dispatcher, config, vec![Event::Shutdown]) {
+        eprintln!("Error on releasing keys: {error}")
+    }
+    // Keys emitted as is, e.g. a modifier held while xremap is stopped, are only known by the dispatcher
+    if let Err(error) = dispatcher.release_pressed_keys().and_then(|_| dispatcher.flush()) {
+        eprintln!("Error on releasing keys: {error}")
+    }
+    for input_device in input_devices.values_mut() {
+        input_device.ungrab();
+    }
+}
+
 fn select_readable<'a>(
     devices: impl Iterator<Item = &'a InputDevice>,
     watchers: &[&Inotify],
-    timer_fd: RawFd,
+    timer_fds: &[RawFd],
 ) -> anyhow::Result<FdSet> {
     let mut read_fds = FdSet::new();
-    read_fds.insert(timer_fd);
+    for timer_fd in timer_fds {
+        read_fds.insert(*timer_fd);
+    }
     for device in devices {
         read_fds.insert(device.as_raw_fd());
     }
@@ -227,18 +486,55 @@ fn handle_input_events(
     dispatcher: &mut ActionDispatcher,
     config: &mut Config,
 ) -> anyhow::Result<bool> {
-    let mut device_exists = true;
-    let events = match input_device.fetch_events().map_err(|e| (e.raw_os_error(), e)) {
-        Err((Some(ENODEV), _)) => {
-            device_exists = false;
-            Ok(Vec::new())
+    let events: io::Result<Vec<InputEvent>> = input_device.fetch_events().map(|events| events.collect());
+    let events = match events.map_err(|e| (e.raw_os_error(), e)) {
+        Ok(events) => events,
+        Err((Some(EAGAIN | EINTR), _)) => return Ok(true),
+        Err((errno, error)) => {
+            if errno != Some(ENODEV) {
+                eprintln!("Error fetching input events from {}: {}", input_device.to_info().path.display(), error);
+            }
+            release_held_keys(input_device, handler, dispatcher, config)?;
+            return Ok(false);
         }
-        Err((_, error)) => Err(error).context("Error fetching input events"),
-        Ok(events) => Ok(events.collect()),
-    }?;
+    };
+    handle_device_events(input_device, handler, dispatcher, config, events)?;
+    Ok(true)
+}
+
+// Release the keys held on a device that's going away so that they don't get stuck
+fn release_held_keys(
+    input_device: &mut InputDevice,
+    handler: &mut EventHandler,
+    dispatcher: &mut ActionDispatcher,
+    config: &mut Config,
+) -> anyhow::Result<()> {
+    let releases = input_device
+        .held_keys()
+        .into_iter()
+        .map(|key| InputEvent::new(EventType::KEY, key.code(), 0))
+        .collect();
+    handle_device_events(input_device, handler, dispatcher, config, releases)
+}
+
+fn handle_device_events(
+    input_device: &mut InputDevice,
+    handler: &mut EventHandler,
+    dispatcher: &mut ActionDispatcher,
+    config: &mut Config,
+    events: Vec<InputEvent>,
+) -> anyhow::Result<()> {
+    if config.is_passthrough_device(&input_device.to_info()) {
+        // Skip EventHandler, but still send them through the output device for ordering
+        for event in events.into_iter().filter(|event| event.event_type() != EventType::LED) {
+            dispatcher.on_action(Action::InputEvent(event))?;
+        }
+        dispatcher.flush()?;
+        return Ok(());
+    }
+    let events: Vec<_> = input_device.initial_led_events().into_iter().chain(events).collect();
     let input_events = events.iter().map(|e| Event::new(input_device.to_info(), *e)).collect();
-    handle_events(handler, dispatcher, config, input_events)?;
-    Ok(device_exists)
+    handle_events(handler, dispatcher, config, input_events)
 }
 
 // Handle an Event with EventHandler, and dispatch Actions with ActionDispatcher
@@ -254,6 +550,7 @@ fn handle_events(
     for action in actions {
         dispatcher.on_action(action)?;
     }
+    dispatcher.flush()?;
     Ok(())
 }
 
@@ -263,13 +560,18 @@ fn handle_device_changes(
     device_filter: &[String],
     ignore_filter: &[String],
     mouse: bool,
+    grab: bool,
+    config: &Config,
 ) -> anyhow::Result<()> {
     input_devices.extend(events.into_iter().filter_map(|event| {
         event.name.and_then(|name| {
             let path = PathBuf::from("/dev/input/").join(name);
             let mut device = InputDevice::try_from(path).ok()?;
-            if device.is_input_device(device_filter, ignore_filter, mouse) && device.grab() {
-                device.print();
+            if device.is_input_device(device_filter, ignore_filter, mouse)
+                && !device.is_ignored(config)
+                && (!grab || device.grab())
+            {
+                device.print_selected(config);
                 Some(device.into())
             } else {
                 None
@@ -285,6 +587,7 @@ fn handle_config_changes(
     device_filter: &[String],
     ignore_filter: &[String],
     mouse: bool,
+    grab: bool,
     config_paths: &Vec<PathBuf>,
 ) -> anyhow::Result<bool> {
     for event in &events {
@@ -307,7 +610,7 @@ fn handle_config_changes(
         event.name.and_then(|name| {
             let path = PathBuf::from("/dev/input/").join(name);
             let mut device = InputDevice::try_from(path).ok()?;
-            if device.is_input_device(device_filter, ignore_filter, mouse) && device.grab() {
+            if device.is_input_device(device_filter, ignore_filter, mouse) && (!grab || device.grab()) {
                 device.print();
                 Some(device.into())
             } else {
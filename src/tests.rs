@@ -1,20 +1,25 @@
-use evdev::Key;
+use evdev::{Key, LedType};
 use indoc::indoc;
 use nix::sys::timerfd::{ClockId, TimerFd, TimerFlags};
+use std::cell::{Cell, RefCell};
+use std::error::Error;
 use std::path::Path;
-use std::time::Duration;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use crate::client::{Client, WMClient};
-use crate::device::InputDeviceInfo;
+use crate::event::InputDeviceInfo;
 use crate::{
     action::Action,
     config::{keymap::build_keymap_table, Config},
-    event::{Event, KeyEvent, KeyValue, RelativeEvent},
+    event::{Event, KeyEvent, KeyValue, LedEvent, RelativeEvent},
     event_handler::EventHandler,
+    timer::Timer,
 };
 
 struct StaticClient {
     current_application: Option<String>,
+    current_window: Option<String>,
 }
 
 impl Client for StaticClient {
@@ -22,7 +27,7 @@ impl Client for StaticClient {
         true
     }
     fn current_window(&mut self) -> Option<String> {
-        None
+        self.current_window.clone()
     }
 
     fn current_application(&mut self) -> Option<String> {
@@ -30,6 +35,73 @@ impl Client for StaticClient {
     }
 }
 
+// Caches the application like clients updated asynchronously
+struct CachingClient {
+    updated_at: Rc<Cell<Option<Instant>>>,
+    refreshes: Rc<Cell<u32>>,
+}
+
+impl Client for CachingClient {
+    fn supported(&mut self) -> bool {
+        true
+    }
+    fn current_window(&mut self) -> Option<String> {
+        None
+    }
+
+    fn current_application(&mut self) -> Option<String> {
+        Some("Firefox".into())
+    }
+
+    fn updated_at(&mut self) -> Option<Instant> {
+        self.updated_at.get()
+    }
+
+    fn refresh(&mut self) {
+        self.refreshes.set(self.refreshes.get() + 1);
+    }
+}
+
+// Tells the PID of the active window
+struct ProcessClient {
+    pid: u32,
+}
+
+impl Client for ProcessClient {
+    fn supported(&mut self) -> bool {
+        true
+    }
+    fn current_window(&mut self) -> Option<String> {
+        None
+    }
+
+    fn current_application(&mut self) -> Option<String> {
+        None
+    }
+
+    fn current_window_pid(&mut self) -> Option<u32> {
+        Some(self.pid)
+    }
+}
+
+// The title of the active window is changed by the test
+struct TitleClient {
+    title: Rc<RefCell<Option<String>>>,
+}
+
+impl Client for TitleClient {
+    fn supported(&mut self) -> bool {
+        true
+    }
+    fn current_window(&mut self) -> Option<String> {
+        self.title.borrow().clone()
+    }
+
+    fn current_application(&mut self) -> Option<String> {
+        None
+    }
+}
+
 fn get_input_device_info<'a>() -> InputDeviceInfo<'a> {
     InputDeviceInfo {
         name: "Some Device",
@@ -95,6 +167,74 @@ const _REL_RESERVED: u16 = 10;
 const _REL_WHEEL_HI_RES: u16 = 11;
 const _REL_HWHEEL_HI_RES: u16 = 12;
 
+#[test]
+fn test_modmap_many_to_one() {
+    assert_actions(
+        indoc! {"
+        modmap:
+          - remap:
+              {a, b}: x
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_modmap_one_to_many() {
+    assert_actions(
+        indoc! {"
+        modmap:
+          - remap:
+              a: [x, y]
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_Y, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_Y, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_any_modifiers() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - exact_match: true
+            remap:
+              '*-f': down
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            // The held modifier is kept pressed
+            Action::KeyEvent(KeyEvent::new(Key::KEY_DOWN, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_DOWN, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+        ],
+    )
+}
+
 #[test]
 fn test_relative_events() {
     assert_actions(
@@ -114,15 +254,43 @@ fn test_relative_events() {
     )
 }
 
+#[test]
+fn test_relative_events_one_direction() {
+    let config = indoc! {"
+        modmap:
+          - remap:
+              XUPSCROLL: b
+    "};
+    assert_actions(
+        config,
+        vec![Event::RelativeEvent(
+            get_input_device_info(),
+            RelativeEvent::new_with(_REL_WHEEL, _POSITIVE),
+        )],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+        ],
+    );
+    // The other direction keeps scrolling
+    assert_actions(
+        config,
+        vec![Event::RelativeEvent(
+            get_input_device_info(),
+            RelativeEvent::new_with(_REL_WHEEL, _NEGATIVE),
+        )],
+        vec![Action::RelativeEvent(RelativeEvent::new_with(_REL_WHEEL, _NEGATIVE))],
+    );
+}
+
 #[test]
 fn verify_disguised_relative_events() {
-    use crate::event_handler::DISGUISED_EVENT_OFFSETTER;
+    use crate::event_handler::{DISGUISED_EVENT_OFFSETTER, KEY_SELF};
     // Verifies that the event offsetter used to "disguise" relative events into key event
     // is a bigger number than the biggest one a scancode had at the time of writing this (26 december 2022)
     assert!(0x2e7 < DISGUISED_EVENT_OFFSETTER);
-    // and that it's not big enough that one of the "disguised" events's scancode would overflow.
-    // (the largest of those events is equal to DISGUISED_EVENT_OFFSETTER + 26)
-    assert!(DISGUISED_EVENT_OFFSETTER <= u16::MAX - 26)
+    // The reserved range ends at the top of u16
+    assert_eq!(KEY_SELF.code(), u16::MAX);
 }
 
 #[test]
@@ -131,7 +299,9 @@ fn test_mouse_movement_event_accumulation() {
     // which is necessary to avoid separating mouse movement events with synchronization events,
     // because such a separation would cause a bug with cursor movement.
 
-    // Please refer to test_cursor_behavior_1 and test_cursor_behavior_2 for more information on said bug.
+    // The bug: REL_X, SYN_REPORT, REL_Y, SYN_REPORT moves the cursor by a different amount than
+    // REL_X, REL_Y, SYN_REPORT, so a diagonal movement wouldn't end up where the input device intended.
+    // ActionDispatcher keeps a MouseMovementEventCollection in a single report unless `syn_report: event`.
     assert_actions(
         indoc! {""},
         vec![
@@ -146,487 +316,2947 @@ fn test_mouse_movement_event_accumulation() {
 }
 
 #[test]
-fn test_interleave_modifiers() {
+fn test_rel_scale() {
     assert_actions(
         indoc! {"
-        keymap:
-          - remap:
-              M-f: C-right
+        rel_scale:
+          - device:
+              only: Some Device
+            scale: 0.5
         "},
         vec![
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
-        ],
-        vec![
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
-            Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
-            Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_X, 3)),
+            Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_Y, -4)),
+            Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_X, 1)),
+            Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_X, 1)),
         ],
+        vec![Action::MouseMovementEventCollection(vec![
+            RelativeEvent::new_with(_REL_X, 1),
+            RelativeEvent::new_with(_REL_Y, -2),
+            RelativeEvent::new_with(_REL_X, 1),
+        ])],
     )
 }
 
 #[test]
-fn test_exact_match_true() {
+fn test_rel_invert() {
     assert_actions(
         indoc! {"
-        keymap:
-          - exact_match: true
-            remap:
-              M-f: C-right
+        rel_invert:
+          - device:
+              only: Some Device
+            invert: [wheel, REL_Y]
+        rel_scale:
+          - device:
+              only: Some Device
+            scale: 0.5
         "},
         vec![
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+            Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_WHEEL, 1)),
+            Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_WHEEL_HI_RES, 120)),
+            Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_HWHEEL, 1)),
+            Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_Y, 4)),
         ],
         vec![
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+            Action::RelativeEvent(RelativeEvent::new_with(_REL_WHEEL, -1)),
+            Action::RelativeEvent(RelativeEvent::new_with(_REL_WHEEL_HI_RES, -120)),
+            Action::RelativeEvent(RelativeEvent::new_with(_REL_HWHEEL, 1)),
+            Action::MouseMovementEventCollection(vec![RelativeEvent::new_with(_REL_Y, -2)]),
         ],
     )
 }
 
 #[test]
-fn test_exact_match_false() {
-    assert_actions(
-        indoc! {"
+fn test_keymap_on_release() {
+    let config = indoc! {"
         keymap:
-          - exact_match: false
+          - on: release
             remap:
-              M-f: C-right
-        "},
-        vec![
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
-        ],
+              F13: { launch: [\"push-to-talk\", \"off\"] }
+              C-F14: a
+    "};
+    assert_actions(
+        config,
         vec![
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
-            Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
-            Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F13, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F13, KeyValue::Repeat)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F13, KeyValue::Release)),
         ],
-    )
-}
+        vec![Action::Command(vec!["push-to-talk".into(), "off".into()])],
+    );
 
-#[test]
-fn test_exact_match_default() {
+    // Modifiers are matched on press, so releasing Control first still triggers it
     assert_actions(
-        indoc! {"
-        keymap:
-          - remap:
-              M-f: C-right
-        "},
+        config,
         vec![
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F14, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F14, KeyValue::Release)),
         ],
         vec![
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
             Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
             Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
         ],
-    )
+    );
 }
 
 #[test]
-fn test_exact_match_true_nested() {
+fn test_run_on_release() {
     assert_actions(
         indoc! {"
         keymap:
-          - exact_match: true
+          - on: release
             remap:
-              C-x:
-                remap:
-                  h: C-a
+              F13: { run: ptt-off }
         "},
         vec![
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_H, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F13, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F13, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F13, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F13, KeyValue::Release)),
         ],
+        vec![Action::Run("ptt-off".into()), Action::Run("ptt-off".into())],
+    );
+}
+
+#[test]
+fn test_mouse_movement_coalescing() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        mouse_movement_coalescing:
+          enable: true
+          window_millis: 4
+        "},
+        Duration::ZERO,
+    );
+    let movement = |code, value| Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(code, value));
+
+    // Movements within the window are summed into a single collection
+    assert_timed_actions(handler.on_events(vec![movement(_REL_X, 1), movement(_REL_Y, 2)]), vec![]);
+    assert_timed_actions(handler.on_events(vec![movement(_REL_X, 3)]), vec![]);
+    assert_timed_actions(
+        handler.advance(Duration::from_millis(4)),
+        vec![Action::MouseMovementEventCollection(vec![
+            RelativeEvent::new_with(_REL_X, 4),
+            RelativeEvent::new_with(_REL_Y, 2),
+        ])],
+    );
+
+    // Other events flush them first
+    assert_timed_actions(handler.on_events(vec![movement(_REL_Y, -5)]), vec![]);
+    assert_timed_actions(
+        handler.on_events(vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )]),
         vec![
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_H, KeyValue::Press)),
+            Action::MouseMovementEventCollection(vec![RelativeEvent::new_with(_REL_Y, -5)]),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
         ],
-    )
+    );
+    assert_timed_actions(handler.advance(Duration::from_millis(4)), vec![]);
 }
 
 #[test]
-fn test_exact_match_false_nested() {
+fn test_mouse_movement_coalescing_disabled_meanwhile() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        mouse_movement_coalescing:
+          enable: true
+          window_millis: 4
+        "},
+        Duration::ZERO,
+    );
+    assert_timed_actions(
+        handler.on_events(vec![Event::RelativeEvent(
+            get_input_device_info(),
+            RelativeEvent::new_with(_REL_X, 1),
+        )]),
+        vec![],
+    );
+
+    // The pending movement is still flushed after a reload disables it
+    handler.config.mouse_movement_coalescing.enable = false;
+    handler.timer.advance(Duration::from_millis(4));
+    assert_timed_actions(
+        handler.on_events(vec![Event::OverrideTimeout]),
+        vec![Action::MouseMovementEventCollection(vec![RelativeEvent::new_with(
+            _REL_X, 1,
+        )])],
+    );
+    assert!(handler.timer.expires_at.get().is_none());
+}
+
+#[test]
+fn test_run_autorepeat() {
+    let events = || {
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Repeat)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+        ]
+    };
     assert_actions(
         indoc! {"
         keymap:
-          - exact_match: false
-            remap:
-              C-x:
-                remap:
-                  h: C-a
+          - remap:
+              F1: { run: notify-send hi }
+        "},
+        events(),
+        vec![
+            Action::Run("notify-send hi".into()),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+            Action::Run("notify-send hi".into()),
+        ],
+    );
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              F1: { run: { command: notify-send hi, repeat: true } }
+        "},
+        events(),
+        vec![
+            Action::Run("notify-send hi".into()),
+            Action::Run("notify-send hi".into()),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+            Action::Run("notify-send hi".into()),
+        ],
+    );
+}
+
+#[test]
+fn test_move_relative() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              F2: { move_relative: { x: -10000, y: 300 } }
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F2, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F2, KeyValue::Release)),
+        ],
+        vec![
+            Action::MouseMovementEventCollection(vec![
+                RelativeEvent::new_with(_REL_X, -10000),
+                RelativeEvent::new_with(_REL_Y, 300),
+            ]),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F2, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_scroll() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              F2: { scroll: { y: -0.25 } }
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F2, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F2, KeyValue::Release)),
+        ],
+        vec![
+            Action::Scroll { x: 0.0, y: -0.25 },
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F2, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_chord() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              C-space: { chord: [alt, shift] }
         "},
         vec![
             Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_SPACE, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_SPACE, KeyValue::Release)),
             Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_H, KeyValue::Press)),
         ],
         vec![
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
-            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
             Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_SPACE, KeyValue::Release)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
         ],
-    )
+    );
 }
 
 #[test]
-fn test_application_override() {
-    let config = indoc! {"
-        keymap:
-
-          - name: firefox
-            application:
-              only: [firefox]
-            remap:
-              a: C-c
-
-          - name: generic
-            remap:
-              a: C-b
-    "};
-
+fn test_cycle() {
     assert_actions(
-        config,
-        vec![Event::KeyEvent(
-            get_input_device_info(),
-            KeyEvent::new(Key::KEY_A, KeyValue::Press),
-        )],
+        indoc! {"
+        keymap:
+          - remap:
+              F1: { cycle: [a, b, c] }
+        "},
         vec![
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
             Action::Delay(Duration::from_nanos(0)),
             Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
-        ],
-    );
-
-    assert_actions_with_current_application(
-        config,
-        Some(String::from("firefox")),
-        vec![Event::KeyEvent(
-            get_input_device_info(),
-            KeyEvent::new(Key::KEY_A, KeyValue::Press),
-        )],
-        vec![
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
             Action::Delay(Duration::from_nanos(0)),
             Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
         ],
     );
 }
 
 #[test]
-fn test_device_override() {
-    let config = indoc! {"
+fn test_conditional() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
         keymap:
+          - remap:
+              F1: { if: pgrep foo, then: a, else: b }
+        "},
+        Duration::ZERO,
+    );
+    let key = |value| {
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_F1, value),
+        )]
+    };
+    let result = |success| vec![Event::PredicateResult("pgrep foo".to_string(), success)];
+    let tap = |key| {
+        vec![
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ]
+    };
 
-          - name: event1
-            device:
-              only: [event1]
-            remap:
-              a: C-c
+    assert_timed_actions(handler.on_events(key(KeyValue::Press)), vec![Action::Predicate("pgrep foo".to_string())]);
+    // Autorepeat waits for the predicate that is running
+    assert_timed_actions(handler.on_events(key(KeyValue::Repeat)), vec![]);
+    assert_timed_actions(handler.on_events(result(true)), tap(Key::KEY_A).into_iter().chain(tap(Key::KEY_A)).collect());
+    // The result is reused for a while
+    handler.on_events(key(KeyValue::Release));
+    handler.advance(Duration::from_millis(499));
+    assert_timed_actions(handler.on_events(key(KeyValue::Press)), tap(Key::KEY_A));
+    handler.on_events(key(KeyValue::Release));
+    handler.advance(Duration::from_millis(1));
+    assert_timed_actions(handler.on_events(key(KeyValue::Press)), vec![Action::Predicate("pgrep foo".to_string())]);
+    assert_timed_actions(handler.on_events(result(false)), tap(Key::KEY_B));
+}
 
-          - name: event0
-            remap:
-              a: C-b
-    "};
+#[test]
+fn test_await_title_change() {
+    let title = Rc::new(RefCell::new(Some("Editor".to_string())));
+    let mut handler = TimedHandler::with_client(
+        indoc! {"
+        keymap:
+          - remap:
+              F1: [{ await_title_change: { key: C-o, timeout_ms: 120ms, interval_ms: 50ms } }, Enter]
+        "},
+        Duration::ZERO,
+        Box::new(TitleClient { title: title.clone() }),
+    );
+    let press = || {
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_F1, KeyValue::Press),
+        )]
+    };
+    let release = || {
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_F1, KeyValue::Release),
+        )]
+    };
+    let ctrl_o = || {
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_O, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_O, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ]
+    };
+    let enter = || {
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_ENTER, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_ENTER, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ]
+    };
+    let poll = |millis| vec![Action::PollTitle(Duration::from_millis(millis))];
+
+    // Enter waits for the title to change
+    assert_timed_actions(handler.on_events(press()), ctrl_o().into_iter().chain(poll(50)).collect());
+    handler.on_events(release());
+    handler.advance(Duration::from_millis(50));
+    assert_timed_actions(handler.on_events(vec![Event::TitlePoll]), poll(50));
+    *title.borrow_mut() = Some("Open File".to_string());
+    handler.advance(Duration::from_millis(50));
+    assert_timed_actions(handler.on_events(vec![Event::TitlePoll]), enter());
 
+    // Enter is pressed anyway on the timeout
+    assert_timed_actions(handler.on_events(press()), ctrl_o().into_iter().chain(poll(50)).collect());
+    handler.on_events(release());
+    handler.advance(Duration::from_millis(50));
+    assert_timed_actions(handler.on_events(vec![Event::TitlePoll]), poll(50));
+    handler.advance(Duration::from_millis(50));
+    assert_timed_actions(handler.on_events(vec![Event::TitlePoll]), poll(20));
+    handler.advance(Duration::from_millis(20));
+    assert_timed_actions(handler.on_events(vec![Event::TitlePoll]), enter());
+}
+
+#[test]
+fn test_on_device() {
     assert_actions(
-        config,
+        indoc! {"
+        virtual_devices:
+          - name: gaming-kbd
+            capabilities: [keyboard]
+        keymap:
+          - remap:
+              F1: [{ device: gaming-kbd, action: [a, { device: other, action: b }] }, c]
+        "},
         vec![Event::KeyEvent(
-            InputDeviceInfo {
-                name: "Some Device",
-                path: &Path::new("/dev/input/event0"),
-            },
-            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_F1, KeyValue::Press),
         )],
         vec![
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::SelectDevice(Some("gaming-kbd".to_string())),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+            Action::SelectDevice(Some("other".to_string())),
             Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
-            Action::Delay(Duration::from_nanos(0)),
-            Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+            Action::SelectDevice(Some("gaming-kbd".to_string())),
+            Action::SelectDevice(None),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
         ],
     );
+}
 
+#[test]
+fn test_description() {
     assert_actions(
-        config,
-        vec![Event::KeyEvent(
-            InputDeviceInfo {
-                name: "Other Device",
-                path: &Path::new("/dev/input/event1"),
-            },
-            KeyEvent::new(Key::KEY_A, KeyValue::Press),
-        )],
+        indoc! {"
+        modmap:
+          - description: Caps Lock as Control
+            remap:
+              CapsLock: Control_L
+        keymap:
+          - description: Copy
+            remap:
+              C-i: C-c
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_CAPSLOCK, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_I, KeyValue::Press)),
+        ],
         vec![
+            Action::Matched("Caps Lock as Control".to_string()),
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::Matched("Copy".to_string()),
             Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
-            Action::Delay(Duration::from_nanos(0)),
-            Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
         ],
     );
 }
 
 #[test]
-fn test_merge_remaps() {
-    let config = indoc! {"
-        keymap:
-          - remap:
-              C-x:
-                remap:
-                  h: C-a
-          - remap:
-              C-x:
-                remap:
-                  k: C-w
-    "};
-
-    assert_actions(
-        config,
+fn test_modifier_order() {
+    let events = || {
         vec![
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_H, KeyValue::Press)),
-        ],
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+        ]
+    };
+    let actions = |first, second| {
         vec![
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(first, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(second, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_DELETE, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_DELETE, KeyValue::Release)),
             Action::Delay(Duration::from_nanos(0)),
             Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
-        ],
-    );
+            Action::KeyEvent(KeyEvent::new(first, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(second, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+        ]
+    };
+
+    let config = |modifier_order| {
+        format!(
+            indoc! {"
+            modifier_order: {}
+            keymap:
+              - remap:
+                  F1: C-M-delete
+            "},
+            modifier_order
+        )
+    };
+
+    // In the order they're written by default
+    assert_actions(&config("[]"), events(), actions(Key::KEY_LEFTCTRL, Key::KEY_LEFTALT));
+    assert_actions(&config("[alt, ctrl]"), events(), actions(Key::KEY_LEFTALT, Key::KEY_LEFTCTRL));
+    // Unlisted modifiers follow listed ones
+    assert_actions(&config("[alt]"), events(), actions(Key::KEY_LEFTALT, Key::KEY_LEFTCTRL));
+}
 
+#[test]
+fn test_hold_while_pressed() {
     assert_actions(
-        config,
+        indoc! {"
+        keymap:
+          - remap:
+              F13: { hold_while_pressed: ctrl }
+        "},
         vec![
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_K, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F13, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F13, KeyValue::Repeat)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F13, KeyValue::Release)),
         ],
         vec![
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_W, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_W, KeyValue::Release)),
-            Action::Delay(Duration::from_nanos(0)),
-            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
         ],
-    )
+    );
 }
 
 #[test]
-fn test_merge_remaps_with_override() {
-    let config = indoc! {"
+fn test_keymap_repeat_ignore() {
+    // Repeats fall through to the next keymap
+    assert_actions(
+        indoc! {"
         keymap:
+          - repeat: ignore
+            remap:
+              a: b
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Repeat)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Repeat)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Repeat)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Repeat)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_keymap_repeat_only() {
+    // Accelerate only while the key is held, on top of a keymap for the press
+    assert_actions(
+        indoc! {"
+        keymap:
+          - repeat: only
+            remap:
+              a: { repeat: { key: b, count: 2 } }
           - remap:
-              C-x:
-                remap:
-                  h: C-a
-          - remap:
-              C-x:
-                remap:
-                  h: C-b
-                  c: C-q
-    "};
+              a: b
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Repeat)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Repeat)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_client_fresh_as_of() {
+    let updated_at = Rc::new(Cell::new(Some(Instant::now())));
+    let mut client = WMClient::new(
+        "caching",
+        Box::new(CachingClient {
+            updated_at: updated_at.clone(),
+            refreshes: Rc::new(Cell::new(0)),
+        }),
+    );
+    assert_eq!(client.begin_event(None), 1);
+    client.current_application();
+    assert_eq!(client.fresh_as_of(), 1);
+
+    // Not reported again
+    client.begin_event(None);
+    client.current_application();
+    assert_eq!(client.fresh_as_of(), 1);
+
+    updated_at.set(Some(Instant::now() + Duration::from_millis(1)));
+    client.begin_event(None);
+    client.current_application();
+    assert_eq!(client.fresh_as_of(), 3);
+
+    // Clients querying on every call are always fresh
+    let mut client = WMClient::new(
+        "static",
+        Box::new(StaticClient {
+            current_application: None,
+            current_window: None,
+        }),
+    );
+    client.begin_event(None);
+    client.begin_event(None);
+    client.current_application();
+    assert_eq!(client.fresh_as_of(), 2);
+}
+
+#[test]
+fn test_client_cache_ttl_refresh() {
+    let ttl = Duration::from_millis(10);
+    let updated_at = Rc::new(Cell::new(Instant::now().checked_sub(ttl * 2)));
+    let refreshes = Rc::new(Cell::new(0));
+    let mut client = WMClient::new(
+        "caching",
+        Box::new(CachingClient {
+            updated_at: updated_at.clone(),
+            refreshes: refreshes.clone(),
+        }),
+    );
+
+    // Values older than the TTL are queried again
+    client.begin_event(Some(ttl));
+    client.current_application();
+    assert_eq!(refreshes.get(), 1);
+
+    // But not again until it's answered
+    client.begin_event(Some(ttl));
+    client.current_application();
+    assert_eq!(refreshes.get(), 1);
+
+    updated_at.set(Some(Instant::now()));
+    client.begin_event(Some(ttl));
+    client.current_application();
+    assert_eq!(client.fresh_as_of(), 3);
+    assert_eq!(refreshes.get(), 1);
+
+    // Nor after the TTL again, until the focus changes
+    std::thread::sleep(ttl * 2);
+    client.begin_event(Some(ttl));
+    client.current_application();
+    assert_eq!(refreshes.get(), 1);
+    updated_at.set(Some(Instant::now()));
+    client.begin_event(Some(ttl));
+    client.current_application();
+    std::thread::sleep(ttl * 2);
+    client.begin_event(Some(ttl));
+    client.current_application();
+    assert_eq!(refreshes.get(), 2);
+}
+
+#[test]
+fn test_client_current_process() {
+    let mut client = WMClient::new(
+        "process",
+        Box::new(ProcessClient {
+            pid: std::process::id(),
+        }),
+    );
+    let exe = std::env::current_exe().unwrap();
+    assert_eq!(client.current_process().as_deref(), exe.file_name().unwrap().to_str());
+
+    // Clients that don't know the PID
+    let mut client = WMClient::new(
+        "static",
+        Box::new(StaticClient {
+            current_application: Some("firefox".into()),
+            current_window: None,
+        }),
+    );
+    assert_eq!(client.current_process(), None);
+}
+
+#[test]
+fn test_process_falls_back_to_application() {
+    assert_actions_with_current_application(
+        indoc! {"
+        keymap:
+          - process:
+              only: [firefox]
+            remap:
+              a: b
+        "},
+        Some(String::from("firefox")),
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+        ],
+    );
+}
 
+#[test]
+fn test_interleave_modifiers() {
     assert_actions(
-        config,
+        indoc! {"
+        keymap:
+          - remap:
+              M-f: C-right
+        "},
         vec![
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_H, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
         ],
         vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_interleave_modifiers_disabled() {
+    let events = || {
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+        ]
+    };
+    // Unlike test_interleave_modifiers, Alt is kept held while C-right is emitted
+    let actions = || {
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ]
+    };
+    assert_actions(
+        indoc! {"
+        interleave_modifiers: false
+        keymap:
+          - remap:
+              M-f: C-right
+        "},
+        events(),
+        actions(),
+    );
+    assert_actions(
+        indoc! {"
+        keymap:
+          - interleave_modifiers: false
+            remap:
+              M-f: C-right
+        "},
+        events(),
+        actions(),
+    );
+    // A keymap can turn it back on
+    assert_actions(
+        indoc! {"
+        interleave_modifiers: false
+        keymap:
+          - interleave_modifiers: true
+            remap:
+              M-f: C-right
+        "},
+        events(),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
             Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
             Action::Delay(Duration::from_nanos(0)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
         ],
     );
+}
 
+#[test]
+fn test_interleave_right_modifier() {
     assert_actions(
-        config,
+        indoc! {"
+        keymap:
+          - remap:
+              M-f: RC-right
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHTCTRL, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_interleave_other_side_modifier() {
+    // Left Ctrl is held, but the right one is pressed for the action and then released
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              C-f: RC-LM-right
+        "},
         vec![
             Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
         ],
         vec![
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTMETA, KeyValue::Press)),
             Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_Q, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_Q, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
             Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
             Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTMETA, KeyValue::Release)),
         ],
     )
 }
 
 #[test]
-fn test_mixing_keypress_and_remap_in_keymap_action() {
-    // KEY_D will be emitted, and the remap will be used for next key press.
+fn test_modifier_mouse_button() {
+    // Ctrl-click becomes a middle click, while a plain click stays as is
     assert_actions(
         indoc! {"
         keymap:
           - remap:
-              f12:
-                - d
-                - remap:
-                    a: b
+              C-BTN_LEFT: btn_middle
         "},
         vec![
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::BTN_LEFT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::BTN_LEFT, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::BTN_LEFT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::BTN_LEFT, KeyValue::Release)),
         ],
         vec![
-            Action::KeyEvent(KeyEvent::new(Key::KEY_D, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_D, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::BTN_LEFT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::BTN_LEFT, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::BTN_MIDDLE, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::BTN_MIDDLE, KeyValue::Release)),
             Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
             Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::BTN_LEFT, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_exact_match_true() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - exact_match: true
+            remap:
+              M-f: C-right
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+        ],
+    )
+}
+
+#[test]
+fn test_exact_match_false() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - exact_match: false
+            remap:
+              M-f: C-right
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_exact_match_default() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              M-f: C-right
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_default_exact_match() {
+    assert_actions(
+        indoc! {"
+        default_exact_match: true
+        keymap:
+          - remap:
+              M-f: C-right
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+        ],
+    )
+}
+
+#[test]
+fn test_default_exact_match_overridden() {
+    assert_actions(
+        indoc! {"
+        default_exact_match: true
+        keymap:
+          - exact_match: false
+            remap:
+              M-f: C-right
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_exact_match_true_nested() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - exact_match: true
+            remap:
+              C-x:
+                remap:
+                  h: C-a
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_H, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_H, KeyValue::Press)),
+        ],
+    )
+}
+
+#[test]
+fn test_nested_on_unmatched() {
+    let config = |on_unmatched| {
+        format!(
+            indoc! {"
+            keymap:
+              - remap:
+                  C-x:
+                    remap:
+                      h: C-a
+                    timeout_millis: 1000
+                    timeout_key: x
+                    on_unmatched: {}
+                  q: w
+            "},
+            on_unmatched
+        )
+    };
+    let events = || {
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_Q, KeyValue::Press)),
+        ]
+    };
+    let with_prefix = |actions: Vec<Action>| {
+        let mut prefix = vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ];
+        prefix.extend(actions);
+        prefix
+    };
+
+    // timeout_key is emitted, and the key is remapped by keymap
+    assert_actions(
+        &config("replay"),
+        events(),
+        with_prefix(vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_W, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_W, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+        ]),
+    );
+
+    // Only the key is remapped by keymap
+    assert_actions(
+        &config("passthrough"),
+        events(),
+        with_prefix(vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_W, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_W, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+        ]),
+    );
+
+    // Neither is emitted
+    assert_actions(&config("swallow"), events(), with_prefix(vec![]));
+}
+
+#[test]
+fn test_exact_match_false_nested() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - exact_match: false
+            remap:
+              C-x:
+                remap:
+                  h: C-a
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_H, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_application_override() {
+    let config = indoc! {"
+        keymap:
+
+          - name: firefox
+            application:
+              only: [firefox]
+            remap:
+              a: C-c
+
+          - name: generic
+            remap:
+              a: C-b
+    "};
+
+    assert_actions(
+        config,
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+
+    assert_actions_with_current_application(
+        config,
+        Some(String::from("firefox")),
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_application_unknown() {
+    // Like with --client none, `only` doesn't match an unknown application, and `not` does
+    assert_actions_with_current_application(
+        indoc! {"
+        keymap:
+          - application:
+              only: [firefox]
+            remap:
+              a: b
+          - application:
+              not: [firefox]
+            remap:
+              a: c
+        "},
+        None,
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+        ],
+    );
+}
+
+#[test]
+fn test_keymap_priority() {
+    // The firefox keymap wins even though it comes later, and keymaps of the same priority keep their order
+    let config = indoc! {"
+        keymap:
+          - name: generic
+            remap:
+              a: C-b
+              b: C-d
+
+          - name: firefox
+            priority: 10
+            application:
+              only: [firefox]
+            remap:
+              a: C-c
+
+          - name: another generic
+            remap:
+              b: C-e
+    "};
+
+    assert_actions_with_current_application(
+        config,
+        Some(String::from("firefox")),
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_D, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_D, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_keymap_conditions() {
+    // Every filter of a keymap has to match
+    let config = indoc! {"
+        virtual_modifiers:
+          - CapsLock
+        keymap:
+          - application:
+              only: firefox
+            device:
+              only: Some Device
+            held: [CapsLock]
+            remap:
+              a: b
+          - application:
+              only: firefox
+            device:
+              not: Some Device
+            remap:
+              a: c
+    "};
+    let events = |held| {
+        let mut events = vec![];
+        if held {
+            events.push(Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_CAPSLOCK, KeyValue::Press)));
+        }
+        events.push(Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)));
+        events
+    };
+
+    assert_actions_with_current_application(
+        config,
+        Some(String::from("firefox")),
+        events(true),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ],
+    );
+    assert_actions_with_current_application(
+        config,
+        Some(String::from("firefox")),
+        events(false),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press))],
+    );
+    assert_actions_with_current_application(
+        config,
+        Some(String::from("chromium")),
+        events(true),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press))],
+    );
+}
+
+#[test]
+fn test_modmap_application_override() {
+    let config = indoc! {"
+        modmap:
+          - name: terminal
+            application:
+              only: [Alacritty]
+            remap:
+              Super_L: Ctrl_L
+          - name: generic
+            remap:
+              Super_L: Alt_L
+    "};
+
+    assert_actions(
+        config,
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_LEFTMETA, KeyValue::Press),
+        )],
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press))],
+    );
+
+    assert_actions_with_current_application(
+        config,
+        Some(String::from("Alacritty")),
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_LEFTMETA, KeyValue::Press),
+        )],
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press))],
+    );
+}
+
+#[test]
+fn test_modmap_swapped_modifiers() {
+    let config = indoc! {"
+        modmap:
+          - remap:
+              Alt_L: Super_L
+              Super_L: Alt_L
+        keymap:
+          - remap:
+              M-f: C-right
+              Super-f: C-left
+    "};
+
+    // The physical Win key is Alt when keymap is matched
+    assert_actions(
+        config,
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTMETA, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTMETA, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHT, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
+        ],
+    );
+
+    // and the physical Alt key is Win
+    assert_actions(
+        config,
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTMETA, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTMETA, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFT, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTMETA, KeyValue::Press)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTMETA, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_window_and_application() {
+    let config = indoc! {"
+        keymap:
+          - application:
+              only: firefox
+            window:
+              not: /Work$/
+            remap:
+              a: C-c
+    "};
+    let ctrl_c = || {
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ]
+    };
+    let a = || {
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )]
+    };
+    let pass_through = || vec![Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press))];
+
+    // Both application and window must match
+    assert_actions_with_current_window(
+        config,
+        Some(String::from("firefox")),
+        Some(String::from("Mozilla Firefox - Personal")),
+        a(),
+        ctrl_c(),
+    );
+    assert_actions_with_current_window(
+        config,
+        Some(String::from("firefox")),
+        Some(String::from("Mozilla Firefox - Work")),
+        a(),
+        pass_through(),
+    );
+    assert_actions_with_current_window(
+        config,
+        Some(String::from("chromium")),
+        Some(String::from("Chromium - Personal")),
+        a(),
+        pass_through(),
+    );
+}
+
+#[test]
+fn test_device_override() {
+    let config = indoc! {"
+        keymap:
+
+          - name: event1
+            device:
+              only: [event1]
+            remap:
+              a: C-c
+
+          - name: event0
+            remap:
+              a: C-b
+    "};
+
+    assert_actions(
+        config,
+        vec![Event::KeyEvent(
+            InputDeviceInfo {
+                name: "Some Device",
+                path: &Path::new("/dev/input/event0"),
+            },
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+
+    assert_actions(
+        config,
+        vec![Event::KeyEvent(
+            InputDeviceInfo {
+                name: "Other Device",
+                path: &Path::new("/dev/input/event1"),
+            },
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_device_binding() {
+    let config = indoc! {"
+        keymap:
+          - remap:
+              a: { device: event1, actions: C-c }
+              b: C-b
+    "};
+    let event = |path, key| {
+        vec![Event::KeyEvent(
+            InputDeviceInfo {
+                name: "Some Device",
+                path: Path::new(path),
+            },
+            KeyEvent::new(key, KeyValue::Press),
+        )]
+    };
+    let with_ctrl = |key| {
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ]
+    };
+
+    assert_actions(config, event("/dev/input/event1", Key::KEY_A), with_ctrl(Key::KEY_C));
+    assert_actions(
+        config,
+        event("/dev/input/event0", Key::KEY_A),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press))],
+    );
+    // The other remaps of the keymap apply to any device
+    assert_actions(config, event("/dev/input/event0", Key::KEY_B), with_ctrl(Key::KEY_B));
+}
+
+#[test]
+fn test_merge_remaps() {
+    let config = indoc! {"
+        keymap:
+          - remap:
+              C-x:
+                remap:
+                  h: C-a
+          - remap:
+              C-x:
+                remap:
+                  k: C-w
+    "};
+
+    assert_actions(
+        config,
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_H, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+
+    assert_actions(
+        config,
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_K, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_W, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_W, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_merge_remaps_with_override() {
+    let config = indoc! {"
+        keymap:
+          - remap:
+              C-x:
+                remap:
+                  h: C-a
+          - remap:
+              C-x:
+                remap:
+                  h: C-b
+                  c: C-q
+    "};
+
+    assert_actions(
+        config,
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_H, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+
+    assert_actions(
+        config,
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_Q, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_Q, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_merge_remaps_with_strategy() {
+    for (merge_strategy, key) in [("first", Key::KEY_A), ("last", Key::KEY_B)] {
+        let config = format!(
+            "merge_strategy: {}\n{}",
+            merge_strategy,
+            indoc! {"
+            keymap:
+              - remap:
+                  C-x:
+                    remap:
+                      h: C-a
+              - remap:
+                  C-x:
+                    remap:
+                      h: C-b
+                      c: C-q
+            "}
+        );
+        let events = |key| {
+            vec![
+                Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+                Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+                Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+                Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+                Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, KeyValue::Press)),
+            ]
+        };
+        let actions = |key| {
+            vec![
+                Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+                Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)),
+                Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)),
+                Action::Delay(Duration::from_nanos(0)),
+                Action::Delay(Duration::from_nanos(0)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            ]
+        };
+
+        // The strategy picks the winner of h, and c is mapped either way
+        assert_actions(&config, events(Key::KEY_H), actions(key));
+        assert_actions(&config, events(Key::KEY_C), actions(Key::KEY_Q));
+    }
+}
+
+#[test]
+fn test_mixing_keypress_and_remap_in_keymap_action() {
+    // KEY_D will be emitted, and the remap will be used for next key press.
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              f12:
+                - d
+                - remap:
+                    a: b
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_D, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_D, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_mixing_no_keypress_and_remap_in_keymap_action() {
+    // The first match stops the search for matches. So the last remap isn't used.
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              f12: []
+          - remap:
+              f12:
+                - remap:
+                    a: b
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_no_keymap_action() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              f12: []
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
+        ],
+        vec![
+            //This is just release, so the key is not emitted.
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
+        ],
+    );
+
+    //Same test with the null keyword
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              f12: null
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
+        ],
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_F12, KeyValue::Release))],
+    )
+}
+
+#[test]
+fn test_keys_raw() {
+    // Events are emitted as-is, without the modifier emulation of a normal key press.
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              M-f: { keys_raw: [+leftctrl, +c, -leftctrl, -c] }
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+        ],
+    )
+}
+
+#[test]
+fn test_press_release() {
+    let config = indoc! {"
+        keymap:
+          - remap:
+              F1: { press: ctrl }
+              F2: { release: ctrl }
+        "};
+    // ctrl is held across other keys until { release: ctrl }
+    assert_actions(
+        config,
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F2, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+    // A config reload releases it
+    assert_actions(
+        config,
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Event::ConfigReload,
+            Event::ConfigReload,
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_on_startup() {
+    assert_actions(
+        indoc! {"
+        on_startup:
+          - { press: ctrl }
+          - { run: notify-send started }
+          - a
+        "},
+        vec![Event::Startup],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::Run("notify-send started".into()),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+        ],
+    );
+}
+
+#[test]
+fn test_panic_key() {
+    assert_actions(
+        indoc! {"
+        panic_key: C-KEY_ESC
+        keymap:
+          - remap:
+              f1: [{ press: shift }, { set_mode: other }]
+          - mode: other
+            remap:
+              a: b
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_ESC, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+        ],
+    );
+}
+
+#[test]
+fn test_sticky_mode() {
+    let config = indoc! {"
+        keymap:
+          - remap:
+              f1: { set_mode: numpad }
+              f2: { set_mode: { mode: numpad, sticky: false } }
+          - mode: numpad
+            remap:
+              j: KEY_1
+              k: KEY_2
+              esc: { clear_mode: true }
+    "};
+    let press = |key| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, KeyValue::Press));
+    let tap = |key| {
+        vec![
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ]
+    };
+
+    // A sticky mode stays for several keys until it's exited
+    assert_actions(
+        config,
+        vec![
+            press(Key::KEY_F1),
+            press(Key::KEY_J),
+            press(Key::KEY_K),
+            press(Key::KEY_J),
+            press(Key::KEY_ESC),
+            press(Key::KEY_J),
+        ],
+        [tap(Key::KEY_1), tap(Key::KEY_2), tap(Key::KEY_1)]
+            .into_iter()
+            .flatten()
+            .chain([Action::KeyEvent(KeyEvent::new(Key::KEY_J, KeyValue::Press))])
+            .collect(),
+    );
+
+    // A one-shot mode ends after the next key press
+    assert_actions(
+        config,
+        vec![press(Key::KEY_F2), press(Key::KEY_J), press(Key::KEY_K)],
+        tap(Key::KEY_1)
+            .into_iter()
+            .chain([Action::KeyEvent(KeyEvent::new(Key::KEY_K, KeyValue::Press))])
+            .collect(),
+    );
+}
+
+#[test]
+fn test_compose() {
+    let tap = |key| {
+        vec![
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ]
+    };
+    assert_actions(
+        indoc! {"
+        compose_key: KEY_RIGHTALT
+        keymap:
+          - remap:
+              f1: { compose: [grave, a] }
+        "},
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_F1, KeyValue::Press),
+        )],
+        [tap(Key::KEY_RIGHTALT), tap(Key::KEY_GRAVE), tap(Key::KEY_A)]
+            .into_iter()
+            .flatten()
+            .collect(),
+    );
+}
+
+#[test]
+fn test_shutdown() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              f1: { press: alt }
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Event::Shutdown,
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_repeat() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              f12: [{ repeat: { key: pagedown, count: 2 } }, a]
+        "},
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_F12, KeyValue::Press),
+        )],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_PAGEDOWN, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_PAGEDOWN, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_PAGEDOWN, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_PAGEDOWN, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+        ],
+    )
+}
+
+#[test]
+fn test_scroll_acceleration() {
+    let press = |key: Key| {
+        vec![
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+        ]
+    };
+    assert_actions(
+        indoc! {"
+        scroll_acceleration:
+          window_millis: 60000
+          max_multiplier: 2
+        keymap:
+          - remap:
+              XUPSCROLL: up
+              XDOWNSCROLL: down
+        "},
+        vec![
+            Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_WHEEL, _POSITIVE)),
+            Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_WHEEL, _POSITIVE)),
+            Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_WHEEL, _POSITIVE)),
+            // A direction reversal starts over from min_multiplier
+            Event::RelativeEvent(get_input_device_info(), RelativeEvent::new_with(_REL_WHEEL, _NEGATIVE)),
+        ],
+        [
+            press(Key::KEY_UP),
+            press(Key::KEY_UP),
+            press(Key::KEY_UP),
+            press(Key::KEY_UP),
+            press(Key::KEY_UP),
+            press(Key::KEY_DOWN),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+    )
+}
+
+#[test]
+fn test_keypress_delay() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        keymap:
+          - remap:
+              a: b
+        "},
+        Duration::from_millis(10),
+    );
+    assert_timed_actions(
+        handler.on_events(vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )]),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_millis(10)),
+            Action::Delay(Duration::from_millis(10)),
+        ],
+    );
+}
+
+#[test]
+fn test_modifier_and_key_delay() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        modifier_delay_ms: 30
+        key_delay_ms: 5
+        keymap:
+          - remap:
+              a: C-b
+              c: d
+        "},
+        Duration::from_millis(10),
+    );
+    assert_timed_actions(
+        handler.on_events(vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )]),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::Delay(Duration::from_millis(30)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_millis(5)),
+            Action::Delay(Duration::from_millis(30)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+    // No modifiers are toggled for a key without them
+    assert_timed_actions(
+        handler.on_events(vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_C, KeyValue::Press),
+        )]),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_D, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_D, KeyValue::Release)),
+            Action::Delay(Duration::from_millis(5)),
+            Action::Delay(Duration::from_millis(30)),
+        ],
+    );
+}
+
+#[test]
+fn test_keypress_delay_without_modifier_delay() {
+    // keypress_delay_ms alone doesn't add a delay between the modifiers and the key
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        keymap:
+          - remap:
+              a: C-b
+        "},
+        Duration::from_millis(10),
+    );
+    assert_timed_actions(
+        handler.on_events(vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )]),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::from_millis(10)),
+            Action::Delay(Duration::from_millis(10)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_keymap_cooldown() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        keymap:
+          - cooldown_ms: 500ms
+            remap:
+              C-w: f1
+        "},
+        Duration::ZERO,
+    );
+    let key = |key, value| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value));
+    let f1 = || {
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::Delay(Duration::ZERO),
+        ]
+    };
+    handler.on_events(vec![key(Key::KEY_LEFTCTRL, KeyValue::Press)]);
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_W, KeyValue::Press)]), f1());
+
+    // Autorepeat and presses within the cooldown are swallowed
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_W, KeyValue::Repeat)]), vec![]);
+    handler.on_events(vec![key(Key::KEY_W, KeyValue::Release)]);
+    handler.advance(Duration::from_millis(499));
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_W, KeyValue::Press)]), vec![]);
+
+    handler.advance(Duration::from_millis(1));
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_W, KeyValue::Press)]), f1());
+}
+
+#[test]
+fn test_keymap_cooldown_passthrough() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        keymap:
+          - cooldown_ms: 500
+            on_cooldown: passthrough
+            remap:
+              a: b
+        "},
+        Duration::ZERO,
+    );
+    let key = |key, value| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value));
+    handler.on_events(vec![key(Key::KEY_A, KeyValue::Press), key(Key::KEY_A, KeyValue::Release)]);
+    assert_timed_actions(
+        handler.on_events(vec![key(Key::KEY_A, KeyValue::Press)]),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press))],
+    );
+
+    // A reload forgets the cooldowns, since the entries are numbered differently
+    handler.on_events(vec![key(Key::KEY_A, KeyValue::Release), Event::ConfigReload]);
+    assert_timed_actions(
+        handler.on_events(vec![key(Key::KEY_A, KeyValue::Press)]),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ],
+    );
+}
+
+#[test]
+fn test_keymap_after() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        keymap:
+          - after: q
+            after_timeout_ms: 500ms
+            remap:
+              a: b
+        "},
+        Duration::ZERO,
+    );
+    let key = |key, value| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value));
+    let tap = |key| vec![Action::KeyEvent(KeyEvent::new(key, KeyValue::Press))];
+    let b = || {
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ]
+    };
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_A, KeyValue::Press)]), tap(Key::KEY_A));
+    handler.on_events(vec![key(Key::KEY_Q, KeyValue::Press), key(Key::KEY_Q, KeyValue::Release)]);
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_A, KeyValue::Press)]), b());
+
+    // Modifiers in between are skipped
+    handler.on_events(vec![
+        key(Key::KEY_Q, KeyValue::Press),
+        key(Key::KEY_LEFTSHIFT, KeyValue::Press),
+    ]);
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_A, KeyValue::Press)]), b());
+
+    handler.on_events(vec![key(Key::KEY_Q, KeyValue::Press)]);
+    handler.advance(Duration::from_millis(501));
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_A, KeyValue::Press)]), tap(Key::KEY_A));
+}
+
+#[test]
+fn test_remap_timeout() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        keymap:
+          - remap:
+              a:
+                remap:
+                  b: c
+                timeout_millis: 100
+        "},
+        Duration::ZERO,
+    );
+    assert_timed_actions(
+        handler.on_events(vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )]),
+        vec![],
+    );
+    assert_timed_actions(handler.advance(Duration::from_millis(99)), vec![]);
+    // The timeout emits the key that started the remap
+    assert_timed_actions(
+        handler.advance(Duration::from_millis(1)),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+        ],
+    );
+    assert_timed_actions(
+        handler.on_events(vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_B, KeyValue::Press),
+        )]),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press))],
+    );
+}
+
+#[test]
+fn test_remap_on_timeout() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        keymap:
+          - remap:
+              a:
+                remap:
+                  b: c
+                timeout_millis: 100ms
+                on_timeout: { run: beep }
+        "},
+        Duration::ZERO,
+    );
+    let key = |key, value| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value));
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_A, KeyValue::Press)]), vec![]);
+    assert_timed_actions(
+        handler.advance(Duration::from_millis(100)),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::Run("beep".into()),
+        ],
+    );
+
+    // Not dispatched when another key aborts the remap
+    handler.on_events(vec![key(Key::KEY_A, KeyValue::Release), key(Key::KEY_A, KeyValue::Press)]);
+    assert_timed_actions(
+        handler.on_events(vec![key(Key::KEY_D, KeyValue::Press)]),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_D, KeyValue::Press)),
+        ],
+    );
+    assert_timed_actions(handler.advance(Duration::from_millis(100)), vec![]);
+}
+
+#[test]
+fn test_leader_sequence() {
+    let config = indoc! {"
+        keymap:
+          - remap:
+              space:
+                remap:
+                  g:
+                    remap:
+                      d: f12
+                timeout_millis: 300
+                replay: true
+        "};
+    let press = |key| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, KeyValue::Press));
+    let tap = |key| {
+        vec![
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)),
+        ]
+    };
+
+    // A completed sequence only runs the action
+    let mut handler = TimedHandler::new(config, Duration::ZERO);
+    assert_timed_actions(handler.on_events(vec![press(Key::KEY_SPACE)]), vec![]);
+    assert_timed_actions(handler.advance(Duration::from_millis(200)), vec![]);
+    assert_timed_actions(handler.on_events(vec![press(Key::KEY_G)]), vec![]);
+    // Each step restarts the timeout of the first one
+    assert_timed_actions(handler.advance(Duration::from_millis(200)), vec![]);
+    let mut f12 = tap(Key::KEY_F12);
+    f12.extend([Action::Delay(Duration::ZERO), Action::Delay(Duration::ZERO)]);
+    assert_timed_actions(handler.on_events(vec![press(Key::KEY_D)]), f12);
+    assert_timed_actions(handler.advance(Duration::from_millis(300)), vec![]);
+
+    // A timed-out sequence replays the keys typed so far
+    let mut handler = TimedHandler::new(config, Duration::ZERO);
+    handler.on_events(vec![press(Key::KEY_SPACE), press(Key::KEY_G)]);
+    let mut replay = tap(Key::KEY_SPACE);
+    replay.extend(tap(Key::KEY_G));
+    assert_timed_actions(handler.advance(Duration::from_millis(300)), replay);
+
+    // So does a key that doesn't continue the sequence, before the key itself
+    let mut handler = TimedHandler::new(config, Duration::ZERO);
+    handler.on_events(vec![press(Key::KEY_SPACE), press(Key::KEY_G)]);
+    let mut replay = tap(Key::KEY_SPACE);
+    replay.extend(tap(Key::KEY_G));
+    replay.push(Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Press)));
+    assert_timed_actions(handler.on_events(vec![press(Key::KEY_X)]), replay);
+    assert_timed_actions(handler.advance(Duration::from_millis(300)), vec![]);
+}
+
+#[test]
+fn test_alone_timeout() {
+    let config = indoc! {"
+        modmap:
+          - remap:
+              capslock:
+                held: leftctrl
+                alone: esc
+                alone_timeout_millis: 500
+    "};
+    let capslock = |value| {
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_CAPSLOCK, value),
+        )]
+    };
+
+    let mut handler = TimedHandler::new(config, Duration::ZERO);
+    assert_timed_actions(handler.on_events(capslock(KeyValue::Press)), vec![]);
+    handler.advance(Duration::from_millis(499));
+    assert_timed_actions(
+        handler.on_events(capslock(KeyValue::Release)),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_ESC, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_ESC, KeyValue::Release)),
+        ],
+    );
+
+    let mut handler = TimedHandler::new(config, Duration::ZERO);
+    assert_timed_actions(handler.on_events(capslock(KeyValue::Press)), vec![]);
+    handler.advance(Duration::from_millis(500));
+    assert_timed_actions(
+        handler.on_events(capslock(KeyValue::Release)),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_tap_hold_policy() {
+    let config = |policy| {
+        format!(
+            indoc! {"
+            modmap:
+              - remap:
+                  capslock:
+                    held: leftctrl
+                    alone: esc
+                    tap_hold_policy: {}
+        "},
+            policy
+        )
+    };
+    let key = |key, value| vec![Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value))];
+    let action = |key, value| Action::KeyEvent(KeyEvent::new(key, value));
+
+    // hold_preferred: held as soon as another key is pressed
+    let mut handler = TimedHandler::new(&config("hold_preferred"), Duration::ZERO);
+    assert_timed_actions(handler.on_events(key(Key::KEY_CAPSLOCK, KeyValue::Press)), vec![]);
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_A, KeyValue::Press)),
+        vec![
+            action(Key::KEY_LEFTCTRL, KeyValue::Press),
+            action(Key::KEY_A, KeyValue::Press),
+        ],
+    );
+
+    // permissive: held if another key is tapped within it
+    let mut handler = TimedHandler::new(&config("permissive"), Duration::ZERO);
+    assert_timed_actions(handler.on_events(key(Key::KEY_CAPSLOCK, KeyValue::Press)), vec![]);
+    assert_timed_actions(handler.on_events(key(Key::KEY_A, KeyValue::Press)), vec![]);
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_A, KeyValue::Release)),
+        vec![
+            action(Key::KEY_LEFTCTRL, KeyValue::Press),
+            action(Key::KEY_A, KeyValue::Press),
+            action(Key::KEY_A, KeyValue::Release),
+        ],
+    );
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_CAPSLOCK, KeyValue::Release)),
+        vec![action(Key::KEY_LEFTCTRL, KeyValue::Release)],
+    );
+
+    // permissive: alone if it's released before the other key
+    let mut handler = TimedHandler::new(&config("permissive"), Duration::ZERO);
+    assert_timed_actions(handler.on_events(key(Key::KEY_CAPSLOCK, KeyValue::Press)), vec![]);
+    assert_timed_actions(handler.on_events(key(Key::KEY_A, KeyValue::Press)), vec![]);
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_CAPSLOCK, KeyValue::Release)),
+        vec![
+            action(Key::KEY_ESC, KeyValue::Press),
+            action(Key::KEY_ESC, KeyValue::Release),
+            action(Key::KEY_A, KeyValue::Press),
+        ],
+    );
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_A, KeyValue::Release)),
+        vec![action(Key::KEY_A, KeyValue::Release)],
+    );
+
+    // permissive: held once the alone timeout passes while another key is held
+    let mut handler = TimedHandler::new(&config("permissive"), Duration::ZERO);
+    assert_timed_actions(handler.on_events(key(Key::KEY_CAPSLOCK, KeyValue::Press)), vec![]);
+    assert_timed_actions(handler.on_events(key(Key::KEY_A, KeyValue::Press)), vec![]);
+    handler.advance(Duration::from_millis(2000));
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_A, KeyValue::Repeat)),
+        vec![
+            action(Key::KEY_LEFTCTRL, KeyValue::Press),
+            action(Key::KEY_A, KeyValue::Press),
+            action(Key::KEY_A, KeyValue::Repeat),
+        ],
+    );
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_CAPSLOCK, KeyValue::Release)),
+        vec![action(Key::KEY_LEFTCTRL, KeyValue::Release)],
+    );
+
+    // timeout_only: other keys don't change it
+    let mut handler = TimedHandler::new(&config("timeout_only"), Duration::ZERO);
+    assert_timed_actions(handler.on_events(key(Key::KEY_CAPSLOCK, KeyValue::Press)), vec![]);
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_A, KeyValue::Press)),
+        vec![action(Key::KEY_A, KeyValue::Press)],
+    );
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_A, KeyValue::Release)),
+        vec![action(Key::KEY_A, KeyValue::Release)],
+    );
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_CAPSLOCK, KeyValue::Release)),
+        vec![
+            action(Key::KEY_ESC, KeyValue::Press),
+            action(Key::KEY_ESC, KeyValue::Release),
+        ],
+    );
+}
+
+#[test]
+fn test_double_tap() {
+    let config = indoc! {"
+        modmap:
+          - remap:
+              Shift_L:
+                double_tap: CapsLock
+                double_tap_timeout_millis: 300
+    "};
+    let key = |key, value| vec![Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value))];
+    let shift = |value| key(Key::KEY_LEFTSHIFT, value);
+    let shift_actions = |value| vec![Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTSHIFT, value))];
+    let capslock_actions = |value| vec![Action::KeyEvent(KeyEvent::new(Key::KEY_CAPSLOCK, value))];
+
+    // The first tap isn't delayed, and the second one within the timeout morphs
+    let mut handler = TimedHandler::new(config, Duration::ZERO);
+    assert_timed_actions(handler.on_events(shift(KeyValue::Press)), shift_actions(KeyValue::Press));
+    assert_timed_actions(handler.on_events(shift(KeyValue::Release)), shift_actions(KeyValue::Release));
+    handler.advance(Duration::from_millis(300));
+    assert_timed_actions(handler.on_events(shift(KeyValue::Press)), capslock_actions(KeyValue::Press));
+    assert_timed_actions(handler.on_events(shift(KeyValue::Release)), capslock_actions(KeyValue::Release));
+
+    // A third tap starts over
+    assert_timed_actions(handler.on_events(shift(KeyValue::Press)), shift_actions(KeyValue::Press));
+    assert_timed_actions(handler.on_events(shift(KeyValue::Release)), shift_actions(KeyValue::Release));
+
+    // Too late
+    handler.advance(Duration::from_millis(301));
+    assert_timed_actions(handler.on_events(shift(KeyValue::Press)), shift_actions(KeyValue::Press));
+    assert_timed_actions(handler.on_events(shift(KeyValue::Release)), shift_actions(KeyValue::Release));
+
+    // Shift used in a combo isn't a tap
+    let mut handler = TimedHandler::new(config, Duration::ZERO);
+    handler.on_events(shift(KeyValue::Press));
+    handler.on_events(key(Key::KEY_A, KeyValue::Press));
+    handler.on_events(key(Key::KEY_A, KeyValue::Release));
+    handler.on_events(shift(KeyValue::Release));
+    assert_timed_actions(handler.on_events(shift(KeyValue::Press)), shift_actions(KeyValue::Press));
+    assert_timed_actions(handler.on_events(shift(KeyValue::Release)), shift_actions(KeyValue::Release));
+
+    // Another key between the taps cancels it as well
+    let mut handler = TimedHandler::new(config, Duration::ZERO);
+    handler.on_events(shift(KeyValue::Press));
+    handler.on_events(shift(KeyValue::Release));
+    handler.on_events(key(Key::KEY_A, KeyValue::Press));
+    handler.on_events(key(Key::KEY_A, KeyValue::Release));
+    assert_timed_actions(handler.on_events(shift(KeyValue::Press)), shift_actions(KeyValue::Press));
+}
+
+#[test]
+fn test_tap_count() {
+    let config = indoc! {"
+        modmap:
+          - remap:
+              F1:
+                taps:
+                  1: a
+                  2: b
+                  3: [c, d]
+                tap_timeout_millis: 300
+    "};
+    let key = |key, value| vec![Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value))];
+    let f1 = |value| key(Key::KEY_F1, value);
+    let tap = |key| {
+        vec![
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ]
+    };
+
+    // A single tap is dispatched once the timeout passes
+    let mut handler = TimedHandler::new(config, Duration::ZERO);
+    assert_timed_actions(handler.on_events(f1(KeyValue::Press)), vec![]);
+    assert_timed_actions(handler.on_events(f1(KeyValue::Release)), vec![]);
+    assert_timed_actions(handler.advance(Duration::from_millis(299)), vec![]);
+    assert_timed_actions(handler.advance(Duration::from_millis(1)), tap(Key::KEY_A));
+
+    // Each tap restarts the timeout
+    handler.on_events(f1(KeyValue::Press));
+    handler.on_events(f1(KeyValue::Release));
+    assert_timed_actions(handler.advance(Duration::from_millis(200)), vec![]);
+    handler.on_events(f1(KeyValue::Press));
+    handler.on_events(f1(KeyValue::Release));
+    assert_timed_actions(handler.advance(Duration::from_millis(200)), vec![]);
+    assert_timed_actions(handler.advance(Duration::from_millis(100)), tap(Key::KEY_B));
+
+    // The largest count is dispatched right away
+    handler.on_events(f1(KeyValue::Press));
+    handler.on_events(f1(KeyValue::Release));
+    handler.on_events(f1(KeyValue::Press));
+    handler.on_events(f1(KeyValue::Release));
+    assert_timed_actions(
+        handler.on_events(f1(KeyValue::Press)),
+        tap(Key::KEY_C).into_iter().chain(tap(Key::KEY_D)).collect(),
+    );
+    assert_timed_actions(handler.on_events(f1(KeyValue::Release)), vec![]);
+    assert_timed_actions(handler.advance(Duration::from_millis(300)), vec![]);
+
+    // Another key settles the count before it's pressed
+    handler.on_events(f1(KeyValue::Press));
+    handler.on_events(f1(KeyValue::Release));
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_X, KeyValue::Press)),
+        tap(Key::KEY_A)
+            .into_iter()
+            .chain(vec![Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Press))])
+            .collect(),
+    );
+    assert_timed_actions(handler.advance(Duration::from_millis(300)), vec![]);
+}
+
+#[test]
+fn test_mouse_move() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        mouse_move_interval_ms: 20
+        keymap:
+          - remap:
+              kp6: { mouse_move: { x: 5 } }
+              kp2: { mouse_move: { y: 5 } }
+        "},
+        Duration::ZERO,
+    );
+    let key = |key, value| vec![Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value))];
+    let movement = |events: Vec<(u16, i32)>| {
+        vec![Action::MouseMovementEventCollection(
+            events
+                .into_iter()
+                .map(|(code, value)| RelativeEvent::new_with(code, value))
+                .collect(),
+        )]
+    };
+
+    assert_timed_actions(handler.on_events(key(Key::KEY_KP6, KeyValue::Press)), movement(vec![(_REL_X, 5)]));
+    assert_timed_actions(handler.advance(Duration::from_millis(19)), vec![]);
+    assert_timed_actions(handler.advance(Duration::from_millis(1)), movement(vec![(_REL_X, 5)]));
+    // Autorepeat doesn't move it on top of the interval
+    assert_timed_actions(handler.on_events(key(Key::KEY_KP6, KeyValue::Repeat)), vec![]);
+    assert_timed_actions(handler.advance(Duration::from_millis(10)), vec![]);
+    assert_timed_actions(handler.on_events(key(Key::KEY_KP6, KeyValue::Repeat)), vec![]);
+    assert_timed_actions(handler.advance(Duration::from_millis(10)), movement(vec![(_REL_X, 5)]));
+    // Movements of held keys are added up
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_KP2, KeyValue::Press)),
+        movement(vec![(_REL_X, 5), (_REL_Y, 5)]),
+    );
+    assert_timed_actions(handler.advance(Duration::from_millis(20)), movement(vec![(_REL_X, 5), (_REL_Y, 5)]));
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_KP6, KeyValue::Release)),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_KP6, KeyValue::Release))],
+    );
+    assert_timed_actions(handler.advance(Duration::from_millis(20)), movement(vec![(_REL_Y, 5)]));
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_KP2, KeyValue::Release)),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_KP2, KeyValue::Release))],
+    );
+    assert_timed_actions(handler.advance(Duration::from_millis(20)), vec![]);
+}
+
+#[test]
+fn test_raw_code() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              f12: { raw_code: 0x2f5 }
+        "},
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_F12, KeyValue::Press),
+        )],
+        vec![Action::RawCode(0x2f5), Action::Delay(Duration::from_nanos(0))],
+    )
+}
+
+#[test]
+fn test_action_json() {
+    let json = |action: Action| serde_json::to_string(&action).unwrap();
+    assert_eq!(
+        r#"{"type":"key_event","key":"KEY_A","value":"press"}"#,
+        json(Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)))
+    );
+    assert_eq!(
+        r#"{"type":"relative_event","code":8,"value":-1}"#,
+        json(Action::RelativeEvent(RelativeEvent::new_with(_REL_WHEEL, _NEGATIVE)))
+    );
+    assert_eq!(
+        r#"{"type":"mouse_movement_event_collection","events":[{"code":0,"value":5}]}"#,
+        json(Action::MouseMovementEventCollection(vec![RelativeEvent::new_with(_REL_X, 5)]))
+    );
+    assert_eq!(r#"{"type":"delay","duration":1.5}"#, json(Action::Delay(Duration::from_micros(1500))));
+    assert_eq!(
+        r#"{"type":"command","command":["echo","hi"]}"#,
+        json(Action::Command(vec!["echo".into(), "hi".into()]))
+    );
+}
+
+#[test]
+fn test_led() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - led: numlock
+            remap:
+              kp1: end
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_KP1, KeyValue::Press)),
+            // The LED changes while the key is held
+            Event::LedEvent(get_input_device_info(), LedEvent::new_with(LedType::LED_NUML.0, 1)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_KP1, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_KP1, KeyValue::Press)),
+            Event::LedEvent(get_input_device_info(), LedEvent::new_with(LedType::LED_NUML.0, 0)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_KP1, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_KP1, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_KP1, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_END, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_END, KeyValue::Release)),
             Action::Delay(Duration::from_nanos(0)),
             Action::Delay(Duration::from_nanos(0)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_KP1, KeyValue::Release)),
         ],
     )
 }
 
 #[test]
-fn test_mixing_no_keypress_and_remap_in_keymap_action() {
-    // The first match stops the search for matches. So the last remap isn't used.
+fn test_led_of_other_device() {
     assert_actions(
         indoc! {"
         keymap:
-          - remap:
-              f12: []
-          - remap:
-              f12:
-                - remap:
-                    a: b
+          - led: numlock
+            remap:
+              kp1: end
         "},
         vec![
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Release)),
-        ],
-        vec![
-            Action::KeyEvent(KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
-            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Event::LedEvent(
+                InputDeviceInfo {
+                    name: "Other Device",
+                    path: Path::new("/dev/input/event1"),
+                },
+                LedEvent::new_with(LedType::LED_NUML.0, 1),
+            ),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_KP1, KeyValue::Press)),
         ],
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_KP1, KeyValue::Press))],
     )
 }
 
 #[test]
-fn test_no_keymap_action() {
+fn test_ungrab() {
+    // Nothing is emitted while ungrabbed, because the applications receive the original events.
     assert_actions(
         indoc! {"
         keymap:
           - remap:
-              f12: []
+              f11: { ungrab: true }
+              f12: { grab: true }
         "},
         vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F11, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F11, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Release)),
             Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Press)),
             Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
         ],
         vec![
-            //This is just release, so the key is not emitted.
+            Action::SetGrab(false),
+            Action::SetGrab(true),
             Action::KeyEvent(KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
         ],
-    );
+    )
+}
 
-    //Same test with the null keyword
+#[test]
+fn test_set_led() {
+    // The LED shows the mode
     assert_actions(
         indoc! {"
         keymap:
           - remap:
-              f12: null
+              f1: [{ set_mode: vim }, { set_led: { led: scrolllock, on: true } }]
+          - mode: vim
+            remap:
+              esc: [{ set_mode: default }, { set_led: { led: LED_SCROLLL, on: false } }]
         "},
         vec![
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Press)),
-            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F12, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_ESC, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_ESC, KeyValue::Release)),
+        ],
+        vec![
+            Action::SetLed {
+                led: LedType::LED_SCROLLL,
+                on: true,
+            },
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+            Action::SetLed {
+                led: LedType::LED_SCROLLL,
+                on: false,
+            },
+            Action::KeyEvent(KeyEvent::new(Key::KEY_ESC, KeyValue::Release)),
         ],
-        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_F12, KeyValue::Release))],
     )
 }
 
@@ -698,6 +3328,83 @@ fn test_any_key_doesnt_match_modifier_2() {
     );
 }
 
+#[test]
+fn test_any_key_including_modifiers() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              a: b
+              ANY_INCLUDING_MODIFIERS: null
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_any_key_self() {
+    assert_actions(
+        indoc! {"
+        keymap:
+          - remap:
+              a: b
+              ANY: C-<self>
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_J, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_J, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_J, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_J, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_J, KeyValue::Release)),
+        ],
+    );
+
+    // Nested in other actions too
+    assert_actions(
+        indoc! {"
+        modmap:
+          - remap:
+              f1:
+                press: { press: <self> }
+                release: { release: <self> }
+                skip_key_event: true
+        keymap:
+          - remap:
+              j: { repeat: { key: C-<self>, count: 1 } }
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_J, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_J, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_J, KeyValue::Release)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::Delay(Duration::from_nanos(0)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_F1, KeyValue::Release)),
+        ],
+    );
+}
+
 #[test]
 fn test_terminal_modifier() {
     assert_actions(
@@ -791,6 +3498,29 @@ fn test_terminal_modifier_sends_same_modifier_combo() {
     )
 }
 
+#[test]
+fn test_held_modifier_is_reused() {
+    // Either Ctrl being held is enough for C-c, so no Ctrl is pressed or released around c
+    for interleave_modifiers in [true, false] {
+        for ctrl in [Key::KEY_LEFTCTRL, Key::KEY_RIGHTCTRL] {
+            assert_actions(
+                &format!("interleave_modifiers: {}\nkeymap:\n  - remap:\n      a: C-c\n", interleave_modifiers),
+                vec![
+                    Event::KeyEvent(get_input_device_info(), KeyEvent::new(ctrl, KeyValue::Press)),
+                    Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+                ],
+                vec![
+                    Action::KeyEvent(KeyEvent::new(ctrl, KeyValue::Press)),
+                    Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+                    Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+                    Action::Delay(Duration::from_nanos(0)),
+                    Action::Delay(Duration::from_nanos(0)),
+                ],
+            );
+        }
+    }
+}
+
 #[test]
 fn test_terminal_modifier_with_exact_match() {
     assert_actions(
@@ -826,15 +3556,32 @@ fn assert_actions_with_current_application(
     current_application: Option<String>,
     events: Vec<Event>,
     actions: Vec<Action>,
+) {
+    assert_actions_with_current_window(config_yaml, current_application, None, events, actions);
+}
+
+fn assert_actions_with_current_window(
+    config_yaml: &str,
+    current_application: Option<String>,
+    current_window: Option<String>,
+    events: Vec<Event>,
+    actions: Vec<Action>,
 ) {
     let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::empty()).unwrap();
     let mut config: Config = serde_yaml::from_str(config_yaml).unwrap();
-    config.keymap_table = build_keymap_table(&config.keymap);
+    config.keymap_table = build_keymap_table(&config.keymap, config.default_exact_match, config.merge_strategy);
     let mut event_handler = EventHandler::new(
         timer,
         "default",
         Duration::from_micros(0),
-        WMClient::new("static", Box::new(StaticClient { current_application })),
+        config.mouse_move_interval,
+        WMClient::new(
+            "static",
+            Box::new(StaticClient {
+                current_application,
+                current_window,
+            }),
+        ),
     );
     let mut actual: Vec<Action> = vec![];
 
@@ -842,3 +3589,400 @@ fn assert_actions_with_current_application(
 
     assert_eq!(format!("{:?}", actions), format!("{:?}", actual));
 }
+
+// A Timer whose clock only moves forward with advance()
+#[derive(Clone)]
+struct FakeTimer {
+    // Shared by its clones
+    now: Rc<Cell<Instant>>,
+    expires_at: Rc<Cell<Option<Instant>>>,
+}
+
+impl FakeTimer {
+    fn new() -> FakeTimer {
+        FakeTimer {
+            now: Rc::new(Cell::new(Instant::now())),
+            expires_at: Rc::new(Cell::new(None)),
+        }
+    }
+
+    // Move the shared clock forward
+    fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+
+    // Returns true if the timer has expired, disarming it
+    fn expired(&self) -> bool {
+        match self.expires_at.get() {
+            Some(expires_at) if expires_at <= self.now.get() => {
+                self.expires_at.set(None);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Timer for FakeTimer {
+    fn set(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        self.expires_at.set(Some(self.now.get() + duration));
+        Ok(())
+    }
+
+    fn unset(&mut self) -> Result<(), Box<dyn Error>> {
+        self.expires_at.set(None);
+        Ok(())
+    }
+
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
+
+// Drives an EventHandler with a FakeTimer to test timing-sensitive features
+struct TimedHandler {
+    handler: EventHandler,
+    config: Config,
+    timer: FakeTimer,
+}
+
+impl TimedHandler {
+    fn new(config_yaml: &str, keypress_delay: Duration) -> TimedHandler {
+        let client = StaticClient {
+            current_application: None,
+            current_window: None,
+        };
+        TimedHandler::with_client(config_yaml, keypress_delay, Box::new(client))
+    }
+
+    fn with_client(config_yaml: &str, keypress_delay: Duration, client: Box<dyn Client>) -> TimedHandler {
+        let timer = FakeTimer::new();
+        let mut config: Config = serde_yaml::from_str(config_yaml).unwrap();
+        config.keymap_table = build_keymap_table(&config.keymap, config.default_exact_match, config.merge_strategy);
+        let handler = EventHandler::new(
+            timer.clone(),
+            "default",
+            keypress_delay,
+            config.mouse_move_interval,
+            WMClient::new("static", client),
+        );
+        TimedHandler { handler, config, timer }
+    }
+
+    fn on_events(&mut self, events: Vec<Event>) -> Vec<Action> {
+        self.handler.on_events(&events, &self.config).unwrap()
+    }
+
+    // Move the clock forward, and handle the timer if it fired like the main loop
+    fn advance(&mut self, duration: Duration) -> Vec<Action> {
+        self.timer.advance(duration);
+        if self.timer.expired() {
+            self.on_events(vec![Event::OverrideTimeout])
+        } else {
+            vec![]
+        }
+    }
+}
+
+fn assert_timed_actions(actual: Vec<Action>, expected: Vec<Action>) {
+    assert_eq!(format!("{:?}", expected), format!("{:?}", actual));
+}
+
+#[test]
+fn test_nested_remap_literal() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        keymap:
+          - remap:
+              C-x:
+                remap:
+                  h: C-a
+                timeout_millis: 1000
+                literal_timeout_millis: 200
+        "},
+        Duration::ZERO,
+    );
+    let key = |key, value| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value));
+
+    // A quick tap of the prefix emits it as is, and leaves the nested remap
+    handler.on_events(vec![
+        key(Key::KEY_LEFTCTRL, KeyValue::Press),
+        key(Key::KEY_X, KeyValue::Press),
+    ]);
+    handler.advance(Duration::from_millis(100));
+    assert_timed_actions(
+        handler.on_events(vec![
+            key(Key::KEY_X, KeyValue::Release),
+            key(Key::KEY_LEFTCTRL, KeyValue::Release),
+        ]),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+    assert_timed_actions(
+        handler.on_events(vec![key(Key::KEY_H, KeyValue::Press)]),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_H, KeyValue::Press))],
+    );
+    handler.on_events(vec![key(Key::KEY_H, KeyValue::Release)]);
+
+    // Holding it longer keeps waiting for the nested remap
+    handler.on_events(vec![
+        key(Key::KEY_LEFTCTRL, KeyValue::Press),
+        key(Key::KEY_X, KeyValue::Press),
+    ]);
+    handler.advance(Duration::from_millis(300));
+    handler.on_events(vec![
+        key(Key::KEY_X, KeyValue::Release),
+        key(Key::KEY_LEFTCTRL, KeyValue::Release),
+    ]);
+    assert_timed_actions(
+        handler.on_events(vec![key(Key::KEY_H, KeyValue::Press)]),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_modmap_modifier_to_key() {
+    // A modifier modmapped to another key isn't a modifier in keymap
+    assert_actions(
+        indoc! {"
+        modmap:
+          - remap:
+              Alt_L: a
+              Control_R: [b, c]
+        keymap:
+          - remap:
+              M-x: y
+              C-x: z
+        "},
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_LEFTALT, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_RIGHTCTRL, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+        ],
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Press)),
+        ],
+    );
+}
+
+#[test]
+fn test_modmap_modifier_to_keys_changed_while_held() {
+    let title = Rc::new(RefCell::new(None));
+    let mut handler = TimedHandler::with_client(
+        indoc! {"
+        modmap:
+          - window:
+              only: Terminal
+            remap:
+              Control_R: [b, c]
+        keymap:
+          - remap:
+              C-x: z
+        "},
+        Duration::ZERO,
+        Box::new(TitleClient { title: title.clone() }),
+    );
+    let key = |key, value| vec![Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value))];
+
+    // Control_R pressed outside of the window is released as Control_R, and doesn't stay a modifier
+    handler.on_events(key(Key::KEY_RIGHTCTRL, KeyValue::Press));
+    *title.borrow_mut() = Some("Terminal".to_string());
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_RIGHTCTRL, KeyValue::Release)),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_RIGHTCTRL, KeyValue::Release))],
+    );
+    *title.borrow_mut() = None;
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_X, KeyValue::Press)),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_X, KeyValue::Press))],
+    );
+    handler.on_events(key(Key::KEY_X, KeyValue::Release));
+
+    // Keys pressed in the window are released as they were pressed
+    *title.borrow_mut() = Some("Terminal".to_string());
+    handler.on_events(key(Key::KEY_RIGHTCTRL, KeyValue::Press));
+    *title.borrow_mut() = None;
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_RIGHTCTRL, KeyValue::Release)),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+        ],
+    );
+}
+
+#[test]
+fn test_repeat_rate() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        keymap:
+          - repeat_rate_ms: 50
+            remap:
+              j: down
+        "},
+        Duration::ZERO,
+    );
+    let key = |key, value| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value));
+    let down = || {
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_DOWN, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_DOWN, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ]
+    };
+
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_J, KeyValue::Press)]), down());
+    // The kernel's autorepeat is ignored
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_J, KeyValue::Repeat)]), vec![]);
+    assert_timed_actions(handler.advance(Duration::from_millis(30)), vec![]);
+    assert_timed_actions(handler.advance(Duration::from_millis(20)), down());
+    assert_timed_actions(handler.advance(Duration::from_millis(50)), down());
+
+    // Releasing the key stops it
+    handler.on_events(vec![key(Key::KEY_J, KeyValue::Release)]);
+    assert_timed_actions(handler.advance(Duration::from_millis(100)), vec![]);
+}
+
+#[test]
+fn test_layer() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        keymap:
+          - remap:
+              F1:
+                layer:
+                  remap:
+                    h: left
+                  timeout_ms: 2000
+              h: end
+        "},
+        Duration::ZERO,
+    );
+    let key = |key, value| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value));
+    let tap = |key| {
+        vec![
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, KeyValue::Press)),
+            Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, KeyValue::Release)),
+        ]
+    };
+    let key_press = |key| {
+        vec![
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ]
+    };
+
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_F1, KeyValue::Press)]), vec![]);
+    handler.on_events(vec![key(Key::KEY_F1, KeyValue::Release)]);
+
+    // The layer stays for every key until the timeout, unlike a nested remap
+    handler.advance(Duration::from_millis(1000));
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_H, KeyValue::Press)]), key_press(Key::KEY_LEFT));
+    handler.on_events(vec![key(Key::KEY_H, KeyValue::Release)]);
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_H, KeyValue::Press)]), key_press(Key::KEY_LEFT));
+    handler.on_events(vec![key(Key::KEY_H, KeyValue::Release)]);
+
+    // Keys it doesn't map are left as they are
+    assert_timed_actions(
+        handler.on_events(tap(Key::KEY_K)),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_K, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_K, KeyValue::Release)),
+        ],
+    );
+
+    // And it's removed after the timeout
+    handler.advance(Duration::from_millis(1000));
+    assert_timed_actions(handler.on_events(vec![key(Key::KEY_H, KeyValue::Press)]), key_press(Key::KEY_END));
+}
+
+#[test]
+fn test_layer_timeout_disarms_timer() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        keymap:
+          - remap:
+              F1:
+                layer:
+                  remap:
+                    h: left
+                  timeout_ms: 2000
+        "},
+        Duration::ZERO,
+    );
+    handler.on_events(vec![Event::KeyEvent(
+        get_input_device_info(),
+        KeyEvent::new(Key::KEY_F1, KeyValue::Press),
+    )]);
+    assert!(handler.timer.expires_at.get().is_some());
+
+    // Unlike FakeTimer::expired, a timerfd isn't disarmed by firing, so the handler has to do it
+    handler.timer.advance(Duration::from_millis(2000));
+    handler.on_events(vec![Event::OverrideTimeout]);
+    assert!(handler.timer.expires_at.get().is_none());
+}
+
+#[test]
+fn test_timer_deadlines() {
+    let mut handler = TimedHandler::new(
+        indoc! {"
+        mouse_move_interval_ms: 20
+        keymap:
+          - remap:
+              kp6: { mouse_move: { x: 5 } }
+              F1:
+                layer:
+                  remap:
+                    h: left
+                  timeout_ms: 30
+        "},
+        Duration::ZERO,
+    );
+    let key = |key, value| vec![Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value))];
+    let movement = || {
+        vec![Action::MouseMovementEventCollection(vec![RelativeEvent::new_with(
+            _REL_X, 5,
+        )])]
+    };
+
+    // The one timer is armed for the earliest of the mouse_move ticks and the layer timeout
+    handler.on_events(key(Key::KEY_F1, KeyValue::Press));
+    assert_timed_actions(handler.on_events(key(Key::KEY_KP6, KeyValue::Press)), movement());
+    assert_timed_actions(handler.advance(Duration::from_millis(20)), movement());
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_H, KeyValue::Press)),
+        vec![
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFT, KeyValue::Press)),
+            Action::KeyEvent(KeyEvent::new(Key::KEY_LEFT, KeyValue::Release)),
+            Action::Delay(Duration::ZERO),
+            Action::Delay(Duration::ZERO),
+        ],
+    );
+    // The layer times out in between two ticks
+    assert_timed_actions(handler.advance(Duration::from_millis(10)), vec![]);
+    assert_timed_actions(
+        handler.on_events(key(Key::KEY_H, KeyValue::Press)),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_H, KeyValue::Press))],
+    );
+    assert_timed_actions(handler.advance(Duration::from_millis(10)), movement());
+    handler.on_events(key(Key::KEY_KP6, KeyValue::Release));
+    assert!(handler.timer.expires_at.get().is_none());
+}
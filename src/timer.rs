@@ -0,0 +1,30 @@
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{Expiration, TimerFd, TimerSetTimeFlags};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+// The clock and the one-shot timer used by EventHandler.
+// TimerFd is used in production, and tests may replace it to control time.
+pub trait Timer {
+    // Arm the timer to fire once after the duration, replacing any previous one
+    fn set(&mut self, duration: Duration) -> Result<(), Box<dyn Error>>;
+    fn unset(&mut self) -> Result<(), Box<dyn Error>>;
+    fn now(&self) -> Instant;
+}
+
+impl Timer for TimerFd {
+    fn set(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        let expiration = Expiration::OneShot(TimeSpec::from_duration(duration));
+        TimerFd::set(self, expiration, TimerSetTimeFlags::empty())?;
+        Ok(())
+    }
+
+    fn unset(&mut self) -> Result<(), Box<dyn Error>> {
+        TimerFd::unset(self)?;
+        Ok(())
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}